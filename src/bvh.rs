@@ -0,0 +1,581 @@
+use glam::{BVec4A, Vec3, Vec3A, Vec4};
+
+use crate::{
+    packet::{PacketHits, RayPacket, PACKET_SIZE},
+    structures::{HitRecord, Ray},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // grows into anything it's merged with
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    // for shapes with no edge, like planes. these stay out of the bvh
+    pub fn infinite() -> Aabb {
+        Aabb {
+            min: Vec3::splat(f32::NEG_INFINITY),
+            max: Vec3::splat(f32::INFINITY),
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.min.is_finite() && self.max.is_finite()
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        points
+            .iter()
+            .fold(Aabb::empty(), |aabb, &point| aabb.grow(point))
+    }
+
+    pub fn grow(self, point: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    // empty if they don't overlap
+    pub fn intersection(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let size = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    // slab test, the distance the ray enters the box at if it does within the range
+    pub fn intersect(
+        &self,
+        origin: Vec3,
+        inv_dir: Vec3,
+        ray_tmin: f32,
+        ray_tmax: f32,
+    ) -> Option<f32> {
+        let t0 = (self.min - origin) * inv_dir;
+        let t1 = (self.max - origin) * inv_dir;
+        let t_near = t0.min(t1).max_element().max(ray_tmin);
+        let t_far = t0.max(t1).min_element().min(ray_tmax);
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+pub trait Bounded {
+    fn aabb(&self) -> Aabb;
+}
+
+#[derive(Clone, Copy)]
+struct BvhNode {
+    // simd friendly copy of the bounds, this is the hot loop
+    min: Vec3A,
+    max: Vec3A,
+    // leaves: the first of count primitives in indices
+    // interiors: the second child, the first one directly follows its parent
+    offset: u32,
+    count: u32,
+}
+
+// bounding volume hierarchy built with binned surface area heuristic splits,
+// flattened depth first into one array
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<u32>,
+    // sah cost right after building, refits are measured against it
+    built_cost: f32,
+}
+
+const SAH_BINS: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+// cost of visiting a node relative to testing a primitive
+const TRAVERSAL_COST: f32 = 1.0;
+// refits stretch boxes over things that drifted apart, past this much of the
+// built cost a rebuild pays for itself
+const REFIT_DEGRADATION: f32 = 1.5;
+// traversal keeps at most one node per level waiting, builds stop splitting here
+const MAX_DEPTH: usize = 64;
+
+struct BuildPrimitive {
+    aabb: Aabb,
+    center: Vec3,
+    index: u32,
+}
+
+impl Bvh {
+    pub fn build<T: Bounded>(primitives: &[T]) -> Bvh {
+        let mut build_primitives: Vec<BuildPrimitive> = primitives
+            .iter()
+            .enumerate()
+            .map(|(index, primitive)| {
+                let aabb = primitive.aabb();
+                BuildPrimitive {
+                    aabb,
+                    center: aabb.center(),
+                    index: index as u32,
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(primitives.len() * 2);
+        if !build_primitives.is_empty() {
+            build_node(&mut nodes, &mut build_primitives, 0, 0);
+        }
+        let indices = build_primitives.iter().map(|p| p.index).collect();
+        let mut bvh = Bvh {
+            nodes,
+            indices,
+            built_cost: 0.0,
+        };
+        bvh.built_cost = bvh.cost();
+        bvh
+    }
+
+    // new bounds for primitives that moved, keeping the tree as it is.
+    // primitives has to line up with the ones the tree was built from
+    pub fn refit<T: Bounded>(&mut self, primitives: &[T]) {
+        // children always come after their parent
+        for node_index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[node_index];
+            let aabb = if node.count > 0 {
+                let start = node.offset as usize;
+                self.indices[start..start + node.count as usize]
+                    .iter()
+                    .fold(Aabb::empty(), |aabb, &index| {
+                        aabb.union(primitives[index as usize].aabb())
+                    })
+            } else {
+                let (first, second) = (
+                    &self.nodes[node_index + 1],
+                    &self.nodes[node.offset as usize],
+                );
+                Aabb::new(
+                    first.min.min(second.min).into(),
+                    first.max.max(second.max).into(),
+                )
+            };
+            self.nodes[node_index].min = aabb.min.into();
+            self.nodes[node_index].max = aabb.max.into();
+        }
+    }
+
+    pub fn num_primitives(&self) -> usize {
+        self.indices.len()
+    }
+
+    // true once refits have made traversal noticeably slower than a fresh build
+    pub fn degraded(&self) -> bool {
+        self.cost() > self.built_cost * REFIT_DEGRADATION
+    }
+
+    // expected work for a random ray that hits the root, boxes plus primitives
+    fn cost(&self) -> f32 {
+        let area = |node: &BvhNode| Aabb::new(node.min.into(), node.max.into()).surface_area();
+        let Some(root) = self.nodes.first() else {
+            return 0.0;
+        };
+        let root_area = area(root).max(f32::MIN_POSITIVE);
+        self.nodes
+            .iter()
+            .map(|node| area(node) * node.count.max(1) as f32)
+            .sum::<f32>()
+            / root_area
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |node| {
+            Aabb::new(node.min.into(), node.max.into())
+        })
+    }
+
+    // nearest hit within the range. hit is called with a primitive's index and
+    // the closest distance found so far, and shrinks the range for the rest of
+    // the traversal when it finds something
+    pub fn closest_hit(
+        &self,
+        ray: &Ray,
+        ray_tmin: f32,
+        ray_tmax: f32,
+        mut hit: impl FnMut(usize, f32) -> Option<HitRecord>,
+    ) -> Option<(usize, HitRecord)> {
+        let mut closest: Option<(usize, HitRecord)> = None;
+        let mut closest_so_far = ray_tmax;
+        self.traverse(ray, ray_tmin, ray_tmax, |index, tmax| {
+            if let Some(hit_record) = hit(index, tmax) {
+                if hit_record.t < closest_so_far {
+                    closest_so_far = hit_record.t;
+                    closest = Some((index, hit_record));
+                }
+            }
+            (closest_so_far, false)
+        });
+        closest
+    }
+
+    // true as soon as hit reports anything, in no particular order
+    pub fn any_hit(
+        &self,
+        ray: &Ray,
+        ray_tmin: f32,
+        ray_tmax: f32,
+        mut hit: impl FnMut(usize) -> bool,
+    ) -> bool {
+        let mut found = false;
+        self.traverse(ray, ray_tmin, ray_tmax, |index, tmax| {
+            found = hit(index);
+            (tmax, found)
+        });
+        found
+    }
+
+    // visits leaves front to back. visit returns the new tmax and whether to stop
+    fn traverse(
+        &self,
+        ray: &Ray,
+        ray_tmin: f32,
+        ray_tmax: f32,
+        mut visit: impl FnMut(usize, f32) -> (f32, bool),
+    ) {
+        let origin = Vec3A::from(ray.origin);
+        let inv_dir = Vec3A::from(ray.dir).recip();
+        let enter = |node: &BvhNode, tmax: f32| {
+            let t0 = (node.min - origin) * inv_dir;
+            let t1 = (node.max - origin) * inv_dir;
+            let t_near = t0.min(t1).max_element().max(ray_tmin);
+            let t_far = t0.max(t1).min_element().min(tmax);
+            (t_near <= t_far).then_some(t_near)
+        };
+        let mut tmax = ray_tmax;
+
+        let Some(root_t) = self.nodes.first().and_then(|root| enter(root, tmax)) else {
+            return;
+        };
+        // nodes waiting to be visited and where the ray enters them
+        let mut stack = [(0u32, 0.0f32); MAX_DEPTH + 1];
+        stack[0] = (0, root_t);
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (mut node_index, t_enter) = stack[stack_len];
+            if t_enter > tmax {
+                // something closer turned up since this was pushed
+                continue;
+            }
+            loop {
+                let node = &self.nodes[node_index as usize];
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    for &index in &self.indices[start..start + node.count as usize] {
+                        let (new_tmax, stop) = visit(index as usize, tmax);
+                        if stop {
+                            return;
+                        }
+                        tmax = new_tmax;
+                    }
+                    break;
+                }
+
+                // go into the nearer child, come back for the other one
+                let (first, second) = (node_index + 1, node.offset);
+                let first_t = enter(&self.nodes[first as usize], tmax);
+                let second_t = enter(&self.nodes[second as usize], tmax);
+                match (first_t, second_t) {
+                    (Some(first_t), Some(second_t)) => {
+                        let (near, far, far_t) = if first_t <= second_t {
+                            (first, second, second_t)
+                        } else {
+                            (second, first, first_t)
+                        };
+                        stack[stack_len] = (far, far_t);
+                        stack_len += 1;
+                        node_index = near;
+                    }
+                    (Some(_), None) => node_index = first,
+                    (None, Some(_)) => node_index = second,
+                    (None, None) => break,
+                }
+            }
+        }
+    }
+
+    // closest_hit for a packet, sharing one walk down the tree. hit gets a
+    // primitive, the lanes that reached it and their closest distances so far
+    pub fn closest_hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        mut hit: impl FnMut(usize, BVec4A, Vec4) -> PacketHits,
+    ) -> [Option<(usize, HitRecord)>; PACKET_SIZE] {
+        let mut closest: [Option<(usize, HitRecord)>; PACKET_SIZE] = Default::default();
+        self.traverse_packet(packet, ray_tmin, ray_tmax, |index, active, mut tmax| {
+            for (lane, hit_record) in hit(index, active, tmax).into_iter().enumerate() {
+                if let Some(hit_record) = hit_record {
+                    if hit_record.t < tmax[lane] {
+                        tmax[lane] = hit_record.t;
+                        closest[lane] = Some((index, hit_record));
+                    }
+                }
+            }
+            tmax
+        });
+        closest
+    }
+
+    // any_hit for a packet, lanes drop out as soon as they're blocked
+    pub fn any_hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        mut hit: impl FnMut(usize, BVec4A, Vec4) -> BVec4A,
+    ) -> BVec4A {
+        let mut found = BVec4A::splat(false);
+        self.traverse_packet(packet, ray_tmin, ray_tmax, |index, active, tmax| {
+            found |= hit(index, active, tmax);
+            // an empty range makes every box miss for finished lanes
+            Vec4::select(found, Vec4::NEG_INFINITY, tmax)
+        });
+        found
+    }
+
+    // visits leaves any lane reaches, roughly front to back. visit gets the
+    // lanes that reached the leaf and returns the new tmax per lane
+    fn traverse_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        mut visit: impl FnMut(usize, BVec4A, Vec4) -> Vec4,
+    ) {
+        let (origin, inv_dir) = (packet.origin, packet.inv_dir);
+        let tmin = Vec4::splat(ray_tmin);
+        // which lanes enter the node, and the nearest entry among them
+        let enter = |node: &BvhNode, tmax: Vec4| {
+            let slab = |min: f32, max: f32, origin: Vec4, inv_dir: Vec4| {
+                let t0 = (Vec4::splat(min) - origin) * inv_dir;
+                let t1 = (Vec4::splat(max) - origin) * inv_dir;
+                (t0.min(t1), t0.max(t1))
+            };
+            let (x_near, x_far) = slab(node.min.x, node.max.x, origin.x, inv_dir.x);
+            let (y_near, y_far) = slab(node.min.y, node.max.y, origin.y, inv_dir.y);
+            let (z_near, z_far) = slab(node.min.z, node.max.z, origin.z, inv_dir.z);
+            let t_near = x_near.max(y_near).max(z_near).max(tmin);
+            let t_far = x_far.min(y_far).min(z_far).min(tmax);
+            let mask = t_near.cmple(t_far);
+            let nearest = Vec4::select(mask, t_near, Vec4::INFINITY).min_element();
+            (mask, nearest)
+        };
+        let mut tmax = ray_tmax;
+
+        let Some(root) = self.nodes.first() else {
+            return;
+        };
+        let (root_mask, root_t) = enter(root, tmax);
+        if !root_mask.any() {
+            return;
+        }
+        let mut stack = [(0u32, 0.0f32); MAX_DEPTH + 1];
+        stack[0] = (0, root_t);
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (mut node_index, t_enter) = stack[stack_len];
+            if t_enter > tmax.max_element() {
+                continue;
+            }
+            loop {
+                let node = &self.nodes[node_index as usize];
+                if node.count > 0 {
+                    let (mut active, _) = enter(node, tmax);
+                    let start = node.offset as usize;
+                    for &index in &self.indices[start..start + node.count as usize] {
+                        if !active.any() {
+                            break;
+                        }
+                        tmax = visit(index as usize, active, tmax);
+                        active &= tmin.cmple(tmax);
+                    }
+                    break;
+                }
+
+                let (first, second) = (node_index + 1, node.offset);
+                let (first_mask, first_t) = enter(&self.nodes[first as usize], tmax);
+                let (second_mask, second_t) = enter(&self.nodes[second as usize], tmax);
+                match (first_mask.any(), second_mask.any()) {
+                    (true, true) => {
+                        let (near, far, far_t) = if first_t <= second_t {
+                            (first, second, second_t)
+                        } else {
+                            (second, first, first_t)
+                        };
+                        stack[stack_len] = (far, far_t);
+                        stack_len += 1;
+                        node_index = near;
+                    }
+                    (true, false) => node_index = first,
+                    (false, true) => node_index = second,
+                    (false, false) => break,
+                }
+            }
+        }
+    }
+}
+
+fn build_node(
+    nodes: &mut Vec<BvhNode>,
+    primitives: &mut [BuildPrimitive],
+    offset: usize,
+    depth: usize,
+) {
+    let aabb = primitives
+        .iter()
+        .fold(Aabb::empty(), |aabb, p| aabb.union(p.aabb));
+    let node_index = nodes.len();
+    nodes.push(BvhNode {
+        min: aabb.min.into(),
+        max: aabb.max.into(),
+        offset: offset as u32,
+        count: primitives.len() as u32,
+    });
+    if primitives.len() <= 1 || depth == MAX_DEPTH {
+        return;
+    }
+
+    let Some(mid) = find_split(primitives, aabb) else {
+        return;
+    };
+
+    let (first, second) = primitives.split_at_mut(mid);
+    build_node(nodes, first, offset, depth + 1);
+    let second_index = nodes.len();
+    build_node(nodes, second, offset + mid, depth + 1);
+    nodes[node_index] = BvhNode {
+        min: aabb.min.into(),
+        max: aabb.max.into(),
+        offset: second_index as u32,
+        count: 0,
+    };
+}
+
+// best binned sah split, partitions the primitives around it. None when a leaf is cheaper
+fn find_split(primitives: &mut [BuildPrimitive], aabb: Aabb) -> Option<usize> {
+    let centers = primitives
+        .iter()
+        .fold(Aabb::empty(), |centers, p| centers.grow(p.center));
+    let extent = centers.max - centers.min;
+
+    let bin_of = |center: Vec3, axis: usize| {
+        let t = (center[axis] - centers.min[axis]) / extent[axis];
+        ((t * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+    };
+
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if extent[axis] <= 0.0 {
+            continue;
+        }
+
+        let mut bins = [(Aabb::empty(), 0usize); SAH_BINS];
+        for primitive in primitives.iter() {
+            let bin = &mut bins[bin_of(primitive.center, axis)];
+            bin.0 = bin.0.union(primitive.aabb);
+            bin.1 += 1;
+        }
+
+        // sweep from the right, then from the left, pricing each boundary
+        let mut right_costs = [0.0f32; SAH_BINS];
+        let (mut right_aabb, mut right_count) = (Aabb::empty(), 0);
+        for bin in (1..SAH_BINS).rev() {
+            right_aabb = right_aabb.union(bins[bin].0);
+            right_count += bins[bin].1;
+            right_costs[bin] = right_aabb.surface_area() * right_count as f32;
+        }
+        let (mut left_aabb, mut left_count) = (Aabb::empty(), 0);
+        for bin in 1..SAH_BINS {
+            left_aabb = left_aabb.union(bins[bin - 1].0);
+            left_count += bins[bin - 1].1;
+            if left_count == 0 || left_count == primitives.len() {
+                continue;
+            }
+            let cost = left_aabb.surface_area() * left_count as f32 + right_costs[bin];
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, bin));
+            }
+        }
+    }
+
+    let Some((cost, axis, bin)) = best else {
+        // all centers in one spot, split down the middle if the leaf is too big
+        if primitives.len() <= MAX_LEAF_SIZE {
+            return None;
+        }
+        return Some(primitives.len() / 2);
+    };
+
+    let leaf_cost = primitives.len() as f32;
+    let split_cost = TRAVERSAL_COST + cost / aabb.surface_area().max(f32::MIN_POSITIVE);
+    if primitives.len() <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..primitives.len() {
+        if bin_of(primitives[i].center, axis) < bin {
+            primitives.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some(mid)
+}
@@ -0,0 +1,146 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    bvh::Aabb,
+    material::Material,
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+};
+
+#[derive(Clone, Copy)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // a with b carved out of it
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_a || in_b,
+            CsgOperation::Intersection => in_a && in_b,
+            CsgOperation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+// two closed shapes combined into one solid. the ray's hits on each are read as
+// the entries and exits of the intervals it spends inside them, and the solid's
+// surface is wherever the combined inside changes. the whole solid takes a's
+// material, so a hole carved by b is lined with a
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub a: Box<dyn Shape>,
+    pub b: Box<dyn Shape>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, a: Box<dyn Shape>, b: Box<dyn Shape>) -> Csg {
+        Csg { operation, a, b }
+    }
+
+    pub fn union(a: Box<dyn Shape>, b: Box<dyn Shape>) -> Csg {
+        Csg::new(CsgOperation::Union, a, b)
+    }
+
+    pub fn intersection(a: Box<dyn Shape>, b: Box<dyn Shape>) -> Csg {
+        Csg::new(CsgOperation::Intersection, a, b)
+    }
+
+    pub fn difference(a: Box<dyn Shape>, b: Box<dyn Shape>) -> Csg {
+        Csg::new(CsgOperation::Difference, a, b)
+    }
+
+    // the solid's surfaces in order. prim_index keeps the part's own index and
+    // which of a or b it came from in its lowest bit
+    fn boundaries(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Vec<HitRecord> {
+        // past ray_tmax too, a shape the ray starts inside only shows it by exiting
+        let hits_a = self.a.hit_all(ray, ray_tmin, f32::INFINITY);
+        let hits_b = self.b.hit_all(ray, ray_tmin, f32::INFINITY);
+        let mut in_a = hits_a.first().is_some_and(|hit| !hit.front_face);
+        let mut in_b = hits_b.first().is_some_and(|hit| !hit.front_face);
+        let mut inside = self.operation.inside(in_a, in_b);
+
+        let mut boundaries = Vec::new();
+        let (mut next_a, mut next_b) = (hits_a.iter().peekable(), hits_b.iter().peekable());
+        loop {
+            let (hit, from_b) = match (next_a.peek(), next_b.peek()) {
+                (Some(a), Some(b)) if b.t < a.t => (next_b.next().unwrap(), true),
+                (Some(_), _) => (next_a.next().unwrap(), false),
+                (None, Some(_)) => (next_b.next().unwrap(), true),
+                (None, None) => break,
+            };
+            if hit.t > ray_tmax {
+                break;
+            }
+
+            if from_b {
+                in_b = hit.front_face;
+            } else {
+                in_a = hit.front_face;
+            }
+            let now_inside = self.operation.inside(in_a, in_b);
+            if now_inside != inside {
+                let mut boundary = hit.clone();
+                boundary.prim_index = hit.prim_index * 2 + from_b as usize;
+                // the part's normal already faces the ray, only which side is outside can change
+                boundary.front_face = now_inside;
+                boundaries.push(boundary);
+                inside = now_inside;
+            }
+        }
+        boundaries
+    }
+
+    fn part(&self, hit_record: &HitRecord) -> (&dyn Shape, HitRecord) {
+        let mut part_hit_record = hit_record.clone();
+        part_hit_record.prim_index = hit_record.prim_index / 2;
+        let part = if hit_record.prim_index % 2 == 1 {
+            &self.b
+        } else {
+            &self.a
+        };
+        (part.as_ref(), part_hit_record)
+    }
+}
+
+impl Shape for Csg {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        self.boundaries(ray, ray_tmin, ray_tmax).into_iter().next()
+    }
+
+    // boundaries keep the parts' entering and exiting, so csg nests in csg
+    fn hit_all(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Vec<HitRecord> {
+        self.boundaries(ray, ray_tmin, ray_tmax)
+    }
+
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let (part, part_hit_record) = self.part(hit_record);
+        part.get_hit_uv(&part_hit_record)
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let (part, part_hit_record) = self.part(hit_record);
+        part.get_hit_tangents(&part_hit_record)
+    }
+
+    fn get_hit_color(&self, hit_record: &HitRecord) -> Option<Vec3> {
+        let (part, part_hit_record) = self.part(hit_record);
+        part.get_hit_color(&part_hit_record)
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.a.material()
+    }
+
+    // an intersection is no bigger than either part, so one with a plane can
+    // still go in the bvh. one of parts that don't touch comes out empty
+    fn aabb(&self) -> Aabb {
+        match self.operation {
+            CsgOperation::Union => self.a.aabb().union(self.b.aabb()),
+            CsgOperation::Intersection => self.a.aabb().intersection(self.b.aabb()),
+            CsgOperation::Difference => self.a.aabb(),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::{
+    bvh::{Aabb, Bounded, Bvh},
+    material::Material,
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+    utils::perpendicular_to,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CurveType {
+    // a ribbon that always turns to face the ray, lit flat across
+    Flat,
+    // the same ribbon, shaded as if it were round
+    Cylinder,
+}
+
+// one strand as cubic bezier segments sharing their ends, so 3n + 1 points.
+// the width is a bezier over the points too
+#[derive(Clone)]
+pub struct Strand {
+    pub points: Vec<Vec3>,
+    pub widths: Vec<f32>,
+}
+
+impl Strand {
+    pub fn new(points: Vec<Vec3>, widths: Vec<f32>) -> Strand {
+        assert!(points.len() >= 4 && (points.len() - 1).is_multiple_of(3));
+        assert!(widths.len() == points.len());
+        Strand { points, widths }
+    }
+
+    // narrowing evenly from root to tip
+    pub fn tapered(points: Vec<Vec3>, root_width: f32, tip_width: f32) -> Strand {
+        let last = (points.len() - 1).max(1) as f32;
+        let widths = (0..points.len())
+            .map(|i| root_width + (tip_width - root_width) * i as f32 / last)
+            .collect();
+        Strand::new(points, widths)
+    }
+}
+
+pub struct CurveSegment {
+    pub points: [Vec3; 4],
+    pub widths: Vec4,
+    // the stretch of its strand this segment covers, the strand runs 0 to 1
+    pub strand_u: Vec2,
+}
+
+impl CurveSegment {
+    // position and derivative at u along the segment
+    fn at(&self, u: f32) -> (Vec3, Vec3) {
+        let [p0, p1, p2, p3] = self.points;
+        let (a, b, c) = (p0.lerp(p1, u), p1.lerp(p2, u), p2.lerp(p3, u));
+        let (d, e) = (a.lerp(b, u), b.lerp(c, u));
+        (d.lerp(e, u), 3.0 * (e - d))
+    }
+
+    fn width_at(&self, u: f32) -> f32 {
+        let [w0, w1, w2, w3] = self.widths.to_array();
+        let s = 1.0 - u;
+        s * s * s * w0 + 3.0 * s * s * u * w1 + 3.0 * s * u * u * w2 + u * u * u * w3
+    }
+
+    // control points in the ray's frame, xyz in ray space and width in w
+    fn to_ray_space(&self, frame: &RayFrame) -> [Vec4; 4] {
+        std::array::from_fn(|i| {
+            let local = self.points[i] - frame.origin;
+            Vec4::new(
+                local.dot(frame.x),
+                local.dot(frame.y),
+                local.dot(frame.z),
+                self.widths[i],
+            )
+        })
+    }
+
+    // nearest (z, u) where the ray passes within half the width of the curve
+    fn hit(&self, frame: &RayFrame, z_min: f32, z_max: f32) -> Option<(f32, f32)> {
+        let cp = self.to_ray_space(frame);
+        let max_width = cp.iter().map(|p| p.w).fold(0.0, f32::max);
+        if max_width <= 0.0 {
+            return None;
+        }
+
+        // deep enough that the pieces are flat to a twentieth of the width
+        let flatness = (0..2)
+            .map(|i| {
+                (cp[i] - 2.0 * cp[i + 1] + cp[i + 2])
+                    .truncate()
+                    .abs()
+                    .max_element()
+            })
+            .fold(0.0, f32::max);
+        let epsilon = max_width / 20.0;
+        let depth = if flatness > 0.0 {
+            ((std::f32::consts::SQRT_2 * 6.0 * flatness / (8.0 * epsilon)).log2() / 2.0)
+                .clamp(0.0, 10.0) as u32
+        } else {
+            0
+        };
+
+        let mut closest = None;
+        let mut z_max = z_max;
+        self.hit_piece(
+            &cp,
+            cp,
+            Vec2::new(0.0, 1.0),
+            depth,
+            z_min,
+            &mut z_max,
+            &mut closest,
+        );
+        closest
+    }
+
+    // splits the curve in half until the pieces are nearly straight, skipping
+    // pieces whose bounds the ray misses, then tests the ray against each
+    // straight piece as a ribbon
+    #[allow(clippy::too_many_arguments)]
+    fn hit_piece(
+        &self,
+        full: &[Vec4; 4],
+        cp: [Vec4; 4],
+        u_range: Vec2,
+        depth: u32,
+        z_min: f32,
+        z_max: &mut f32,
+        closest: &mut Option<(f32, f32)>,
+    ) {
+        let half_width = cp.iter().map(|p| p.w).fold(0.0, f32::max) * 0.5;
+        let (min, max) = cp
+            .iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), p| {
+                (min.min(p.truncate()), max.max(p.truncate()))
+            });
+        let (min, max) = (min - half_width, max + half_width);
+        // the ray is the z axis of its own frame
+        if min.x > 0.0 || max.x < 0.0 || min.y > 0.0 || max.y < 0.0 {
+            return;
+        }
+        if min.z > *z_max || max.z < z_min {
+            return;
+        }
+
+        if depth > 0 {
+            let (a, b) = split(cp);
+            let middle = (u_range.x + u_range.y) * 0.5;
+            self.hit_piece(
+                full,
+                a,
+                Vec2::new(u_range.x, middle),
+                depth - 1,
+                z_min,
+                z_max,
+                closest,
+            );
+            self.hit_piece(
+                full,
+                b,
+                Vec2::new(middle, u_range.y),
+                depth - 1,
+                z_min,
+                z_max,
+                closest,
+            );
+            return;
+        }
+
+        // the ray has to land between the lines square to the piece at its ends
+        let start_edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        let end_edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if start_edge < 0.0 || end_edge < 0.0 {
+            return;
+        }
+
+        // closest point to the ray along the straightened piece
+        let direction = cp[3].truncate().truncate() - cp[0].truncate().truncate();
+        let length_squared = direction.length_squared();
+        if length_squared == 0.0 {
+            return;
+        }
+        let w = (-cp[0].truncate().truncate()).dot(direction) / length_squared;
+        let u = (u_range.x + (u_range.y - u_range.x) * w).clamp(u_range.x, u_range.y);
+
+        let point = bezier(full, u);
+        if point.x * point.x + point.y * point.y > point.w * point.w * 0.25 {
+            return;
+        }
+        if point.z < z_min || point.z > *z_max {
+            return;
+        }
+        *z_max = point.z;
+        *closest = Some((point.z, u));
+    }
+}
+
+impl Bounded for CurveSegment {
+    fn aabb(&self) -> Aabb {
+        let half_width = self.widths.max_element() * 0.5;
+        let aabb = Aabb::from_points(&self.points);
+        Aabb::new(aabb.min - half_width, aabb.max + half_width)
+    }
+}
+
+// the ray's origin and an orthonormal frame with z along it
+struct RayFrame {
+    origin: Vec3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    // world distance per unit of t
+    length: f32,
+}
+
+impl RayFrame {
+    fn new(ray: &Ray) -> RayFrame {
+        let length = ray.dir.length();
+        let z = ray.dir / length;
+        let x = perpendicular_to(z);
+        RayFrame {
+            origin: ray.origin,
+            x,
+            y: z.cross(x),
+            z,
+            length,
+        }
+    }
+}
+
+fn bezier(cp: &[Vec4; 4], u: f32) -> Vec4 {
+    let (a, b, c) = (
+        cp[0].lerp(cp[1], u),
+        cp[1].lerp(cp[2], u),
+        cp[2].lerp(cp[3], u),
+    );
+    let (d, e) = (a.lerp(b, u), b.lerp(c, u));
+    d.lerp(e, u)
+}
+
+// de casteljau at the middle, both halves are beziers again
+fn split(cp: [Vec4; 4]) -> ([Vec4; 4], [Vec4; 4]) {
+    let (a, b, c) = (
+        cp[0].lerp(cp[1], 0.5),
+        cp[1].lerp(cp[2], 0.5),
+        cp[2].lerp(cp[3], 0.5),
+    );
+    let (d, e) = (a.lerp(b, 0.5), b.lerp(c, 0.5));
+    let middle = d.lerp(e, 0.5);
+    ([cp[0], a, d, middle], [middle, e, c, cp[3]])
+}
+
+// many strands under one material, with a bvh over their segments like
+// TrisModel has over its triangles. fur and grass are thousands of strands,
+// as triangles they would be millions
+pub struct Curves {
+    pub segments: Vec<CurveSegment>,
+    pub curve_type: CurveType,
+    pub material: Box<dyn Material>,
+    bvh: Bvh,
+}
+
+impl Curves {
+    pub fn new(strands: Vec<Strand>, curve_type: CurveType, material: Box<dyn Material>) -> Curves {
+        let mut segments = Vec::new();
+        for strand in &strands {
+            let num_segments = (strand.points.len() - 1) / 3;
+            for i in 0..num_segments {
+                let start = i * 3;
+                segments.push(CurveSegment {
+                    points: std::array::from_fn(|j| strand.points[start + j]),
+                    widths: Vec4::from_array(std::array::from_fn(|j| strand.widths[start + j])),
+                    strand_u: Vec2::new(i as f32, (i + 1) as f32) / num_segments as f32,
+                });
+            }
+        }
+        let bvh = Bvh::build(&segments);
+        Curves {
+            segments,
+            curve_type,
+            material,
+            bvh,
+        }
+    }
+}
+
+impl Shape for Curves {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let frame = RayFrame::new(ray);
+        // only t and u while searching, the rest is worked out for the winner
+        let (index, closest) =
+            self.bvh
+                .closest_hit(ray, ray_tmin, ray_tmax, |index, closest_so_far| {
+                    let (z, u) = self.segments[index].hit(
+                        &frame,
+                        ray_tmin * frame.length,
+                        closest_so_far * frame.length,
+                    )?;
+                    let mut hit_record = HitRecord::new();
+                    hit_record.t = z / frame.length;
+                    hit_record.barycentric.x = u;
+                    Some(hit_record)
+                })?;
+        let (t, u) = (closest.t, closest.barycentric.x);
+
+        let segment = &self.segments[index];
+        let (center, dpdu) = segment.at(u);
+        let width = segment.width_at(u);
+        let tangent = dpdu.normalize_or_zero();
+
+        // the ribbon faces back along the ray, square to the curve
+        let mut normal = (-frame.z + tangent * tangent.dot(frame.z)).normalize_or_zero();
+        if normal == Vec3::ZERO {
+            normal = -frame.z;
+        }
+        let side = tangent.cross(normal);
+        let p = ray.at(t);
+        let across =
+            ((p - center).dot(side) / (width * 0.5).max(f32::MIN_POSITIVE)).clamp(-1.0, 1.0);
+
+        let mut hit_record = HitRecord::new();
+        hit_record.t = t;
+        hit_record.p = p;
+        // the ribbon isn't where a ray from the other side would see it, so
+        // spawned rays start past the strand's whole width
+        hit_record.p_error = Vec3::splat(width);
+        hit_record.set_face_normal(ray, normal);
+        if self.curve_type == CurveType::Cylinder {
+            hit_record.set_shading_normal(normal * (1.0 - across * across).sqrt() + side * across);
+        }
+        hit_record.prim_index = index;
+        hit_record.barycentric = Vec2::new(u, (across + 1.0) * 0.5);
+        Some(hit_record)
+    }
+
+    // u along the whole strand from its root, v across the width
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let strand_u = self.segments[hit_record.prim_index].strand_u;
+        Vec2::new(
+            strand_u.x + (strand_u.y - strand_u.x) * hit_record.barycentric.x,
+            hit_record.barycentric.y,
+        )
+    }
+
+    // along the strand first, hair shading lights it by this one
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let segment = &self.segments[hit_record.prim_index];
+        let (_, dpdu) = segment.at(hit_record.barycentric.x);
+        let width = segment.width_at(hit_record.barycentric.x);
+        let side = dpdu.cross(hit_record.geometric_normal).normalize_or_zero();
+        (
+            dpdu / (segment.strand_u.y - segment.strand_u.x),
+            side * width,
+        )
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bvh.aabb()
+    }
+}
@@ -0,0 +1,189 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use glam::{Quat, Vec2, Vec3};
+use rand::{rngs::SmallRng, Rng};
+use rayon::prelude::*;
+
+use crate::{
+    error::Result,
+    texture::{AddressMode, FilterMode, Texture},
+    texture_cache::{TextureCache, TextureUsage},
+};
+
+// whatever surrounds the scene: rays that escape see it, surfaces are lit by it.
+// radiance is in the same 0-255 units as every other color
+pub trait Environment: Sync {
+    fn radiance(&self, dir: Vec3) -> Vec3;
+    // a direction worth sending a shadow ray towards, with its solid angle pdf
+    fn sample(&self, rng: &mut SmallRng) -> (Vec3, f32);
+}
+
+// piecewise constant distribution over [0, 1), sampled by inverting its cdf
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f32;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // all zero falls back to uniform
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f32 / n as f32
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    // (x in [0, 1), pdf of x, bucket index)
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        let index = self.cdf.partition_point(|&c| c <= u).clamp(1, n) - 1;
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+        let pdf = self.pdf(index);
+        ((index as f32 + offset) / n as f32, pdf, index)
+    }
+
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[index] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// equirectangular image around the scene, u follows the azimuth, v runs top to bottom
+pub struct EnvironmentMap {
+    texture: Arc<Texture>,
+    pub rotation: Quat,
+    pub intensity: f32,
+    // rows, then columns within the picked row
+    marginal: Distribution1D,
+    conditionals: Vec<Distribution1D>,
+}
+
+// the importance grid doesnt need the full image
+const MAX_DISTRIBUTION_WIDTH: u32 = 512;
+
+impl EnvironmentMap {
+    pub fn new(texture_path: &str) -> Result<EnvironmentMap> {
+        let texture = TextureCache::global().load(texture_path, TextureUsage::Color)?;
+
+        // each cell averages every texel it covers, so a sun smaller than a cell
+        // still shows up in it instead of falling between the sampled points
+        let (texture_width, texture_height) = (texture.width(), texture.height());
+        let width = texture_width.min(MAX_DISTRIBUTION_WIDTH);
+        let height = texture_height.clamp(1, MAX_DISTRIBUTION_WIDTH / 2);
+        let conditionals: Vec<Distribution1D> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                let v = (y as f32 + 0.5) / height as f32;
+                // rows near the poles cover less of the sphere
+                let sin_theta = (v * PI).sin();
+                let rows = y * texture_height / height..(y + 1) * texture_height / height;
+                let row = (0..width)
+                    .map(|x| {
+                        let columns = x * texture_width / width..(x + 1) * texture_width / width;
+                        let mut sum = 0.0;
+                        for ty in rows.clone() {
+                            for tx in columns.clone() {
+                                sum += luminance(texture.get_pixel(tx, ty)).max(0.0);
+                            }
+                        }
+                        sum / (rows.len() * columns.len()) as f32 * sin_theta
+                    })
+                    .collect();
+                Distribution1D::new(row)
+            })
+            .collect();
+        let marginal = Distribution1D::new(conditionals.iter().map(|c| c.integral()).collect());
+
+        Ok(EnvironmentMap {
+            texture,
+            rotation: Quat::IDENTITY,
+            intensity: 1.0,
+            marginal,
+            conditionals,
+        })
+    }
+
+    pub fn rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, dir: Vec3) -> Vec3 {
+        let uv = direction_to_equirect(self.rotation.inverse() * dir);
+        self.texture.filter(
+            uv,
+            Vec2::ZERO,
+            Vec2::ZERO,
+            AddressMode::Repeat,
+            FilterMode::Bilinear,
+        ) * self.intensity
+    }
+
+    fn sample(&self, rng: &mut SmallRng) -> (Vec3, f32) {
+        let (v, pdf_v, row) = self.marginal.sample(rng.gen::<f32>());
+        let (u, pdf_u, _) = self.conditionals[row].sample(rng.gen::<f32>());
+
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return (Vec3::Y, 0.0);
+        }
+        let dir = self.rotation * equirect_to_direction(Vec2::new(u, v));
+        // uv area to solid angle
+        let pdf = pdf_u * pdf_v / (2.0 * PI * PI * sin_theta);
+        (dir, pdf)
+    }
+}
+
+pub fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+pub fn direction_to_equirect(dir: Vec3) -> Vec2 {
+    let u = 0.5 + dir.x.atan2(-dir.z) / (2.0 * PI);
+    let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+    Vec2::new(u, v)
+}
+
+pub fn equirect_to_direction(uv: Vec2) -> Vec3 {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
@@ -0,0 +1,91 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+// anything that can go wrong getting a scene's files in or its frames out,
+// always with the file it was about
+#[derive(Debug)]
+pub enum Error {
+    // couldn't be opened, read or written at all
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    // read, but not decodable as an image
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Obj {
+        path: PathBuf,
+        source: tobj::LoadError,
+    },
+    // ply, stl and ies files the loaders can't make sense of, or a file
+    // holding the wrong kind of thing for what it was loaded as
+    Parse {
+        path: PathBuf,
+        reason: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    // io failures inside the image crate are still io failures
+    pub fn image(path: impl Into<PathBuf>, source: image::ImageError) -> Error {
+        match source {
+            image::ImageError::IoError(source) => Error::io(path, source),
+            source => Error::Image {
+                path: path.into(),
+                source,
+            },
+        }
+    }
+
+    pub fn parse(path: impl Into<PathBuf>, reason: impl Into<String>) -> Error {
+        Error::Parse {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Error::Io { path, .. }
+            | Error::Image { path, .. }
+            | Error::Obj { path, .. }
+            | Error::Parse { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path().display();
+        match self {
+            Error::Io { source, .. } => write!(f, "{path}: {source}"),
+            Error::Image { source, .. } => write!(f, "{path}: {source}"),
+            Error::Obj { source, .. } => write!(f, "{path}: {source}"),
+            Error::Parse { reason, .. } => write!(f, "{path}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Image { source, .. } => Some(source),
+            Error::Obj { source, .. } => Some(source),
+            Error::Parse { .. } => None,
+        }
+    }
+}
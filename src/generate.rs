@@ -1,20 +1,23 @@
 use indicatif::ProgressBar;
 
+use crate::error::{Error, Result};
 use crate::image_writing::write_as_png;
 use crate::scene::{Scene, SceneBuilder};
+use crate::texture_cache::TextureCache;
 use glam::IVec2;
 
-pub type SceneModifier = fn(&mut Scene);
-pub type ProceduralSceneModifier = fn(&mut Scene, u32, u32);
+pub type SceneModifier = fn(&mut Scene) -> Result<()>;
+pub type ProceduralSceneModifier = fn(&mut Scene, u32, u32) -> Result<()>;
 
 pub fn generate_image(
     resolution: IVec2,
     num_samples_per_pixel: u32,
     rng_seed: [u8; 32],
     scene_builder: &SceneBuilder,
-) {
-    let scene = scene_builder.generate_static();
+) -> Result<()> {
+    let scene = scene_builder.generate_static()?;
     let optimized_scene = scene.optimize();
+    print_texture_memory();
 
     const MULTITHREADED: bool = true;
     const USE_PROGRESS_BAR: bool = true;
@@ -29,7 +32,7 @@ pub fn generate_image(
     );
 
     write_as_png("output", &pixels, resolution.x as u32, resolution.y as u32)
-        .expect("Failed to write PNG file");
+        .map_err(|error| Error::io("output.png", error))
 }
 
 pub fn generate_animation(
@@ -39,18 +42,25 @@ pub fn generate_animation(
     rng_seed: [u8; 32],
 
     scene_builder: &SceneBuilder,
-) {
+) -> Result<()> {
+    // a missing or broken asset should stop things now, not hours in
+    scene_builder.validate(num_frames)?;
+
     // clear/make folder to store frames
     let path = std::path::Path::new("animation");
     if path.exists() {
-        std::fs::remove_dir_all(path).expect("Failed to remove animation folder");
+        std::fs::remove_dir_all(path).map_err(|error| Error::io(path, error))?;
     }
-    std::fs::create_dir_all("animation").expect("Failed to create animation folder");
+    std::fs::create_dir_all(path).map_err(|error| Error::io(path, error))?;
 
     let pb = ProgressBar::new(num_frames as u64);
+    let mut previous_scene = None;
     for frame in 0..num_frames {
-        let scene = scene_builder.generate(num_frames, frame);
-        let optimized_scene = scene.optimize();
+        let scene = scene_builder.generate(num_frames, frame)?;
+        let optimized_scene = match previous_scene.take() {
+            Some(previous_scene) => scene.optimize_from(previous_scene),
+            None => scene.optimize(),
+        };
 
         let pixels = crate::rendering::render_scene(
             &optimized_scene,
@@ -64,16 +74,30 @@ pub fn generate_animation(
         // save rendered  frame
         let path = format!("animation/{}", frame);
         write_as_png(&path, &pixels, resolution.x as u32, resolution.y as u32)
-            .expect("Failed to write PNG file");
+            .map_err(|error| Error::io(format!("{path}.png"), error))?;
 
+        previous_scene = Some(optimized_scene);
         pb.inc(1);
     }
     pb.finish_with_message("Animation complete");
+    print_texture_memory();
 
     // run make_vid.sh
     let output = std::process::Command::new("sh")
         .arg("make_vid.sh")
         .output()
-        .expect("Failed to run make_vid.sh");
+        .map_err(|error| Error::io("make_vid.sh", error))?;
     println!("{}", String::from_utf8_lossy(&output.stdout));
+    Ok(())
+}
+
+fn print_texture_memory() {
+    let cache = TextureCache::global();
+    if cache.num_files() > 0 {
+        println!(
+            "textures: {} files, {:.2} MiB",
+            cache.num_files(),
+            cache.memory_usage() as f64 / (1024.0 * 1024.0)
+        );
+    }
 }
@@ -0,0 +1,354 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    bvh::Aabb,
+    error::{Error, Result},
+    material::Material,
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+    texture_cache::{TextureCache, TextureUsage},
+    triangle::{hit_triangle, triangle_point, RayShear},
+};
+
+// a grid of heights over the xz plane, each cell split into two triangles.
+// a min-max pyramid over the cells lets the ray skip everything it passes over
+// or under, so a big terrain costs about as much as the cells near the ray
+pub struct Heightfield {
+    // the corner at the lowest x and z, heights are measured up from its y
+    pub origin: Vec3,
+    // x and z extent
+    pub size: Vec2,
+    pub material: Box<dyn Material>,
+    // samples, the grid has one cell fewer each way
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    // per level the lowest and highest height under each node. level 0 is the
+    // cells, each level above covers 2x2 of the one below
+    levels: Vec<Level>,
+}
+
+struct Level {
+    width: usize,
+    depth: usize,
+    ranges: Vec<Vec2>,
+}
+
+impl Heightfield {
+    // heights row by row along x, width samples per row
+    pub fn new(
+        origin: Vec3,
+        size: Vec2,
+        width: usize,
+        depth: usize,
+        heights: Vec<f32>,
+        material: Box<dyn Material>,
+    ) -> Heightfield {
+        assert!(width >= 2 && depth >= 2 && heights.len() == width * depth);
+        let mut heightfield = Heightfield {
+            origin,
+            size,
+            material,
+            width,
+            depth,
+            heights,
+            normals: Vec::new(),
+            levels: Vec::new(),
+        };
+        heightfield.normals = heightfield.vertex_normals();
+        heightfield.levels = heightfield.min_max_levels();
+        heightfield
+    }
+
+    // height sampled at uv across the grid, both in [0, 1]
+    pub fn from_fn(
+        origin: Vec3,
+        size: Vec2,
+        width: usize,
+        depth: usize,
+        height: impl Fn(Vec2) -> f32,
+        material: Box<dyn Material>,
+    ) -> Heightfield {
+        let heights = (0..depth)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                height(Vec2::new(
+                    i as f32 / (width - 1) as f32,
+                    j as f32 / (depth - 1) as f32,
+                ))
+            })
+            .collect();
+        Heightfield::new(origin, size, width, depth, heights, material)
+    }
+
+    // one sample per texel, black at origin.y and white max_height above it
+    pub fn from_image(
+        texture_path: &str,
+        origin: Vec3,
+        size: Vec2,
+        max_height: f32,
+        material: Box<dyn Material>,
+    ) -> Result<Heightfield> {
+        let texture = TextureCache::global().load(texture_path, TextureUsage::Mask)?;
+        let (width, depth) = (texture.width() as usize, texture.height() as usize);
+        if width < 2 || depth < 2 {
+            return Err(Error::parse(
+                texture_path,
+                format!("{width}x{depth} image, a heightfield needs at least 2x2"),
+            ));
+        }
+        let heights = (0..depth)
+            .flat_map(|j| (0..width).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let texel = texture.get_pixel(i as u32, j as u32);
+                (texel.x + texel.y + texel.z) / 3.0 * max_height
+            })
+            .collect();
+        Ok(Heightfield::new(
+            origin, size, width, depth, heights, material,
+        ))
+    }
+
+    fn cell_size(&self) -> Vec2 {
+        self.size / Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        let cell_size = self.cell_size();
+        self.origin
+            + Vec3::new(
+                i as f32 * cell_size.x,
+                self.heights[j * self.width + i],
+                j as f32 * cell_size.y,
+            )
+    }
+
+    // central differences, one sided at the edges
+    fn vertex_normals(&self) -> Vec<Vec3> {
+        let cell_size = self.cell_size();
+        let height = |i: usize, j: usize| self.heights[j * self.width + i];
+        (0..self.depth)
+            .flat_map(|j| (0..self.width).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let (left, right) = (i.saturating_sub(1), (i + 1).min(self.width - 1));
+                let (back, front) = (j.saturating_sub(1), (j + 1).min(self.depth - 1));
+                let slope_x =
+                    (height(right, j) - height(left, j)) / ((right - left) as f32 * cell_size.x);
+                let slope_z =
+                    (height(i, front) - height(i, back)) / ((front - back) as f32 * cell_size.y);
+                Vec3::new(-slope_x, 1.0, -slope_z).normalize()
+            })
+            .collect()
+    }
+
+    fn min_max_levels(&self) -> Vec<Level> {
+        let (cells_x, cells_z) = (self.width - 1, self.depth - 1);
+        let ranges = (0..cells_z)
+            .flat_map(|j| (0..cells_x).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let corners = [
+                    self.heights[j * self.width + i],
+                    self.heights[j * self.width + i + 1],
+                    self.heights[(j + 1) * self.width + i],
+                    self.heights[(j + 1) * self.width + i + 1],
+                ];
+                corners
+                    .iter()
+                    .fold(Vec2::new(f32::INFINITY, f32::NEG_INFINITY), |range, &h| {
+                        Vec2::new(range.x.min(h), range.y.max(h))
+                    })
+            })
+            .collect();
+        let mut levels = vec![Level {
+            width: cells_x,
+            depth: cells_z,
+            ranges,
+        }];
+
+        while levels
+            .last()
+            .is_some_and(|level| level.width > 1 || level.depth > 1)
+        {
+            let below = levels.last().unwrap();
+            let (width, depth) = (below.width.div_ceil(2), below.depth.div_ceil(2));
+            let mut ranges = vec![Vec2::new(f32::INFINITY, f32::NEG_INFINITY); width * depth];
+            for j in 0..below.depth {
+                for i in 0..below.width {
+                    let range = &mut ranges[(j / 2) * width + i / 2];
+                    let child = below.ranges[j * below.width + i];
+                    *range = Vec2::new(range.x.min(child.x), range.y.max(child.y));
+                }
+            }
+            levels.push(Level {
+                width,
+                depth,
+                ranges,
+            });
+        }
+        levels
+    }
+
+    // the box under node (i, j) of a level. corners come from whole cell
+    // counts the same way vertex() does, so neighbouring boxes meet exactly
+    fn node_aabb(&self, level: usize, i: usize, j: usize) -> Aabb {
+        let cell_size = self.cell_size();
+        let cells = &self.levels[0];
+        let range = self.levels[level].ranges[j * self.levels[level].width + i];
+        let (min_i, min_j) = (i << level, j << level);
+        let max_i = ((i + 1) << level).min(cells.width);
+        let max_j = ((j + 1) << level).min(cells.depth);
+        Aabb::new(
+            self.origin
+                + Vec3::new(
+                    min_i as f32 * cell_size.x,
+                    range.x,
+                    min_j as f32 * cell_size.y,
+                ),
+            self.origin
+                + Vec3::new(
+                    max_i as f32 * cell_size.x,
+                    range.y,
+                    max_j as f32 * cell_size.y,
+                ),
+        )
+    }
+
+    // slab test against node_aabb. a ray running exactly along a node's wall,
+    // like the middle column of a centred camera, makes 0 * inf there. that
+    // slab doesn't limit the ray, where Aabb::intersect would drop it
+    fn enter_node(
+        &self,
+        ray: &Ray,
+        inv_dir: Vec3,
+        (level, i, j): (usize, usize, usize),
+        ray_tmin: f32,
+        ray_tmax: f32,
+    ) -> Option<f32> {
+        let aabb = self.node_aabb(level, i, j);
+        let t0 = (aabb.min - ray.origin) * inv_dir;
+        let t1 = (aabb.max - ray.origin) * inv_dir;
+        let on_wall = t0.is_nan_mask() | t1.is_nan_mask();
+        let near = Vec3::select(on_wall, Vec3::NEG_INFINITY, t0.min(t1));
+        let far = Vec3::select(on_wall, Vec3::INFINITY, t0.max(t1));
+        let t_near = near.max_element().max(ray_tmin);
+        let t_far = far.min_element().min(ray_tmax);
+        (t_near <= t_far).then_some(t_near)
+    }
+
+    // the cell's two triangles share its diagonal, as vertex indices
+    fn cell_triangles(&self, i: usize, j: usize) -> [[(usize, usize); 3]; 2] {
+        [
+            [(i, j), (i + 1, j), (i + 1, j + 1)],
+            [(i, j), (i + 1, j + 1), (i, j + 1)],
+        ]
+    }
+}
+
+impl Shape for Heightfield {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let shear = RayShear::new(ray.dir);
+        let inv_dir = ray.dir.recip();
+        let top = self.levels.len() - 1;
+        let entry = self.enter_node(ray, inv_dir, (top, 0, 0), ray_tmin, ray_tmax)?;
+
+        let mut closest = None;
+        let mut closest_so_far = ray_tmax;
+        // each level leaves at most three siblings waiting
+        let mut stack = [(0, 0, 0, 0.0); 3 * 32 + 1];
+        stack[0] = (top, 0, 0, entry);
+        let mut stack_size = 1;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let (level, i, j, entry) = stack[stack_size];
+            if entry > closest_so_far {
+                continue;
+            }
+
+            if level == 0 {
+                for (half, corners) in self.cell_triangles(i, j).into_iter().enumerate() {
+                    let vertices = corners.map(|(vi, vj)| self.vertex(vi, vj));
+                    if let Some((t, weights)) =
+                        hit_triangle(ray, &shear, vertices, ray_tmin, closest_so_far)
+                    {
+                        closest_so_far = t;
+                        let prim_index = (j * self.levels[0].width + i) * 2 + half;
+                        closest = Some((t, weights, vertices, corners, prim_index));
+                    }
+                }
+                continue;
+            }
+
+            // children far to near, so the nearest comes off the stack first
+            let below = &self.levels[level - 1];
+            let mut children = [(0, 0, f32::INFINITY); 4];
+            let mut num_children = 0;
+            for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (ci, cj) = (2 * i + di, 2 * j + dj);
+                if ci >= below.width || cj >= below.depth {
+                    continue;
+                }
+                if let Some(entry) =
+                    self.enter_node(ray, inv_dir, (level - 1, ci, cj), ray_tmin, closest_so_far)
+                {
+                    children[num_children] = (ci, cj, entry);
+                    num_children += 1;
+                }
+            }
+            children[..num_children].sort_unstable_by(|a, b| b.2.total_cmp(&a.2));
+            for &(ci, cj, entry) in &children[..num_children] {
+                stack[stack_size] = (level - 1, ci, cj, entry);
+                stack_size += 1;
+            }
+        }
+
+        let (t, weights, vertices, corners, prim_index) = closest?;
+        let (p, p_error) = triangle_point(vertices, weights);
+        let [a, b, c] = vertices;
+        let normal = (b - a).cross(c - a).normalize();
+        let shading_normal = corners
+            .iter()
+            .zip(weights.to_array())
+            .map(|(&(vi, vj), weight)| self.normals[vj * self.width + vi] * weight)
+            .sum::<Vec3>()
+            .normalize();
+
+        let mut hit_record = HitRecord::new();
+        hit_record.t = t;
+        hit_record.p = p;
+        hit_record.p_error = p_error;
+        hit_record.set_face_normal(ray, normal);
+        hit_record.set_shading_normal(shading_normal);
+        hit_record.prim_index = prim_index;
+        hit_record.barycentric = Vec2::new(weights.y, weights.z);
+        Some(hit_record)
+    }
+
+    // the grid's extent maps to [0, 1], like looking down on a texture
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let local = hit_record.p - self.origin;
+        Vec2::new(local.x, local.z) / self.size
+    }
+
+    // along x and z, climbing with the slope under the shading normal
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let normal = hit_record.normal;
+        let (slope_x, slope_z) = if normal.y.abs() > 1e-6 {
+            (-normal.x / normal.y, -normal.z / normal.y)
+        } else {
+            (0.0, 0.0)
+        };
+        (
+            Vec3::new(1.0, slope_x, 0.0) * self.size.x,
+            Vec3::new(0.0, slope_z, 1.0) * self.size.y,
+        )
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.node_aabb(self.levels.len() - 1, 0, 0)
+    }
+}
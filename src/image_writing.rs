@@ -21,5 +21,5 @@ pub fn write_as_png(
     }
 
     img.save(format!("{}.png", file_name))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        .map_err(io::Error::other)
 }
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use glam::{Affine3A, BVec4A, Mat3A, Quat, Vec2, Vec3, Vec4};
+
+use crate::{
+    bvh::Aabb,
+    material::Material,
+    packet::{lanes, PacketHits, RayPacket},
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+    utils::gamma,
+};
+
+// a shared shape placed in the world by a transform. the shape keeps its own
+// bvh in object space, so moving an instance between frames only needs the
+// scene's top level bvh refit
+pub struct Instance {
+    shape: Arc<dyn Shape>,
+    to_world: Affine3A,
+    to_object: Affine3A,
+    // set when the instance moves while the shutter is open
+    motion: Option<Motion>,
+}
+
+// scale, rotation and translation as the shutter opens and as it closes. kept
+// apart so the rotation turns in between rather than shearing the shape
+#[derive(Clone, Copy)]
+struct Motion {
+    start: (Vec3, Quat, Vec3),
+    end: (Vec3, Quat, Vec3),
+}
+
+impl Motion {
+    fn transform_at(&self, time: f32) -> Affine3A {
+        let (start_scale, start_rotation, start_translation) = self.start;
+        let (end_scale, end_rotation, end_translation) = self.end;
+        Affine3A::from_scale_rotation_translation(
+            start_scale.lerp(end_scale, time),
+            start_rotation.slerp(end_rotation, time),
+            start_translation.lerp(end_translation, time),
+        )
+    }
+}
+
+// the moving bounds are the union of the bounds at this many times across the shutter
+const MOTION_BOUND_STEPS: usize = 16;
+
+impl Instance {
+    pub fn new(shape: Arc<dyn Shape>, transform: Affine3A) -> Instance {
+        Instance {
+            shape,
+            to_world: transform,
+            to_object: transform.inverse(),
+            motion: None,
+        }
+    }
+
+    // placed by start as the shutter opens and by end as it closes, rays in
+    // between see it part way. with an instant shutter it sits at start
+    pub fn moving(shape: Arc<dyn Shape>, start: Affine3A, end: Affine3A) -> Instance {
+        if start == end {
+            return Instance::new(shape, start);
+        }
+        Instance {
+            motion: Some(Motion {
+                start: start.to_scale_rotation_translation(),
+                end: end.to_scale_rotation_translation(),
+            }),
+            ..Instance::new(shape, start)
+        }
+    }
+
+    pub fn set_transform(&mut self, transform: Affine3A) {
+        self.to_world = transform;
+        self.to_object = transform.inverse();
+        self.motion = None;
+    }
+
+    // to world and to object at the time
+    fn transforms_at(&self, time: f32) -> (Affine3A, Affine3A) {
+        match &self.motion {
+            Some(motion) => {
+                let to_world = motion.transform_at(time);
+                (to_world, to_world.inverse())
+            }
+            None => (self.to_world, self.to_object),
+        }
+    }
+
+    // the shape's hit record functions expect object space
+    fn object_hit_record(&self, hit_record: &HitRecord) -> HitRecord {
+        let (to_world, to_object) = self.transforms_at(hit_record.time);
+        let mut object_hit_record = hit_record.clone();
+        object_hit_record.p = to_object.transform_point3(hit_record.p);
+        object_hit_record.normal = normal_through(&to_world, hit_record.normal);
+        object_hit_record.geometric_normal = normal_through(&to_world, hit_record.geometric_normal);
+        object_hit_record
+    }
+}
+
+// the object's error grows through the matrix, plus the rounding of the transform itself
+fn point_to_world(to_world: &Affine3A, p: Vec3, p_error: Vec3) -> (Vec3, Vec3) {
+    let p_error =
+        (1.0 + gamma(3)) * (abs_matrix(to_world) * p_error) + transform_error(to_world, p);
+    (to_world.transform_point3(p), p_error)
+}
+
+// normals go through the inverse transpose so non uniform scales keep them perpendicular,
+// so to world takes the to object matrix and the other way round
+fn normal_through(inverse: &Affine3A, normal: Vec3) -> Vec3 {
+    (inverse.matrix3.transpose() * normal).normalize()
+}
+
+fn abs_matrix(transform: &Affine3A) -> Mat3A {
+    let m = transform.matrix3;
+    Mat3A::from_cols(m.x_axis.abs(), m.y_axis.abs(), m.z_axis.abs())
+}
+
+// rounding error of transforming the point p
+fn transform_error(transform: &Affine3A, p: Vec3) -> Vec3 {
+    let translation = Vec3::from(transform.translation).abs();
+    gamma(3) * (abs_matrix(transform) * p.abs() + translation)
+}
+
+// a world ray in the shape's space. scaling stretches the direction, so the
+// ray is renormalized and t is kept in world units by dividing length back out.
+// transforming the origin rounds it, maybe back into the surface a spawned ray
+// just left, so the ray starts skip past that error and skip is added back to t
+struct ObjectRay {
+    ray: Ray,
+    length: f32,
+    skip: f32,
+}
+
+impl ObjectRay {
+    fn new(ray: &Ray, to_object: &Affine3A) -> ObjectRay {
+        let dir = to_object.transform_vector3(ray.dir);
+        let length = dir.length();
+        let object_ray = Ray::new(to_object.transform_point3(ray.origin), dir)
+            .with_cone(ray.cone_width, ray.cone_spread)
+            .with_time(ray.time);
+
+        let origin_error = transform_error(to_object, ray.origin);
+        let skip = object_ray.dir.abs().dot(origin_error);
+        ObjectRay {
+            ray: Ray {
+                origin: object_ray.at(skip),
+                ..object_ray
+            },
+            length,
+            skip,
+        }
+    }
+
+    fn tmax(&self, ray_tmax: f32) -> f32 {
+        ray_tmax * self.length - self.skip
+    }
+
+    // the shape's hit back in world space
+    fn world_hit_record(
+        &self,
+        mut hit_record: HitRecord,
+        to_world: &Affine3A,
+        to_object: &Affine3A,
+    ) -> HitRecord {
+        hit_record.t = (hit_record.t + self.skip) / self.length;
+        let (p, p_error) = point_to_world(to_world, hit_record.p, hit_record.p_error);
+        hit_record.p = p;
+        hit_record.p_error = p_error;
+        hit_record.normal = normal_through(to_object, hit_record.normal);
+        hit_record.geometric_normal = normal_through(to_object, hit_record.geometric_normal);
+        hit_record.time = self.ray.time;
+        hit_record
+    }
+}
+
+impl Shape for Instance {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (to_world, to_object) = self.transforms_at(ray.time);
+        let object_ray = ObjectRay::new(ray, &to_object);
+        let hit_record = self.shape.hit(
+            &object_ray.ray,
+            ray_tmin * object_ray.length,
+            object_ray.tmax(ray_tmax),
+        )?;
+        Some(object_ray.world_hit_record(hit_record, &to_world, &to_object))
+    }
+
+    // the packet goes into object space whole, so the shape's own packet test
+    // and bvh traversal still run four wide
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        active: BVec4A,
+    ) -> PacketHits {
+        let mut hits = PacketHits::default();
+
+        // a moving instance is somewhere else for every lane's time, and a
+        // nonzero tmin would need a different object space tmin per lane
+        if self.motion.is_some() || ray_tmin != 0.0 {
+            for lane in lanes(active) {
+                hits[lane] = self.hit(&packet.rays[lane], ray_tmin, ray_tmax[lane]);
+            }
+            return hits;
+        }
+
+        let object_rays = packet
+            .rays
+            .each_ref()
+            .map(|ray| ObjectRay::new(ray, &self.to_object));
+        let object_packet = RayPacket::new(object_rays.each_ref().map(|object_ray| object_ray.ray));
+        let object_tmax = Vec4::from_array(std::array::from_fn(|lane| {
+            object_rays[lane].tmax(ray_tmax[lane])
+        }));
+        let object_hits = self
+            .shape
+            .hit_packet(&object_packet, 0.0, object_tmax, active);
+        for (lane, hit_record) in object_hits.into_iter().enumerate() {
+            hits[lane] = hit_record.map(|hit_record| {
+                object_rays[lane].world_hit_record(hit_record, &self.to_world, &self.to_object)
+            });
+        }
+        hits
+    }
+
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        self.shape.get_hit_uv(&self.object_hit_record(hit_record))
+    }
+
+    fn get_hit_color(&self, hit_record: &HitRecord) -> Option<Vec3> {
+        self.shape
+            .get_hit_color(&self.object_hit_record(hit_record))
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let (dpdu, dpdv) = self
+            .shape
+            .get_hit_tangents(&self.object_hit_record(hit_record));
+        let (to_world, _) = self.transforms_at(hit_record.time);
+        (
+            to_world.transform_vector3(dpdu),
+            to_world.transform_vector3(dpdv),
+        )
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.shape.material()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let aabb = self.shape.aabb();
+        if !aabb.is_finite() {
+            return Aabb::infinite();
+        }
+        let corners = aabb.corners();
+        let Some(motion) = &self.motion else {
+            return Aabb::from_points(
+                &corners.map(|corner| self.to_world.transform_point3(corner)),
+            );
+        };
+        let points: Vec<Vec3> = (0..=MOTION_BOUND_STEPS)
+            .flat_map(|step| {
+                let to_world = motion.transform_at(step as f32 / MOTION_BOUND_STEPS as f32);
+                corners.map(|corner| to_world.transform_point3(corner))
+            })
+            .collect();
+        let aabb = Aabb::from_points(&points);
+
+        // corners swing along arcs between the steps, bulging past the boxes by
+        // at most their reach times 1 - cos of half a step's turn
+        let step_angle = motion.start.1.angle_between(motion.end.1) / MOTION_BOUND_STEPS as f32;
+        let scale = motion.start.0.abs().max(motion.end.0.abs()).max_element();
+        let reach = corners
+            .iter()
+            .map(|corner| corner.length() * scale)
+            .fold(0.0, f32::max);
+        let bulge = reach * (1.0 - (step_angle * 0.5).cos());
+        Aabb::new(aabb.min - bulge, aabb.max + bulge)
+    }
+}
@@ -0,0 +1,190 @@
+use glam::Vec3;
+
+use crate::lights::Light;
+
+// power weighted bvh over the lights that have a position. picking a light
+// walks down from the root choosing each child by how much light it could
+// send towards the shading point, so the cost grows with the depth of the
+// tree instead of the number of lights
+pub struct LightTree {
+    nodes: Vec<LightNode>,
+    // lights without a position, too few to be worth sampling
+    infinite: Vec<usize>,
+}
+
+struct LightNode {
+    min: Vec3,
+    max: Vec3,
+    power: f32,
+    kind: LightNodeKind,
+}
+
+enum LightNodeKind {
+    // index into the scene's lights
+    Leaf(usize),
+    // the first child directly follows its parent, this is the second one
+    Interior(usize),
+}
+
+struct BuildLight {
+    index: usize,
+    pos: Vec3,
+    power: f32,
+}
+
+impl LightTree {
+    pub fn new(lights: &[Box<dyn Light>]) -> LightTree {
+        let mut infinite = vec![];
+        let mut positioned = vec![];
+        for (index, light) in lights.iter().enumerate() {
+            match light.position() {
+                Some(pos) => positioned.push(BuildLight {
+                    index,
+                    pos,
+                    power: light.power().max(0.0),
+                }),
+                None => infinite.push(index),
+            }
+        }
+
+        let mut nodes = Vec::with_capacity(positioned.len() * 2);
+        if !positioned.is_empty() {
+            build(&mut nodes, &mut positioned);
+        }
+        LightTree { nodes, infinite }
+    }
+
+    pub fn infinite_lights(&self) -> &[usize] {
+        &self.infinite
+    }
+
+    pub fn num_positioned_lights(&self) -> usize {
+        self.nodes.len().div_ceil(2)
+    }
+
+    // picks a positioned light for a point with normal n using u in [0, 1),
+    // returns the light's index and the probability it was picked with. no
+    // normal for points lit from every side, like hair
+    pub fn sample(&self, p: Vec3, n: Option<Vec3>, u: f32) -> Option<(usize, f32)> {
+        if self.nodes.is_empty() || self.nodes[0].importance(p, n) <= 0.0 {
+            return None;
+        }
+
+        let mut u = u;
+        let mut pdf = 1.0;
+        let mut node = 0;
+        loop {
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(index) => return Some((index, pdf)),
+                LightNodeKind::Interior(second_index) => {
+                    let (first, second) = (&self.nodes[node + 1], &self.nodes[second_index]);
+                    let mut weights = (first.importance(p, n), second.importance(p, n));
+                    if weights.0 + weights.1 <= 0.0 {
+                        // both estimates underflowed, fall back to plain power
+                        weights = (first.power, second.power);
+                    }
+                    let p_first = if weights.0 + weights.1 > 0.0 {
+                        weights.0 / (weights.0 + weights.1)
+                    } else {
+                        0.5
+                    };
+                    if u < p_first {
+                        u = (u / p_first).min(ONE_MINUS_EPSILON);
+                        pdf *= p_first;
+                        node += 1;
+                    } else {
+                        u = ((u - p_first) / (1.0 - p_first)).min(ONE_MINUS_EPSILON);
+                        pdf *= 1.0 - p_first;
+                        node = second_index;
+                    }
+                }
+            }
+        }
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON;
+
+impl LightNode {
+    // upper-ish bound on the light reaching p: power over squared distance,
+    // clamped inside the bounds, and nothing if the whole box is behind the surface
+    fn importance(&self, p: Vec3, n: Option<Vec3>) -> f32 {
+        let facing = |n: Vec3| {
+            (0..8).any(|corner| {
+                let c = Vec3::new(
+                    if corner & 1 == 0 {
+                        self.min.x
+                    } else {
+                        self.max.x
+                    },
+                    if corner & 2 == 0 {
+                        self.min.y
+                    } else {
+                        self.max.y
+                    },
+                    if corner & 4 == 0 {
+                        self.min.z
+                    } else {
+                        self.max.z
+                    },
+                );
+                n.dot(c - p) > 0.0
+            })
+        };
+        if !n.is_none_or(facing) {
+            return 0.0;
+        }
+
+        let center = (self.min + self.max) * 0.5;
+        let radius_squared = (self.max - center).length_squared();
+        // inside its bounds a node counts as being at their edge. a lone point
+        // light has no extent, so floor it at float precision around where it is
+        let floor = radius_squared
+            .max(center.length_squared() * f32::EPSILON)
+            .max(f32::MIN_POSITIVE);
+        let distance_squared = p.distance_squared(center).max(floor);
+        self.power / distance_squared
+    }
+}
+
+// splits at the median of the longest axis, writing nodes depth first
+fn build(nodes: &mut Vec<LightNode>, lights: &mut [BuildLight]) {
+    let (min, max) = lights.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), light| (min.min(light.pos), max.max(light.pos)),
+    );
+    let power = lights.iter().map(|light| light.power).sum();
+
+    if lights.len() == 1 {
+        nodes.push(LightNode {
+            min,
+            max,
+            power,
+            kind: LightNodeKind::Leaf(lights[0].index),
+        });
+        return;
+    }
+
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let mid = lights.len() / 2;
+    lights.select_nth_unstable_by(mid, |a, b| a.pos[axis].total_cmp(&b.pos[axis]));
+
+    let node = nodes.len();
+    nodes.push(LightNode {
+        min,
+        max,
+        power,
+        kind: LightNodeKind::Interior(0),
+    });
+    let (first, second) = lights.split_at_mut(mid);
+    build(nodes, first);
+    nodes[node].kind = LightNodeKind::Interior(nodes.len());
+    build(nodes, second);
+}
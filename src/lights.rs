@@ -0,0 +1,482 @@
+use std::f32::consts::PI;
+
+use glam::{Quat, Vec3};
+use rand::rngs::SmallRng;
+
+use crate::environment::luminance;
+use crate::error::{Error, Result};
+
+// where a light is, as seen from a shading point
+pub struct LightSample {
+    // normalized, from the shading point towards the light
+    pub dir: Vec3,
+    // how far a shadow ray has to go, infinite for directional lights
+    pub distance: f32,
+}
+
+// colors are in the usual 0-255 units, a light evaluating to 255 white lights
+// a head on surface as much as the old faux lights did
+pub trait Light: Sync {
+    fn sample(&self, p: Vec3, rng: &mut SmallRng) -> LightSample;
+    // light arriving at p from dir, falloff and profiles applied, no cosine
+    fn evaluate(&self, p: Vec3, dir: Vec3) -> Vec3;
+    // where the light sits, None for lights without one like directional lights
+    fn position(&self) -> Option<Vec3>;
+    // rough brightness for picking between many lights, scaled to one unit away
+    fn power(&self) -> f32;
+}
+
+// (reference_distance / distance)^2, full strength at the reference distance
+fn inverse_square(falloff: Option<f32>, distance: f32) -> f32 {
+    match falloff {
+        Some(reference_distance) => (reference_distance / distance.max(1e-4)).powi(2),
+        None => 1.0,
+    }
+}
+
+// brightness one unit away from a light with the given falloff
+fn falloff_power(color: Vec3, falloff: Option<f32>) -> f32 {
+    luminance(color) * falloff.map_or(1.0, |reference_distance| reference_distance.powi(2))
+}
+
+fn towards(from: Vec3, to: Vec3) -> LightSample {
+    let offset = to - from;
+    let distance = offset.length();
+    LightSample {
+        dir: offset / distance.max(1e-8),
+        distance,
+    }
+}
+
+#[derive(Clone)]
+pub struct PointLight {
+    pub pos: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    // None keeps the constant brightness of the original faux lights
+    pub falloff: Option<f32>,
+}
+
+impl PointLight {
+    pub fn new(pos: Vec3, color: Vec3) -> PointLight {
+        PointLight {
+            pos,
+            color,
+            intensity: 1.0,
+            falloff: None,
+        }
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    // inverse square falloff, full color at reference_distance
+    pub fn falloff(mut self, reference_distance: f32) -> Self {
+        self.falloff = Some(reference_distance);
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3, _rng: &mut SmallRng) -> LightSample {
+        towards(p, self.pos)
+    }
+
+    fn evaluate(&self, p: Vec3, _dir: Vec3) -> Vec3 {
+        let distance = p.distance(self.pos);
+        self.color * self.intensity * inverse_square(self.falloff, distance)
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        Some(self.pos)
+    }
+
+    fn power(&self) -> f32 {
+        falloff_power(self.color * self.intensity, self.falloff)
+    }
+}
+
+// infinitely far away, like the sun
+#[derive(Clone)]
+pub struct DirectionalLight {
+    // the way the light travels
+    pub dir: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl DirectionalLight {
+    pub fn new(dir: Vec3, color: Vec3) -> DirectionalLight {
+        DirectionalLight {
+            dir: dir.normalize(),
+            color,
+            intensity: 1.0,
+        }
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Vec3, _rng: &mut SmallRng) -> LightSample {
+        LightSample {
+            dir: -self.dir,
+            distance: f32::INFINITY,
+        }
+    }
+
+    fn evaluate(&self, _p: Vec3, _dir: Vec3) -> Vec3 {
+        self.color * self.intensity
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        None
+    }
+
+    fn power(&self) -> f32 {
+        luminance(self.color * self.intensity)
+    }
+}
+
+// a point light limited to a cone, fully bright inside inner_angle,
+// fading out smoothly towards outer_angle. angles are half angles in radians
+#[derive(Clone)]
+pub struct SpotLight {
+    pub pos: Vec3,
+    pub dir: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub falloff: Option<f32>,
+}
+
+impl SpotLight {
+    pub fn new(pos: Vec3, dir: Vec3, color: Vec3, inner_angle: f32, outer_angle: f32) -> SpotLight {
+        SpotLight {
+            pos,
+            dir: dir.normalize(),
+            color,
+            intensity: 1.0,
+            inner_angle: inner_angle.min(outer_angle),
+            outer_angle,
+            falloff: None,
+        }
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn falloff(mut self, reference_distance: f32) -> Self {
+        self.falloff = Some(reference_distance);
+        self
+    }
+
+    fn cone(&self, dir_from_light: Vec3) -> f32 {
+        let cos_angle = self.dir.dot(dir_from_light);
+        let (cos_outer, cos_inner) = (self.outer_angle.cos(), self.inner_angle.cos());
+        if cos_inner - cos_outer <= 1e-6 {
+            return if cos_angle >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3, _rng: &mut SmallRng) -> LightSample {
+        towards(p, self.pos)
+    }
+
+    fn evaluate(&self, p: Vec3, dir: Vec3) -> Vec3 {
+        let distance = p.distance(self.pos);
+        self.color * self.intensity * self.cone(-dir) * inverse_square(self.falloff, distance)
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        Some(self.pos)
+    }
+
+    fn power(&self) -> f32 {
+        falloff_power(self.color * self.intensity, self.falloff)
+    }
+}
+
+// candela distribution of a real fixture, from an LM-63 .ies file.
+// stored normalized to the brightest direction, so the light's color and
+// intensity still set the overall level
+#[derive(Clone)]
+pub struct IesProfile {
+    // degrees, 0 is straight down the fixture's axis
+    vertical_angles: Vec<f32>,
+    // degrees around the axis
+    horizontal_angles: Vec<f32>,
+    // candela[horizontal][vertical]
+    candela: Vec<Vec<f32>>,
+}
+
+impl IesProfile {
+    pub fn load(path: &str) -> Result<IesProfile> {
+        let text = std::fs::read_to_string(path).map_err(|error| Error::io(path, error))?;
+        IesProfile::parse(&text).ok_or_else(|| Error::parse(path, "not an LM-63 profile"))
+    }
+
+    // rotationally symmetric profile straight from vertical angles and candela values
+    pub fn symmetric(vertical_angles: Vec<f32>, candela: Vec<f32>) -> IesProfile {
+        IesProfile::new(vertical_angles, vec![0.0], vec![candela])
+    }
+
+    fn new(
+        vertical_angles: Vec<f32>,
+        horizontal_angles: Vec<f32>,
+        mut candela: Vec<Vec<f32>>,
+    ) -> IesProfile {
+        let max = candela
+            .iter()
+            .flatten()
+            .fold(0.0f32, |max, &value| max.max(value));
+        if max > 0.0 {
+            for value in candela.iter_mut().flatten() {
+                *value /= max;
+            }
+        }
+        IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        }
+    }
+
+    // header lines up to TILT=, then a flat run of numbers
+    fn parse(text: &str) -> Option<IesProfile> {
+        let tilt = text.find("TILT=")?;
+        let after_tilt = &text[tilt..];
+        let body_start = after_tilt.find('\n')? + 1;
+        let mut numbers = after_tilt[body_start..]
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f32>().ok());
+
+        if !after_tilt.starts_with("TILT=NONE") {
+            // tilt tables change output with lamp angle, skip them
+            let _geometry = numbers.next()??;
+            let pairs = numbers.next()?? as usize;
+            for _ in 0..pairs * 2 {
+                numbers.next()??;
+            }
+        }
+
+        let mut next = || numbers.next().flatten();
+        let _num_lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let num_vertical = next()? as usize;
+        let num_horizontal = next()? as usize;
+        let _photometric_type = next()?;
+        let _units = next()?;
+        let _width = next()?;
+        let _length = next()?;
+        let _height = next()?;
+        let _ballast_factor = next()?;
+        let _future_use = next()?;
+        let _input_watts = next()?;
+
+        let vertical_angles = (0..num_vertical)
+            .map(|_| next())
+            .collect::<Option<Vec<f32>>>()?;
+        let horizontal_angles = (0..num_horizontal)
+            .map(|_| next())
+            .collect::<Option<Vec<f32>>>()?;
+        let candela = (0..num_horizontal)
+            .map(|_| {
+                (0..num_vertical)
+                    .map(|_| next().map(|value| value * multiplier))
+                    .collect::<Option<Vec<f32>>>()
+            })
+            .collect::<Option<Vec<Vec<f32>>>>()?;
+
+        if vertical_angles.is_empty() || horizontal_angles.is_empty() {
+            return None;
+        }
+        Some(IesProfile::new(vertical_angles, horizontal_angles, candela))
+    }
+
+    // relative intensity in [0, 1] towards the given angles, in degrees
+    pub fn evaluate(&self, vertical: f32, horizontal: f32) -> f32 {
+        // files only store the unique part of symmetric fixtures
+        let last = *self.horizontal_angles.last().unwrap();
+        let horizontal = horizontal.rem_euclid(360.0);
+        let horizontal = if last <= 0.0 {
+            0.0
+        } else if last <= 90.0 {
+            let h = horizontal % 180.0;
+            if h > 90.0 {
+                180.0 - h
+            } else {
+                h
+            }
+        } else if last <= 180.0 {
+            if horizontal > 180.0 {
+                360.0 - horizontal
+            } else {
+                horizontal
+            }
+        } else {
+            horizontal
+        };
+
+        let (h0, h1, th) = bracket(&self.horizontal_angles, horizontal);
+        let (v0, v1, tv) = bracket(&self.vertical_angles, vertical);
+        let row = |h: usize| {
+            let values = &self.candela[h];
+            values[v0] + (values[v1] - values[v0]) * tv
+        };
+        row(h0) + (row(h1) - row(h0)) * th
+    }
+}
+
+// the two entries of a sorted list around x and how far between them x is
+fn bracket(angles: &[f32], x: f32) -> (usize, usize, f32) {
+    if x <= angles[0] {
+        return (0, 0, 0.0);
+    }
+    let last = angles.len() - 1;
+    if x >= angles[last] {
+        return (last, last, 0.0);
+    }
+    let i = angles.partition_point(|&a| a <= x) - 1;
+    let t = (x - angles[i]) / (angles[i + 1] - angles[i]);
+    (i, i + 1, t)
+}
+
+// a point light shaped by an ies profile. the fixture points down -y,
+// orientation turns it
+#[derive(Clone)]
+pub struct IesLight {
+    pub pos: Vec3,
+    pub orientation: Quat,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub profile: IesProfile,
+    pub falloff: Option<f32>,
+}
+
+impl IesLight {
+    pub fn new(pos: Vec3, color: Vec3, profile: IesProfile) -> IesLight {
+        IesLight {
+            pos,
+            orientation: Quat::IDENTITY,
+            color,
+            intensity: 1.0,
+            profile,
+            falloff: None,
+        }
+    }
+
+    pub fn orientation(mut self, orientation: Quat) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn falloff(mut self, reference_distance: f32) -> Self {
+        self.falloff = Some(reference_distance);
+        self
+    }
+}
+
+impl Light for IesLight {
+    fn sample(&self, p: Vec3, _rng: &mut SmallRng) -> LightSample {
+        towards(p, self.pos)
+    }
+
+    fn evaluate(&self, p: Vec3, dir: Vec3) -> Vec3 {
+        let local = self.orientation.inverse() * -dir;
+        let vertical = (-local.y).clamp(-1.0, 1.0).acos() * 180.0 / PI;
+        let horizontal = local.z.atan2(local.x) * 180.0 / PI;
+        let distance = p.distance(self.pos);
+        self.color
+            * self.intensity
+            * self.profile.evaluate(vertical, horizontal)
+            * inverse_square(self.falloff, distance)
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        Some(self.pos)
+    }
+
+    fn power(&self) -> f32 {
+        falloff_power(self.color * self.intensity, self.falloff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3 vertical angles, 1 horizontal one, a fixture that fades out to the side
+    const SYMMETRIC: &str = "IESNA:LM-63-2002\n[TEST] symmetric\nTILT=NONE\n\
+        1 1000 2 3 1 1 2 0 0 0\n1 1 100\n0 45 90\n0\n100 50 0\n";
+
+    fn profile(text: &str) -> IesProfile {
+        IesProfile::parse(text).expect("a profile")
+    }
+
+    #[test]
+    fn symmetric_profile_is_normalized() {
+        let profile = profile(SYMMETRIC);
+        assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+        assert_eq!(profile.candela, vec![vec![1.0, 0.5, 0.0]]);
+        assert_eq!(profile.evaluate(22.5, 123.0), 0.75);
+        assert_eq!(profile.evaluate(120.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn commas_separate_numbers_too() {
+        let text = SYMMETRIC.replace("100 50 0", "100,50,0");
+        assert_eq!(profile(&text).candela, vec![vec![1.0, 0.5, 0.0]]);
+    }
+
+    #[test]
+    fn tilt_table_is_skipped() {
+        let text = SYMMETRIC.replace("TILT=NONE\n", "TILT=INCLUDE\n1\n2\n0 90\n1 1\n");
+        assert_eq!(profile(&text).candela, vec![vec![1.0, 0.5, 0.0]]);
+    }
+
+    #[test]
+    fn quadrant_profile_is_mirrored() {
+        let text = "TILT=NONE\n1 1000 1 2 2 1 2 0 0 0\n1 1 100\n0 90\n0 90\n10 0\n20 0\n";
+        let profile = profile(text);
+        assert_eq!(profile.evaluate(0.0, 0.0), 0.5);
+        assert_eq!(profile.evaluate(0.0, 90.0), 1.0);
+        assert_eq!(profile.evaluate(0.0, 270.0), 1.0);
+        assert_eq!(profile.evaluate(0.0, 135.0), 0.75);
+    }
+
+    #[test]
+    fn truncated_profiles_are_rejected() {
+        assert!(IesProfile::parse(&SYMMETRIC.replace("100 50 0\n", "100 50")).is_none());
+        assert!(IesProfile::parse(&SYMMETRIC.replace("\n0 45 90\n0\n100 50 0\n", "")).is_none());
+        assert!(IesProfile::parse("IESNA:LM-63-2002\n[TEST] no tilt\n").is_none());
+        // counts far beyond what the body holds
+        let huge = SYMMETRIC.replace("1 1000 2 3 1", "1 1000 2 1e9 1e9");
+        assert!(IesProfile::parse(&huge).is_none());
+    }
+
+    #[test]
+    fn empty_angle_lists_are_rejected() {
+        let text = "TILT=NONE\n1 1000 1 0 0 1 2 0 0 0\n1 1 100\n";
+        assert!(IesProfile::parse(text).is_none());
+    }
+}
@@ -1,16 +1,39 @@
 use glam::IVec2;
 
-use crate::{generate::generate_image, scene::SceneBuilder};
+use crate::scene::SceneBuilder;
 
+pub mod bvh;
+pub mod csg;
+pub mod curves;
+pub mod environment;
+pub mod error;
 pub mod generate;
+pub mod heightfield;
 pub mod image_writing;
+pub mod instance;
+pub mod light_tree;
+pub mod lights;
 pub mod material;
+pub mod mesh;
+pub mod mesh_cache;
+pub mod packet;
+pub mod ply;
+pub mod point_cloud;
+pub mod primitives;
+pub mod procedural;
 pub mod rendering;
 pub mod scene;
 pub mod scenes;
+pub mod sdf;
 pub mod shape_bvh_node;
 pub mod shapes;
+pub mod sky;
 pub mod structures;
+pub mod subdivision;
+pub mod texture;
+pub mod texture_cache;
+pub mod texture_input;
+pub mod triangle;
 pub mod utils;
 
 fn main() {
@@ -39,7 +62,9 @@ fn main() {
     scene_builder.add_mod(scenes::fixed::quad_light);
 
     // scene_builder.add_mod(scenes::fixed::single_centered_light);
+    // scene_builder.add_mod(scenes::fixed::stage_lights);
     // scene_builder.add_mod(scenes::fixed::some_random_lights);
+    // scene_builder.add_mod(scenes::fixed::many_lights);
     // scene_builder.add_mod(scenes::fixed::basic_quad);
 
     // scene_builder.add_mod(scenes::fixed::light_box);
@@ -52,9 +77,23 @@ fn main() {
     scene_builder.add_mod(scenes::fixed::checkered_floor);
     // scene_builder.add_mod(scenes::fixed::textured_floor);
     // scene_builder.add_mod(scenes::fixed::matte_floor);
+    // scene_builder.add_mod(scenes::fixed::wood_floor);
+    // scene_builder.add_mod(scenes::fixed::marble_ball);
+    // scene_builder.add_mod(scenes::fixed::primitives);
+    // scene_builder.add_mod(scenes::fixed::csg);
+    // scene_builder.add_mod(scenes::fixed::sdf);
+    // scene_builder.add_mod(scenes::fixed::curves);
+    // scene_builder.add_mod(scenes::fixed::point_cloud);
+    // scene_builder.add_mod(scenes::fixed::graph_ball);
+    // scene_builder.add_mod(scenes::fixed::displaced_terrain);
+    // scene_builder.add_mod(scenes::fixed::heightfield_terrain);
 
     scene_builder.add_mod(scenes::fixed::sky_sphere);
+    // scene_builder.add_mod(scenes::fixed::hdr_sky);
+    // scene_builder.add_mod(scenes::fixed::afternoon_sky);
     scene_builder.add_mod(scenes::fixed::duck);
+    // scene_builder.add_mod(scenes::fixed::subdivided_ducks);
+    // scene_builder.add_mod(scenes::fixed::stl_spheres);
 
     // scene_builder.add_mod(scenes::fixed::infinite_checkered_floor);
     // scene_builder.add_mod(scenes::fixed::raised_cam);
@@ -66,12 +105,17 @@ fn main() {
     // scene_builder.add_mod(scenes::fixed::some_random_balls);
     // scene_builder.add_mod(scenes::fixed::scene_4);
 
+    // open for half of each frame, moving instances and cameras blur
+    // scene_builder.set_shutter(scene::Shutter::new(0.0, 0.5));
+
     ////////    STANDALONE ANIMATIONS    ////////
     // scene_builder.add_proc_mod(scenes::animated::interweaved_xbox_spinny);
     // scene_builder.add_proc_mod(scenes::animated::wave_sheet);
+    // scene_builder.add_proc_mod(scenes::animated::duck_carousel);
 
     //////////////////////// CAMERA ZONE ////////////////////////
     scene_builder.add_proc_mod(scenes::animated::orbit_camera);
+    // scene_builder.add_proc_mod(scenes::animated::time_of_day);
     // scene_builder.add_proc_mod(scenes::animated::pidgeon_camera);
 
     let time = std::time::Instant::now();
@@ -81,8 +125,12 @@ fn main() {
 
     let rng_seed = [0u8; 32];
 
-    // generate_image(resolution, samps, rng_seed, &scene_builder);
-    generate::generate_animation(resolution, 240, samps, rng_seed, &scene_builder);
+    // let result = generate::generate_image(resolution, samps, rng_seed, &scene_builder);
+    let result = generate::generate_animation(resolution, 240, samps, rng_seed, &scene_builder);
 
     println!("Time elapsed: {:?}", time.elapsed());
+    if let Err(error) = result {
+        eprintln!("error: {error}");
+        std::process::exit(1);
+    }
 }
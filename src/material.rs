@@ -1,23 +1,35 @@
-use image::{DynamicImage, GenericImageView};
 use std::sync::Arc;
 
 use glam::{Vec2, Vec3};
 
-pub trait Material: Sync {
-    fn color_at(&self, uv: &Vec2) -> Vec3;
-    fn ambient_at(&self, uv: &Vec2) -> f32;
-    fn diffuse_at(&self, uv: &Vec2) -> f32;
-    fn specular_at(&self, uv: &Vec2) -> f32;
-    fn reflection_at(&self, uv: &Vec2) -> f32;
-    fn roughness_at(&self, uv: &Vec2) -> f32;
-    fn refraction_at(&self, uv: &Vec2) -> f32;
-    fn refractive_index_at(&self, uv: &Vec2) -> f32;
-    fn normal_at(&self, uv: &Vec2) -> Vec3 {
+use crate::{
+    error::Result,
+    procedural::ProceduralTexture,
+    structures::SurfacePoint,
+    texture::{AddressMode, FilterMode, Texture},
+    texture_cache::{TextureCache, TextureUsage},
+    texture_input::TextureInput,
+};
+
+pub trait Material: Send + Sync {
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3;
+    fn ambient_at(&self, sp: &SurfacePoint) -> f32;
+    fn diffuse_at(&self, sp: &SurfacePoint) -> f32;
+    fn specular_at(&self, sp: &SurfacePoint) -> f32;
+    fn reflection_at(&self, sp: &SurfacePoint) -> f32;
+    fn roughness_at(&self, sp: &SurfacePoint) -> f32;
+    fn refraction_at(&self, sp: &SurfacePoint) -> f32;
+    fn refractive_index_at(&self, sp: &SurfacePoint) -> f32;
+    fn normal_at(&self, _sp: &SurfacePoint) -> Vec3 {
         Vec3::ZERO
     }
     fn normal_map_magnitude_multiplier(&self) -> f32 {
         0.0
     }
+    // strands are lit along their tangent instead of around a normal
+    fn hair(&self) -> Option<&HairMaterial> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -33,6 +45,7 @@ pub struct BasicMaterial {
 }
 
 impl BasicMaterial {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         color: Vec3,
         ambient: f32,
@@ -129,35 +142,35 @@ impl BasicMaterialBuilder {
 }
 
 impl Material for BasicMaterial {
-    fn color_at(&self, _uv: &Vec2) -> Vec3 {
+    fn color_at(&self, _sp: &SurfacePoint) -> Vec3 {
         self.color
     }
 
-    fn ambient_at(&self, _uv: &Vec2) -> f32 {
+    fn ambient_at(&self, _sp: &SurfacePoint) -> f32 {
         self.ambient
     }
 
-    fn diffuse_at(&self, _uv: &Vec2) -> f32 {
+    fn diffuse_at(&self, _sp: &SurfacePoint) -> f32 {
         self.diffuse
     }
 
-    fn specular_at(&self, _uv: &Vec2) -> f32 {
+    fn specular_at(&self, _sp: &SurfacePoint) -> f32 {
         self.specular
     }
 
-    fn reflection_at(&self, _uv: &Vec2) -> f32 {
+    fn reflection_at(&self, _sp: &SurfacePoint) -> f32 {
         self.reflection
     }
 
-    fn roughness_at(&self, _uv: &Vec2) -> f32 {
+    fn roughness_at(&self, _sp: &SurfacePoint) -> f32 {
         self.roughness
     }
 
-    fn refraction_at(&self, _uv: &Vec2) -> f32 {
+    fn refraction_at(&self, _sp: &SurfacePoint) -> f32 {
         self.refraction
     }
 
-    fn refractive_index_at(&self, _uv: &Vec2) -> f32 {
+    fn refractive_index_at(&self, _sp: &SurfacePoint) -> f32 {
         self.refractive_index
     }
 }
@@ -167,7 +180,7 @@ pub struct CheckerMaterial {
     pub color1: Vec3,
     pub color2: Vec3,
     pub scale: f32,
-    pub basic_material: BasicMaterial,
+    pub channels: GraphMaterial,
 }
 
 impl CheckerMaterial {
@@ -175,13 +188,13 @@ impl CheckerMaterial {
         color1: Vec3,
         color2: Vec3,
         scale: f32,
-        basic_material: BasicMaterial,
+        channels: impl Into<GraphMaterial>,
     ) -> CheckerMaterial {
         CheckerMaterial {
             color1,
             color2,
             scale,
-            basic_material,
+            channels: channels.into(),
         }
     }
 
@@ -197,196 +210,363 @@ impl CheckerMaterial {
 }
 
 impl Material for CheckerMaterial {
-    fn color_at(&self, uv: &Vec2) -> Vec3 {
-        self.checker_at(uv)
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.checker_at(&sp.uv)
     }
 
-    fn ambient_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.ambient_at(uv)
+    fn ambient_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.ambient_at(sp)
     }
 
-    fn diffuse_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.diffuse_at(uv)
+    fn diffuse_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.diffuse_at(sp)
     }
 
-    fn specular_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.specular_at(uv)
+    fn specular_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.specular_at(sp)
     }
 
-    fn reflection_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.reflection_at(uv)
+    fn reflection_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.reflection_at(sp)
     }
 
-    fn roughness_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.roughness_at(uv)
+    fn roughness_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.roughness_at(sp)
     }
 
-    fn refraction_at(&self, _uv: &Vec2) -> f32 {
-        self.basic_material.refraction
+    fn refraction_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refraction_at(sp)
     }
 
-    fn refractive_index_at(&self, _uv: &Vec2) -> f32 {
-        self.basic_material.refractive_index
+    fn refractive_index_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refractive_index_at(sp)
     }
 }
 
-pub fn sample_texture(
-    uv: &Vec2,
-    texture: &Texture,
-    scale: Vec2,
-    wrap: bool,
-    fallback_material: &BasicMaterial,
-) -> Vec3 {
-    // Scale the UV coordinates
-    let scaled_uv = Vec2::new(uv.x / scale.x, uv.y / scale.y);
+// color from a procedural pattern, blended between two colors. the other
+// channels are a GraphMaterial's, so they can follow patterns too
+#[derive(Clone)]
+pub struct ProceduralMaterial {
+    pub texture: ProceduralTexture,
+    pub color1: Vec3,
+    pub color2: Vec3,
+    pub channels: GraphMaterial,
+}
+
+impl ProceduralMaterial {
+    pub fn new(
+        texture: ProceduralTexture,
+        color1: Vec3,
+        color2: Vec3,
+        channels: impl Into<GraphMaterial>,
+    ) -> ProceduralMaterial {
+        ProceduralMaterial {
+            texture,
+            color1,
+            color2,
+            channels: channels.into(),
+        }
+    }
+}
 
-    // Apply wrapping by using modulo operation
-    // Check if UV is out of bounds and wrap is false
-    if !wrap && (scaled_uv.x < 0.0 || scaled_uv.x > 1.0 || scaled_uv.y < 0.0 || scaled_uv.y > 1.0) {
-        return fallback_material.color_at(uv);
+impl Material for ProceduralMaterial {
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.color1.lerp(self.color2, self.texture.value_at(sp))
     }
 
-    let (width, height) = (texture.width, texture.height);
-    let x = ((scaled_uv.x * width as f32).rem_euclid(width as f32)) as u32;
-    let y = ((scaled_uv.y * height as f32).rem_euclid(height as f32)) as u32;
+    fn ambient_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.ambient_at(sp)
+    }
 
-    let pixel = texture.get_pixel(x, y);
-    // reconsider the value scales here
-    Vec3::new(pixel[0], pixel[1], pixel[2])
+    fn diffuse_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.diffuse_at(sp)
+    }
+
+    fn specular_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.specular_at(sp)
+    }
+
+    fn reflection_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.reflection_at(sp)
+    }
+
+    fn roughness_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.roughness_at(sp)
+    }
+
+    fn refraction_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refraction_at(sp)
+    }
+
+    fn refractive_index_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refractive_index_at(sp)
+    }
+
+    fn normal_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.channels.normal_at(sp)
+    }
+
+    fn normal_map_magnitude_multiplier(&self) -> f32 {
+        self.channels.normal_map_magnitude_multiplier()
+    }
 }
 
+// every channel is a texture graph, so one material can mix
+// e.g. an image color, a procedural roughness map and a reflectivity mask
 #[derive(Clone)]
-pub struct TexturedMaterial {
-    texture: Arc<Texture>,
-    scale: Vec2,
-    wrap: bool,
-    basic_material: BasicMaterial,
+pub struct GraphMaterial {
+    pub color: TextureInput,
+    pub ambient: TextureInput,
+    pub diffuse: TextureInput,
+    pub specular: TextureInput,
+    pub reflection: TextureInput,
+    pub roughness: TextureInput,
+    pub refraction: TextureInput,
+    pub refractive_index: TextureInput,
+    // tangent space normal in [-1, 1]
+    pub normal: Option<TextureInput>,
+    // scalar height, only used when there is no normal map
+    pub bump: Option<TextureInput>,
+    pub normal_strength: f32,
 }
 
-impl TexturedMaterial {
-    pub fn new(
-        texture_path: &str,
-        scale: Vec2,
-        wrap: bool,
-        basic_material: BasicMaterial,
-    ) -> TexturedMaterial {
-        let dimage = image::open(texture_path).expect("Failed to load texture");
-        let texture = Texture::from_image(&dimage);
-
-        TexturedMaterial {
-            texture: Arc::new(texture),
-            scale,
-            wrap,
-            basic_material,
+impl GraphMaterial {
+    pub fn builder() -> GraphMaterialBuilder {
+        GraphMaterialBuilder::default()
+    }
+}
+
+impl From<BasicMaterial> for GraphMaterial {
+    fn from(basic: BasicMaterial) -> Self {
+        GraphMaterial {
+            color: basic.color.into(),
+            ambient: basic.ambient.into(),
+            diffuse: basic.diffuse.into(),
+            specular: basic.specular.into(),
+            reflection: basic.reflection.into(),
+            roughness: basic.roughness.into(),
+            refraction: basic.refraction.into(),
+            refractive_index: basic.refractive_index.into(),
+            normal: None,
+            bump: None,
+            normal_strength: 0.0,
         }
     }
+}
 
-    // fn color_at(&self, uv: &Vec2) -> Vec3 {
-    //     let (width, height) = self.texture.dimensions();
-    //     let x = (uv.x.clamp(0.0, 1.0) * width as f32) as u32;
-    //     let y = (uv.y.clamp(0.0, 1.0) * height as f32) as u32;
+// builder pattern for GraphMaterial, unset channels match BasicMaterial's defaults
+pub struct GraphMaterialBuilder {
+    material: GraphMaterial,
+}
 
-    //     let pixel = self.texture.get_pixel(x, y);
-    //     Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32)
-    // }
+impl Default for GraphMaterialBuilder {
+    fn default() -> Self {
+        GraphMaterialBuilder {
+            material: BasicMaterial::builder().build().into(),
+        }
+    }
+}
+
+impl GraphMaterialBuilder {
+    pub fn color(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.color = input.into();
+        self
+    }
+
+    pub fn ambient(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.ambient = input.into();
+        self
+    }
+
+    pub fn diffuse(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.diffuse = input.into();
+        self
+    }
 
-    fn color_at(&self, uv: &Vec2) -> Vec3 {
-        sample_texture(
-            uv,
-            &self.texture,
-            self.scale,
-            self.wrap,
-            &self.basic_material,
-        )
+    pub fn specular(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.specular = input.into();
+        self
+    }
+
+    pub fn reflection(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.reflection = input.into();
+        self
+    }
+
+    pub fn roughness(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.roughness = input.into();
+        self
+    }
+
+    pub fn refraction(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.refraction = input.into();
+        self
+    }
+
+    pub fn refractive_index(mut self, input: impl Into<TextureInput>) -> Self {
+        self.material.refractive_index = input.into();
+        self
+    }
+
+    pub fn normal(mut self, input: impl Into<TextureInput>, strength: f32) -> Self {
+        self.material.normal = Some(input.into());
+        self.material.normal_strength = strength;
+        self
+    }
+
+    pub fn bump(mut self, input: impl Into<TextureInput>, strength: f32) -> Self {
+        self.material.bump = Some(input.into());
+        self.material.normal_strength = strength;
+        self
+    }
+
+    pub fn build(self) -> GraphMaterial {
+        self.material
     }
 }
 
-impl Material for TexturedMaterial {
-    fn color_at(&self, uv: &Vec2) -> Vec3 {
-        self.color_at(uv)
+impl Material for GraphMaterial {
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.color.eval(sp)
     }
 
-    // default to basic_material for other unsampled material properties
-    fn ambient_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.ambient_at(uv)
+    fn ambient_at(&self, sp: &SurfacePoint) -> f32 {
+        self.ambient.value(sp)
     }
 
-    fn diffuse_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.diffuse_at(uv)
+    fn diffuse_at(&self, sp: &SurfacePoint) -> f32 {
+        self.diffuse.value(sp)
     }
 
-    fn specular_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.specular_at(uv)
+    fn specular_at(&self, sp: &SurfacePoint) -> f32 {
+        self.specular.value(sp)
     }
 
-    fn reflection_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.reflection_at(uv)
+    fn reflection_at(&self, sp: &SurfacePoint) -> f32 {
+        self.reflection.value(sp)
     }
 
-    fn roughness_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.roughness_at(uv)
+    fn roughness_at(&self, sp: &SurfacePoint) -> f32 {
+        self.roughness.value(sp)
     }
 
-    fn refraction_at(&self, _uv: &Vec2) -> f32 {
-        self.basic_material.refraction
+    fn refraction_at(&self, sp: &SurfacePoint) -> f32 {
+        self.refraction.value(sp)
     }
 
-    fn refractive_index_at(&self, _uv: &Vec2) -> f32 {
-        self.basic_material.refractive_index
+    fn refractive_index_at(&self, sp: &SurfacePoint) -> f32 {
+        self.refractive_index.value(sp)
     }
+
+    fn normal_at(&self, sp: &SurfacePoint) -> Vec3 {
+        match (&self.normal, &self.bump) {
+            (Some(normal), _) => normal.eval(sp),
+            (None, Some(bump)) => bump.bump_normal(sp),
+            (None, None) => Vec3::Z,
+        }
+    }
+
+    fn normal_map_magnitude_multiplier(&self) -> f32 {
+        if self.normal.is_some() || self.bump.is_some() {
+            self.normal_strength
+        } else {
+            0.0
+        }
+    }
+}
+
+pub fn sample_texture(
+    sp: &SurfacePoint,
+    texture: &Texture,
+    scale: Vec2,
+    address: AddressMode,
+    filter: FilterMode,
+) -> Vec3 {
+    // Scale the UV coordinates, and the footprint along with them
+    let scaled_uv = sp.uv / scale;
+    let duv_dx = sp.duv_dx / scale;
+    let duv_dy = sp.duv_dy / scale;
+
+    // reconsider the value scales here
+    texture.filter(scaled_uv, duv_dx, duv_dy, address, filter)
 }
 
 #[derive(Clone)]
-pub struct Texture {
-    width: u32,
-    height: u32,
-    data: Vec<Vec3>, // Store normalized normals as Vec3 (assuming Vec3 is a struct for a 3D vector)
+pub struct TexturedMaterial {
+    texture: Arc<Texture>,
+    scale: Vec2,
+    address: AddressMode,
+    filter: FilterMode,
+    channels: GraphMaterial,
 }
 
-impl Texture {
-    pub fn new(width: u32, height: u32, data: Vec<Vec3>) -> Texture {
-        Texture {
-            width,
-            height,
-            data,
-        }
+impl TexturedMaterial {
+    pub fn new(
+        texture_path: &str,
+        scale: Vec2,
+        address: AddressMode,
+        channels: impl Into<GraphMaterial>,
+    ) -> Result<TexturedMaterial> {
+        Ok(TexturedMaterial {
+            texture: TextureCache::global().load(texture_path, TextureUsage::Color)?,
+            scale,
+            address,
+            filter: FilterMode::Trilinear,
+            channels: channels.into(),
+        })
     }
 
-    pub fn from_image(image: &DynamicImage) -> Texture {
-        let (width, height) = image.dimensions();
-        let mut data = Vec::new();
-        for (_, _, pixel) in image.to_rgb8().enumerate_pixels() {
-            data.push(Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32));
-        }
+    pub fn filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
 
-        Texture {
-            width,
-            height,
-            data,
-        }
+    // fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+    //     let (width, height) = self.texture.dimensions();
+    //     let x = (uv.x.clamp(0.0, 1.0) * width as f32) as u32;
+    //     let y = (uv.y.clamp(0.0, 1.0) * height as f32) as u32;
+
+    //     let pixel = self.texture.get_pixel(x, y);
+    //     Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32)
+    // }
+
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        sample_texture(sp, &self.texture, self.scale, self.address, self.filter)
     }
+}
 
-    pub fn remap(&mut self, current_min: f32, current_max: f32, new_min: f32, new_max: f32) {
-        for pixel in &mut self.data {
-            pixel[0] = (pixel[0] - current_min) / (current_max - current_min) * (new_max - new_min)
-                + new_min;
-            pixel[1] = (pixel[1] - current_min) / (current_max - current_min) * (new_max - new_min)
-                + new_min;
-            pixel[2] = (pixel[2] - current_min) / (current_max - current_min) * (new_max - new_min)
-                + new_min;
-        }
+impl Material for TexturedMaterial {
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.color_at(sp)
     }
 
-    pub fn normalize_from_255(&mut self) {
-        self.remap(0.0, 255.0, 0.0, 1.0);
+    // every other channel comes from channels, constant or not
+    fn ambient_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.ambient_at(sp)
     }
 
-    pub fn normalize_from_255_to_full_range(&mut self) {
-        self.remap(0.0, 255.0, -1.0, 1.0)
+    fn diffuse_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.diffuse_at(sp)
     }
 
-    pub fn get_pixel(&self, x: u32, y: u32) -> Vec3 {
-        self.data[(y * self.width + x) as usize]
+    fn specular_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.specular_at(sp)
+    }
+
+    fn reflection_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.reflection_at(sp)
+    }
+
+    fn roughness_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.roughness_at(sp)
+    }
+
+    fn refraction_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refraction_at(sp)
+    }
+
+    fn refractive_index_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refractive_index_at(sp)
     }
 }
 
@@ -395,9 +575,10 @@ pub struct TexturedMaterialWithNormal {
     texture: Arc<Texture>,
     normal_map: Arc<Texture>,
     scale: Vec2,
-    wrap: bool,
+    address: AddressMode,
+    filter: FilterMode,
     normal_map_magnitude_multiplier: f32,
-    basic_material: BasicMaterial,
+    channels: GraphMaterial,
 }
 
 impl TexturedMaterialWithNormal {
@@ -405,30 +586,28 @@ impl TexturedMaterialWithNormal {
         texture_path: &str,
         normal_map_path: &str,
         scale: Vec2,
-        wrap: bool,
+        address: AddressMode,
         normal_map_magnitude_multiplier: f32,
-        basic_material: BasicMaterial,
-    ) -> TexturedMaterialWithNormal {
-        let dimage = image::open(texture_path).expect("Failed to load texture");
-        let texture = Texture::from_image(&dimage);
-
-        let normal_map_image = image::open(normal_map_path).expect("Failed to load normal map");
-
-        // Construct the normal map
-        let mut normal_map = Texture::from_image(&normal_map_image);
-        normal_map.normalize_from_255_to_full_range();
-
-        TexturedMaterialWithNormal {
-            texture: Arc::new(texture),
-            normal_map: Arc::new(normal_map),
+        channels: impl Into<GraphMaterial>,
+    ) -> Result<TexturedMaterialWithNormal> {
+        let cache = TextureCache::global();
+        Ok(TexturedMaterialWithNormal {
+            texture: cache.load(texture_path, TextureUsage::Color)?,
+            normal_map: cache.load(normal_map_path, TextureUsage::Normal)?,
             scale,
-            wrap,
+            address,
+            filter: FilterMode::Trilinear,
             normal_map_magnitude_multiplier,
-            basic_material,
-        }
+            channels: channels.into(),
+        })
+    }
+
+    pub fn filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
     }
 
-    // fn color_at(&self, uv: &Vec2) -> Vec3 {
+    // fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
     //     let (width, height) = self.texture.dimensions();
     //     let x = (uv.x.clamp(0.0, 1.0) * width as f32) as u32;
     //     let y = (uv.y.clamp(0.0, 1.0) * height as f32) as u32;
@@ -437,66 +616,182 @@ impl TexturedMaterialWithNormal {
     //     Vec3::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32)
     // }
 
-    fn color_at(&self, uv: &Vec2) -> Vec3 {
-        sample_texture(
-            uv,
-            &self.texture,
-            self.scale,
-            self.wrap,
-            &self.basic_material,
-        )
-    }
-
-    fn normal_at(&self, uv: &Vec2) -> Vec3 {
-        sample_texture(
-            uv,
-            &self.normal_map,
-            self.scale,
-            self.wrap,
-            &self.basic_material,
-        )
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        sample_texture(sp, &self.texture, self.scale, self.address, self.filter)
+    }
+
+    fn normal_at(&self, sp: &SurfacePoint) -> Vec3 {
+        sample_texture(sp, &self.normal_map, self.scale, self.address, self.filter)
     }
 }
 
 impl Material for TexturedMaterialWithNormal {
-    fn color_at(&self, uv: &Vec2) -> Vec3 {
-        self.color_at(uv)
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.color_at(sp)
     }
 
-    // default to basic_material for other unsampled material properties
-    fn ambient_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.ambient_at(uv)
+    // every other channel comes from channels, constant or not
+    fn ambient_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.ambient_at(sp)
     }
 
-    fn diffuse_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.diffuse_at(uv)
+    fn diffuse_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.diffuse_at(sp)
     }
 
-    fn specular_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.specular_at(uv)
+    fn specular_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.specular_at(sp)
     }
 
-    fn reflection_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.reflection_at(uv)
+    fn reflection_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.reflection_at(sp)
     }
 
-    fn roughness_at(&self, uv: &Vec2) -> f32 {
-        self.basic_material.roughness_at(uv)
+    fn roughness_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.roughness_at(sp)
     }
 
-    fn refraction_at(&self, _uv: &Vec2) -> f32 {
-        self.basic_material.refraction
+    fn refraction_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refraction_at(sp)
     }
 
-    fn refractive_index_at(&self, _uv: &Vec2) -> f32 {
-        self.basic_material.refractive_index
+    fn refractive_index_at(&self, sp: &SurfacePoint) -> f32 {
+        self.channels.refractive_index_at(sp)
     }
 
-    fn normal_at(&self, uv: &Vec2) -> Vec3 {
-        self.normal_at(uv)
+    fn normal_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.normal_at(sp)
     }
 
     fn normal_map_magnitude_multiplier(&self) -> f32 {
         self.normal_map_magnitude_multiplier
     }
 }
+
+// a simple hair bsdf for Curves. diffuse is Kajiya-Kay's, brightest with the
+// light square to the strand. specular is two lobes around the cone of mirror
+// directions about the strand: a white one off the cuticle, tilted towards the
+// tip by shift, and one tinted by the fibre it went through and back out of,
+// tilted 1.5x as far the other way and twice as wide. both fade as light and
+// camera move apart around the strand, as Marschner's reflection does. the
+// tangent is the shape's dpdu, which for Curves runs along the strand
+#[derive(Clone)]
+pub struct HairMaterial {
+    pub color: TextureInput,
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+    pub secondary: f32,
+    // cuticle scale tilt in radians, a few degrees for human hair
+    pub shift: f32,
+    // width of the specular lobes in radians
+    pub roughness: f32,
+}
+
+impl HairMaterial {
+    pub fn new(color: impl Into<TextureInput>) -> HairMaterial {
+        HairMaterial {
+            color: color.into(),
+            ambient: 0.05,
+            diffuse: 0.4,
+            specular: 0.2,
+            secondary: 0.25,
+            shift: 3f32.to_radians(),
+            roughness: 8f32.to_radians(),
+        }
+    }
+
+    pub fn ambient(mut self, ambient: f32) -> Self {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn diffuse(mut self, diffuse: f32) -> Self {
+        self.diffuse = diffuse;
+        self
+    }
+
+    pub fn specular(mut self, specular: f32) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn secondary(mut self, secondary: f32) -> Self {
+        self.secondary = secondary;
+        self
+    }
+
+    pub fn shift(mut self, shift: f32) -> Self {
+        self.shift = shift;
+        self
+    }
+
+    pub fn roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    // Kajiya-Kay's diffuse falloff, the sine between strand and light
+    pub fn diffuse_falloff(tangent: Vec3, to_light: Vec3) -> f32 {
+        (1.0 - tangent.dot(to_light).powi(2)).max(0.0).sqrt()
+    }
+
+    // what a light of unit strength from to_light sends to_cam, in the same
+    // 0 to 255 terms as the renderer's other diffuse and specular terms
+    pub fn evaluate(&self, color: Vec3, tangent: Vec3, to_light: Vec3, to_cam: Vec3) -> Vec3 {
+        // longitudinal angles, measured from the plane square to the strand
+        let theta_i = tangent.dot(to_light).clamp(-1.0, 1.0).asin();
+        let theta_o = tangent.dot(to_cam).clamp(-1.0, 1.0).asin();
+        let theta_h = (theta_i + theta_o) * 0.5;
+        let lobe =
+            |offset: f32, width: f32| (-(theta_h - offset).powi(2) / (2.0 * width * width)).exp();
+
+        // azimuth between light and camera around the strand
+        let across = |dir: Vec3| (dir - tangent * tangent.dot(dir)).normalize_or_zero();
+        let cos_phi = across(to_light).dot(across(to_cam)).clamp(-1.0, 1.0);
+        let azimuthal = ((1.0 + cos_phi) * 0.5).sqrt();
+
+        let primary = self.specular * azimuthal * lobe(-self.shift, self.roughness);
+        let secondary = self.secondary * azimuthal * lobe(1.5 * self.shift, 2.0 * self.roughness);
+        let diffuse = self.diffuse * HairMaterial::diffuse_falloff(tangent, to_light);
+        color / 255.0 * (diffuse + secondary) + Vec3::splat(primary)
+    }
+}
+
+impl Material for HairMaterial {
+    fn color_at(&self, sp: &SurfacePoint) -> Vec3 {
+        self.color.eval(sp)
+    }
+
+    fn ambient_at(&self, _sp: &SurfacePoint) -> f32 {
+        self.ambient
+    }
+
+    fn diffuse_at(&self, _sp: &SurfacePoint) -> f32 {
+        self.diffuse
+    }
+
+    fn specular_at(&self, _sp: &SurfacePoint) -> f32 {
+        self.specular
+    }
+
+    fn reflection_at(&self, _sp: &SurfacePoint) -> f32 {
+        0.0
+    }
+
+    fn roughness_at(&self, _sp: &SurfacePoint) -> f32 {
+        self.roughness
+    }
+
+    fn refraction_at(&self, _sp: &SurfacePoint) -> f32 {
+        0.0
+    }
+
+    fn refractive_index_at(&self, _sp: &SurfacePoint) -> f32 {
+        1.55
+    }
+
+    fn hair(&self) -> Option<&HairMaterial> {
+        Some(self)
+    }
+}
@@ -0,0 +1,580 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use glam::{Vec2, Vec3};
+use rayon::prelude::*;
+
+use crate::{
+    bvh::Aabb,
+    error::Error,
+    ply::{load_ply, Ply},
+    structures::SurfacePoint,
+    texture_input::TextureInput,
+    utils::perpendicular_to,
+};
+
+// indexed triangle soup, one normal and uv per vertex
+#[derive(Clone, Default, PartialEq)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    // 0-255 per vertex like every other color, empty when the file has none
+    pub colors: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    // obj, ply or stl by the file's extension
+    pub fn load(filename: &str) -> Result<Mesh, Error> {
+        let extension = std::path::Path::new(filename)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("ply") => match load_ply(filename)? {
+                Ply::Mesh(mesh) => Ok(mesh),
+                Ply::Points(_) => Err(Error::parse(
+                    filename,
+                    "has no faces, load it as a PointCloud",
+                )),
+            },
+            Some("stl") => Mesh::load_stl(filename),
+            _ => Mesh::load_obj(filename),
+        }
+    }
+
+    // all the models in the file merged into one mesh
+    pub fn load_obj(filename: &str) -> Result<Mesh, Error> {
+        // single index so positions, uvs and normals line up per vertex,
+        // uv seams split vertices just like mikktspace wants
+        let options = tobj::LoadOptions {
+            triangulate: false,
+            single_index: true,
+            ..Default::default()
+        };
+
+        // opened here so a missing file says why, the tracer has its own
+        // materials so mtl files aren't read
+        let file = std::fs::File::open(filename).map_err(|error| Error::io(filename, error))?;
+        let (models, _materials) =
+            tobj::load_obj_buf(&mut std::io::BufReader::new(file), &options, |_| {
+                Ok(Default::default())
+            })
+            .map_err(|source| Error::Obj {
+                path: filename.into(),
+                source,
+            })?;
+
+        let mut mesh = Mesh::default();
+        let mut missing_normals = false;
+        for m in models.iter() {
+            let obj = &m.mesh;
+            let offset = mesh.positions.len() as u32;
+            let num_vertices = obj.positions.len() / 3;
+
+            mesh.positions.extend(
+                obj.positions
+                    .chunks_exact(3)
+                    .map(|v| Vec3::new(v[0], v[1], v[2])),
+            );
+
+            // obj v runs bottom to top, images top to bottom
+            if obj.texcoords.len() / 2 == num_vertices {
+                mesh.uvs.extend(
+                    obj.texcoords
+                        .chunks_exact(2)
+                        .map(|t| Vec2::new(t[0], 1.0 - t[1])),
+                );
+            } else {
+                mesh.uvs
+                    .extend(std::iter::repeat_n(Vec2::ZERO, num_vertices));
+            }
+
+            if obj.normals.len() / 3 == num_vertices {
+                mesh.normals.extend(
+                    obj.normals
+                        .chunks_exact(3)
+                        .map(|n| Vec3::new(n[0], n[1], n[2]).normalize_or_zero()),
+                );
+            } else {
+                missing_normals = true;
+                mesh.normals
+                    .extend(std::iter::repeat_n(Vec3::ZERO, num_vertices));
+            }
+
+            mesh.indices.extend(obj.indices.iter().map(|i| i + offset));
+        }
+
+        if missing_normals {
+            mesh.recompute_normals();
+        }
+        Ok(mesh)
+    }
+
+    // ascii or binary stl. stl has no shared vertices, every triangle gets its own
+    // three with the facet normal, so it shades flat until welded
+    pub fn load_stl(filename: &str) -> Result<Mesh, Error> {
+        let data = std::fs::read(filename).map_err(|error| Error::io(filename, error))?;
+        let triangles = parse_stl(&data).map_err(|reason| Error::parse(filename, reason))?;
+
+        let mut mesh = Mesh::default();
+        for (normal, [a, b, c]) in triangles {
+            // some exporters leave the normal zero, the winding still says which way is out
+            let normal = if normal.length_squared() > 0.0 {
+                normal.normalize()
+            } else {
+                (b - a).cross(c - a).normalize_or_zero()
+            };
+            mesh.indices
+                .extend((0..3).map(|i| mesh.positions.len() as u32 + i));
+            mesh.positions.extend_from_slice(&[a, b, c]);
+            mesh.normals.extend_from_slice(&[normal; 3]);
+        }
+        mesh.uvs = vec![Vec2::ZERO; mesh.positions.len()];
+        Ok(mesh)
+    }
+
+    // binary stl, the inverse of load_stl. uvs, colors and vertex normals are
+    // lost, each facet gets the normal of its winding
+    pub fn write_stl(&self, filename: &str) -> Result<(), Error> {
+        let mut data = vec![0; 80];
+        data.extend_from_slice(&(self.num_tris() as u32).to_le_bytes());
+        for face in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.positions[face[i] as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for v in [normal, a, b, c] {
+                for x in v.to_array() {
+                    data.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            data.extend_from_slice(&[0, 0]);
+        }
+        std::fs::write(filename, data).map_err(|error| Error::io(filename, error))
+    }
+
+    // a flat quad with the same arguments and uvs as shapes::Quad, ready to be subdivided
+    pub fn quad(point: Vec3, normal: Vec3, edge1: Vec3, edge2: Vec3) -> Mesh {
+        let normal = normal.normalize();
+        // wind the triangles so recomputed normals agree with the given one
+        let indices = if edge1.cross(edge2).dot(normal) >= 0.0 {
+            vec![0, 1, 2, 2, 1, 3]
+        } else {
+            vec![0, 2, 1, 1, 2, 3]
+        };
+        Mesh {
+            positions: vec![point, point + edge1, point + edge2, point + edge1 + edge2],
+            normals: vec![normal; 4],
+            uvs: vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(1.0, 0.0),
+                Vec2::new(0.0, 1.0),
+                Vec2::new(1.0, 1.0),
+            ],
+            colors: Vec::new(),
+            indices,
+        }
+    }
+
+    // a unit sphere around the origin, u around the equator and v from the top
+    // pole down. the seam and the poles repeat their vertices so the uvs wrap
+    pub fn uv_sphere(segments: u32, rings: u32) -> Mesh {
+        let mut mesh = Mesh::default();
+        for ring in 0..=rings {
+            let v = ring as f32 / rings as f32;
+            let (sin_theta, cos_theta) = (v * PI).sin_cos();
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let (sin_phi, cos_phi) = (u * 2.0 * PI).sin_cos();
+                let normal = Vec3::new(sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi);
+                mesh.positions.push(normal);
+                mesh.normals.push(normal);
+                mesh.uvs.push(Vec2::new(u, v));
+            }
+        }
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = ring * (segments + 1) + segment;
+                let b = a + segments + 1;
+                // the triangle that would collapse onto a pole is left out
+                if ring > 0 {
+                    mesh.indices.extend_from_slice(&[a, b, a + 1]);
+                }
+                if ring < rings - 1 {
+                    mesh.indices.extend_from_slice(&[a + 1, b, b + 1]);
+                }
+            }
+        }
+        mesh
+    }
+
+    pub fn num_tris(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    // scale then move, normals get the inverse scale so they stay perpendicular
+    pub fn transform(mut self, p: Vec3, scale: Vec3) -> Mesh {
+        for position in self.positions.iter_mut() {
+            *position = *position * scale + p;
+        }
+        for normal in self.normals.iter_mut() {
+            *normal = (*normal / scale).normalize_or_zero();
+        }
+        self
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.positions)
+    }
+
+    // centered on the origin and scaled evenly so the longest side is 1, then
+    // transform places it with scale as its size
+    pub fn normalized(self) -> Mesh {
+        let bounds = self.bounds();
+        let longest = (bounds.max - bounds.min).max_element();
+        if bounds.is_empty() || longest <= 0.0 {
+            return self;
+        }
+        self.transform(-bounds.center() / longest, Vec3::splat(1.0 / longest))
+    }
+
+    // vertices that match in position, uv and color become one, so faces that
+    // only touched now share them. their normals are averaged, which smooths
+    // over the creases a flat shaded mesh like an stl had
+    pub fn welded(&self) -> Mesh {
+        let mut mesh = Mesh::default();
+        let mut vertices: HashMap<Vec<u32>, u32> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.positions.len());
+        for i in 0..self.positions.len() {
+            let mut key: Vec<u32> = self.positions[i].to_array().map(f32::to_bits).to_vec();
+            key.extend(self.uvs[i].to_array().map(f32::to_bits));
+            if let Some(color) = self.colors.get(i) {
+                key.extend(color.to_array().map(f32::to_bits));
+            }
+            let index = *vertices.entry(key).or_insert_with(|| {
+                mesh.positions.push(self.positions[i]);
+                mesh.normals.push(Vec3::ZERO);
+                mesh.uvs.push(self.uvs[i]);
+                if let Some(color) = self.colors.get(i) {
+                    mesh.colors.push(*color);
+                }
+                mesh.positions.len() as u32 - 1
+            });
+            mesh.normals[index as usize] += self.normals[i];
+            remap.push(index);
+        }
+        for normal in mesh.normals.iter_mut() {
+            *normal = normal.normalize_or_zero();
+        }
+        mesh.indices = self.indices.iter().map(|&i| remap[i as usize]).collect();
+        mesh
+    }
+
+    // inside out, for models whose faces wind the wrong way round
+    pub fn flipped(mut self) -> Mesh {
+        for face in self.indices.chunks_exact_mut(3) {
+            face.swap(1, 2);
+        }
+        for normal in self.normals.iter_mut() {
+            *normal = -*normal;
+        }
+        self
+    }
+
+    // area weighted vertex normals from the faces
+    pub fn recompute_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+        for face in self.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0] as usize, face[1] as usize, face[2] as usize];
+            let n = (self.positions[b] - self.positions[a])
+                .cross(self.positions[c] - self.positions[a]);
+            normals[a] += n;
+            normals[b] += n;
+            normals[c] += n;
+        }
+        self.normals = normals.iter().map(|n| n.normalize_or_zero()).collect();
+    }
+
+    // splits every triangle into four at its edge midpoints.
+    // shared edges share their midpoint, so the mesh stays watertight
+    pub fn subdivide(&self) -> Mesh {
+        let mut mesh = self.clone();
+        mesh.indices = Vec::with_capacity(self.indices.len() * 4);
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+
+        let mut midpoint = |mesh: &mut Mesh, a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let (a, b) = (a as usize, b as usize);
+                mesh.positions
+                    .push((mesh.positions[a] + mesh.positions[b]) * 0.5);
+                mesh.normals
+                    .push((mesh.normals[a] + mesh.normals[b]).normalize_or_zero());
+                mesh.uvs.push((mesh.uvs[a] + mesh.uvs[b]) * 0.5);
+                if !mesh.colors.is_empty() {
+                    mesh.colors.push((mesh.colors[a] + mesh.colors[b]) * 0.5);
+                }
+                mesh.positions.len() as u32 - 1
+            })
+        };
+
+        for face in self.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]];
+            let ab = midpoint(&mut mesh, a, b);
+            let bc = midpoint(&mut mesh, b, c);
+            let ca = midpoint(&mut mesh, c, a);
+            mesh.indices
+                .extend_from_slice(&[a, ab, ca, ab, b, bc, ca, bc, c, ab, bc, ca]);
+        }
+        mesh
+    }
+
+    // vertices split along a uv seam or a hard edge are moved as one, along their
+    // averaged normal by their averaged height, so the surface doesn't tear there
+    pub fn displaced(&self, displacement: &Displacement) -> Mesh {
+        let mut mesh = self.clone();
+        for _ in 0..displacement.subdivisions {
+            mesh = mesh.subdivide();
+        }
+
+        let (groups, num_groups) = mesh.coincident_groups();
+        let heights: Vec<f32> = (0..mesh.positions.len())
+            .into_par_iter()
+            .map(|i| {
+                let sp = SurfacePoint::new(mesh.uvs[i], mesh.positions[i]);
+                displacement.height.value(&sp)
+            })
+            .collect();
+        let mut normals = vec![Vec3::ZERO; num_groups];
+        let mut group_heights = vec![0.0; num_groups];
+        let mut counts = vec![0.0; num_groups];
+        for (i, &group) in groups.iter().enumerate() {
+            normals[group] += mesh.normals[i];
+            group_heights[group] += heights[i];
+            counts[group] += 1.0;
+        }
+        for (position, &group) in mesh.positions.iter_mut().zip(&groups) {
+            let height = group_heights[group] / counts[group];
+            *position += normals[group].normalize_or_zero() * height * displacement.scale;
+        }
+
+        mesh.recompute_normals();
+        let mut normals = vec![Vec3::ZERO; num_groups];
+        for (normal, &group) in mesh.normals.iter().zip(&groups) {
+            normals[group] += *normal;
+        }
+        for (normal, &group) in mesh.normals.iter_mut().zip(&groups) {
+            *normal = normals[group].normalize_or_zero();
+        }
+        mesh
+    }
+
+    // the group of every vertex, vertices at exactly the same position share one
+    fn coincident_groups(&self) -> (Vec<usize>, usize) {
+        let mut ids: HashMap<[u32; 3], usize> = HashMap::new();
+        let groups = self
+            .positions
+            .iter()
+            .map(|position| {
+                let next = ids.len();
+                *ids.entry(position.to_array().map(f32::to_bits))
+                    .or_insert(next)
+            })
+            .collect();
+        (groups, ids.len())
+    }
+
+    // mikktspace style: each face's uv gradient is normalized, angle weighted onto
+    // its corners, then orthogonalized against the vertex normal. the bitangent is
+    // normal x tangent flipped to the uv handedness, so mirrored uvs still work
+    pub fn tangents(&self) -> (Vec<Vec3>, Vec<Vec3>) {
+        let mut tangent_sums = vec![Vec3::ZERO; self.positions.len()];
+        let mut bitangent_sums = vec![Vec3::ZERO; self.positions.len()];
+
+        for face in self.indices.chunks_exact(3) {
+            let corners = [face[0] as usize, face[1] as usize, face[2] as usize];
+            let p = corners.map(|i| self.positions[i]);
+            let Some((dpdu, dpdv)) = uv_gradients(p, corners.map(|i| self.uvs[i])) else {
+                continue;
+            };
+            let (t, b) = (dpdu.normalize_or_zero(), dpdv.normalize_or_zero());
+
+            for k in 0..3 {
+                let to_next = (p[(k + 1) % 3] - p[k]).normalize_or_zero();
+                let to_prev = (p[(k + 2) % 3] - p[k]).normalize_or_zero();
+                let angle = to_next.dot(to_prev).clamp(-1.0, 1.0).acos();
+                tangent_sums[corners[k]] += t * angle;
+                bitangent_sums[corners[k]] += b * angle;
+            }
+        }
+
+        let mut tangents = Vec::with_capacity(self.positions.len());
+        let mut bitangents = Vec::with_capacity(self.positions.len());
+        for i in 0..self.positions.len() {
+            let n = self.normals[i];
+            let t = tangent_sums[i];
+            let mut tangent = (t - n * n.dot(t)).normalize_or_zero();
+            if tangent == Vec3::ZERO {
+                tangent = perpendicular_to(n);
+            }
+            let mut bitangent = n.cross(tangent);
+            if bitangent.dot(bitangent_sums[i]) < 0.0 {
+                bitangent = -bitangent;
+            }
+            tangents.push(tangent);
+            bitangents.push(bitangent);
+        }
+        (tangents, bitangents)
+    }
+}
+
+// (facet normal, corners) per triangle. binary files are told apart by their size
+// matching the triangle count, some start with "solid" just like ascii ones
+fn parse_stl(data: &[u8]) -> Result<Vec<(Vec3, [Vec3; 3])>, String> {
+    if data.len() >= 84 {
+        let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+        if data.len() == 84 + count * 50 {
+            let float =
+                |bytes: &[u8], at: usize| f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+            let vec3 = |bytes: &[u8], at: usize| {
+                Vec3::new(float(bytes, at), float(bytes, at + 4), float(bytes, at + 8))
+            };
+            return Ok(data[84..]
+                .chunks_exact(50)
+                .map(|facet| {
+                    (
+                        vec3(facet, 0),
+                        [vec3(facet, 12), vec3(facet, 24), vec3(facet, 36)],
+                    )
+                })
+                .collect());
+        }
+    }
+
+    let text = std::str::from_utf8(data).map_err(|_| "neither binary nor ascii stl")?;
+    if !text.trim_start().starts_with("solid") {
+        return Err("neither binary nor ascii stl".to_string());
+    }
+    let mut words = text.split_whitespace();
+    let vec3 = |words: &mut std::str::SplitWhitespace| -> Result<Vec3, String> {
+        let mut value = || -> Result<f32, String> {
+            let word = words.next().ok_or("file ends early")?;
+            word.parse()
+                .map_err(|_| format!("expected a number, found {word:?}"))
+        };
+        Ok(Vec3::new(value()?, value()?, value()?))
+    };
+
+    let mut triangles = Vec::new();
+    let mut normal = Vec3::ZERO;
+    let mut corners = Vec::with_capacity(3);
+    while let Some(word) = words.next() {
+        match word {
+            "normal" => normal = vec3(&mut words)?,
+            "vertex" => corners.push(vec3(&mut words)?),
+            "endfacet" => {
+                let [a, b, c] = corners[..] else {
+                    return Err(format!("facet with {} vertices", corners.len()));
+                };
+                triangles.push((normal, [a, b, c]));
+                corners.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+// dp/du and dp/dv of a flat triangle, None when the uvs are degenerate
+pub fn uv_gradients(p: [Vec3; 3], uv: [Vec2; 3]) -> Option<(Vec3, Vec3)> {
+    let (e1, e2) = (p[1] - p[0], p[2] - p[0]);
+    let (d1, d2) = (uv[1] - uv[0], uv[2] - uv[0]);
+    let det = d1.x * d2.y - d2.x * d1.y;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let dpdu = (e1 * d2.y - e2 * d1.y) / det;
+    let dpdv = (e2 * d1.x - e1 * d2.x) / det;
+    Some((dpdu, dpdv))
+}
+
+// real geometry from a height texture, baked when the scene is optimized.
+// the mesh is subdivided first so the height has vertices to move,
+// each level makes four times the triangles
+#[derive(Clone, PartialEq)]
+pub struct Displacement {
+    pub height: TextureInput,
+    pub scale: f32,
+    pub subdivisions: u32,
+}
+
+impl Displacement {
+    pub fn new(height: impl Into<TextureInput>, scale: f32) -> Displacement {
+        Displacement {
+            height: height.into(),
+            scale,
+            subdivisions: 4,
+        }
+    }
+
+    pub fn subdivisions(mut self, subdivisions: u32) -> Self {
+        self.subdivisions = subdivisions;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a binary stl of the given facets behind an 80 byte header
+    fn binary_stl(header: &[u8], facets: &[(Vec3, [Vec3; 3])]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&(facets.len() as u32).to_le_bytes());
+        for (normal, corners) in facets {
+            for v in [*normal, corners[0], corners[1], corners[2]] {
+                for x in v.to_array() {
+                    data.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            data.extend_from_slice(&[0, 0]);
+        }
+        data
+    }
+
+    const FACET: (Vec3, [Vec3; 3]) = (Vec3::Z, [Vec3::ZERO, Vec3::X, Vec3::Y]);
+
+    #[test]
+    fn ascii_stl() {
+        let data =
+            b"solid test\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 0\n   vertex 1 0 0\n\
+            vertex 0 1 0\n  endloop\n endfacet\nendsolid test\n";
+        assert_eq!(parse_stl(data).unwrap(), vec![FACET]);
+    }
+
+    #[test]
+    fn binary_stl_starting_with_solid() {
+        let data = binary_stl(b"solid exported by some cad program", &[FACET, FACET]);
+        assert_eq!(parse_stl(&data).unwrap(), vec![FACET, FACET]);
+    }
+
+    #[test]
+    fn truncated_binary_stl_is_an_error() {
+        let data = binary_stl(b"binary", &[FACET, FACET]);
+        assert_eq!(
+            parse_stl(&data[..data.len() - 20]).unwrap_err(),
+            "neither binary nor ascii stl"
+        );
+        let data = binary_stl(b"solid", &[FACET, FACET]);
+        assert!(parse_stl(&data[..data.len() - 20]).is_err());
+    }
+
+    #[test]
+    fn truncated_ascii_stl_is_an_error() {
+        let data = b"solid test\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 0\n   vertex 1 0";
+        assert_eq!(parse_stl(data).unwrap_err(), "file ends early");
+        let data =
+            b"solid test\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 0\n   vertex 1 0 0\n\
+            endloop\n endfacet\nendsolid test\n";
+        assert_eq!(parse_stl(data).unwrap_err(), "facet with 2 vertices");
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::{
+    error::{Error, Result},
+    mesh::Mesh,
+    shapes::TrisGeometry,
+    subdivision::Subdivision,
+};
+
+// how a file's mesh is prepared before its bvh is built. where it ends up in
+// the scene isn't part of it, instances place the shared geometry
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MeshOptions {
+    // centered on the origin with its longest side 1, whatever the file's units
+    pub fit: bool,
+    // also smooth shaded with the limit normals, other meshes stay flat
+    pub subdivision: Option<Subdivision>,
+    // shared vertices joined and smooth shaded, for stls and other files that
+    // repeat every corner per face
+    pub weld: bool,
+    // inside out, for files whose faces wind the wrong way round
+    pub flip: bool,
+}
+
+impl MeshOptions {
+    pub fn fit(mut self, fit: bool) -> MeshOptions {
+        self.fit = fit;
+        self
+    }
+
+    pub fn subdivision(mut self, subdivision: Subdivision) -> MeshOptions {
+        self.subdivision = Some(subdivision);
+        self
+    }
+
+    pub fn weld(mut self, weld: bool) -> MeshOptions {
+        self.weld = weld;
+        self
+    }
+
+    pub fn flip(mut self, flip: bool) -> MeshOptions {
+        self.flip = flip;
+        self
+    }
+}
+
+// a key's geometry, built by whichever thread gets to it first. the others wait
+// on its lock instead of building the same model again
+type Slot = Arc<Mutex<Option<Arc<TrisGeometry>>>>;
+
+// loads and builds the bvh of every model file once, so animations don't redo
+// it every frame. the same file prepared with different options is built
+// separately
+#[derive(Default)]
+pub struct MeshCache {
+    models: Mutex<HashMap<(PathBuf, MeshOptions), Slot>>,
+}
+
+impl MeshCache {
+    pub fn global() -> &'static MeshCache {
+        static CACHE: OnceLock<MeshCache> = OnceLock::new();
+        CACHE.get_or_init(MeshCache::default)
+    }
+
+    pub fn load(&self, path: &str, options: MeshOptions) -> Result<Arc<TrisGeometry>> {
+        let canonical = fs::canonicalize(path).map_err(|error| Error::io(path, error))?;
+        let slot = self
+            .models
+            .lock()
+            .unwrap()
+            .entry((canonical, options))
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().unwrap();
+        if let Some(geometry) = slot.as_ref() {
+            return Ok(geometry.clone());
+        }
+
+        // welded first so subdivision sees one surface rather than loose faces
+        let mut mesh = Mesh::load(path)?;
+        if options.weld {
+            mesh = mesh.welded();
+        }
+        if options.flip {
+            mesh = mesh.flipped();
+        }
+        if options.fit {
+            mesh = mesh.normalized();
+        }
+        if let Some(subdivision) = options.subdivision {
+            mesh = subdivision.apply(&mesh);
+        }
+        let smooth = options.weld || options.subdivision.is_some();
+        Ok(slot
+            .insert(Arc::new(TrisGeometry::from_mesh(&mesh, smooth)))
+            .clone())
+    }
+
+    pub fn num_models(&self) -> usize {
+        self.models
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|slot| slot.lock().unwrap().is_some())
+            .count()
+    }
+}
@@ -0,0 +1,136 @@
+use std::ops::{Add, Mul, Sub};
+
+use glam::{BVec4A, Vec3, Vec4};
+
+use crate::{
+    structures::{HitRecord, Ray},
+    triangle::PacketShear,
+};
+
+// rays traced together, one per simd lane
+pub const PACKET_SIZE: usize = 4;
+
+// one hit per lane
+pub type PacketHits = [Option<HitRecord>; PACKET_SIZE];
+
+// a 3d vector per lane, each component in its own simd register
+#[derive(Clone, Copy)]
+pub struct Vec3x4 {
+    pub x: Vec4,
+    pub y: Vec4,
+    pub z: Vec4,
+}
+
+impl Vec3x4 {
+    pub fn splat(v: Vec3) -> Vec3x4 {
+        Vec3x4 {
+            x: Vec4::splat(v.x),
+            y: Vec4::splat(v.y),
+            z: Vec4::splat(v.z),
+        }
+    }
+
+    pub fn from_lanes(lanes: [Vec3; PACKET_SIZE]) -> Vec3x4 {
+        Vec3x4 {
+            x: Vec4::from_array(lanes.map(|v| v.x)),
+            y: Vec4::from_array(lanes.map(|v| v.y)),
+            z: Vec4::from_array(lanes.map(|v| v.z)),
+        }
+    }
+
+    pub fn dot(self, other: Vec3x4) -> Vec4 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3x4) -> Vec3x4 {
+        Vec3x4 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn recip(self) -> Vec3x4 {
+        Vec3x4 {
+            x: self.x.recip(),
+            y: self.y.recip(),
+            z: self.z.recip(),
+        }
+    }
+}
+
+impl Add for Vec3x4 {
+    type Output = Vec3x4;
+    fn add(self, other: Vec3x4) -> Vec3x4 {
+        Vec3x4 {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl Sub for Vec3x4 {
+    type Output = Vec3x4;
+    fn sub(self, other: Vec3x4) -> Vec3x4 {
+        Vec3x4 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+impl Mul<Vec4> for Vec3x4 {
+    type Output = Vec3x4;
+    fn mul(self, scale: Vec4) -> Vec3x4 {
+        Vec3x4 {
+            x: self.x * scale,
+            y: self.y * scale,
+            z: self.z * scale,
+        }
+    }
+}
+
+// rays that start close together and head roughly the same way, like the
+// primary rays of neighbouring pixels or shadow rays from one shading point
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_SIZE],
+    pub origin: Vec3x4,
+    pub dir: Vec3x4,
+    pub inv_dir: Vec3x4,
+    // for the watertight triangle test
+    pub shear: PacketShear,
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; PACKET_SIZE]) -> RayPacket {
+        let dir = Vec3x4::from_lanes(rays.map(|ray| ray.dir));
+        RayPacket {
+            origin: Vec3x4::from_lanes(rays.map(|ray| ray.origin)),
+            dir,
+            inv_dir: dir.recip(),
+            shear: PacketShear::new(&rays),
+            rays,
+        }
+    }
+
+    // pads short runs with copies of the last ray, callers ignore the extra lanes
+    pub fn from_slice(rays: &[Ray]) -> RayPacket {
+        let last = rays[rays.len() - 1];
+        RayPacket::new(std::array::from_fn(|lane| {
+            rays.get(lane).copied().unwrap_or(last)
+        }))
+    }
+}
+
+// glam has no vector sqrt
+pub fn sqrt(v: Vec4) -> Vec4 {
+    Vec4::from_array(v.to_array().map(f32::sqrt))
+}
+
+// the lanes set in a mask, in order
+pub fn lanes(mask: BVec4A) -> impl Iterator<Item = usize> {
+    let bits = mask.bitmask();
+    (0..PACKET_SIZE).filter(move |lane| bits & (1 << lane) != 0)
+}
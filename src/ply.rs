@@ -0,0 +1,499 @@
+use glam::{Vec2, Vec3};
+
+use crate::{error::Error, mesh::Mesh};
+
+// what a ply file turns into. scans without faces are points to be splatted
+pub enum Ply {
+    Mesh(Mesh),
+    Points(Vec<CloudPoint>),
+}
+
+#[derive(Clone, Copy)]
+pub struct CloudPoint {
+    pub position: Vec3,
+    // zero when the file has none
+    pub normal: Vec3,
+    // 0-255, white when the file has none
+    pub color: Vec3,
+    // zero when the file has none, the point cloud's own radius is used then
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Scalar, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type {name}")),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // what a color stored as this type reads as at full intensity
+    fn color_range(self) -> f32 {
+        match self {
+            Scalar::F32 | Scalar::F64 => 1.0,
+            Scalar::U16 | Scalar::I16 => 65535.0,
+            _ => 255.0,
+        }
+    }
+}
+
+enum Property {
+    Scalar(Scalar, String),
+    List {
+        count: Scalar,
+        item: Scalar,
+        name: String,
+    },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// reads values one after another, as text or in either byte order
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    format: Format,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            while self
+                .data
+                .get(self.position)
+                .is_some_and(|b| b.is_ascii_whitespace())
+            {
+                self.position += 1;
+            }
+            let start = self.position;
+            while self
+                .data
+                .get(self.position)
+                .is_some_and(|b| !b.is_ascii_whitespace())
+            {
+                self.position += 1;
+            }
+            let token = std::str::from_utf8(&self.data[start..self.position])
+                .map_err(|_| "invalid text in body".to_string())?;
+            return token
+                .parse()
+                .map_err(|_| format!("expected a number, found {token:?}"));
+        }
+
+        let size = scalar.size();
+        let bytes = self
+            .data
+            .get(self.position..self.position + size)
+            .ok_or("file ends early")?;
+        self.position += size;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => buffer[0] as i8 as f64,
+            Scalar::U8 => buffer[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            Scalar::U32 => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            Scalar::F32 => f32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+            Scalar::F64 => f64::from_le_bytes(buffer),
+        })
+    }
+}
+
+// vertex properties the loader understands, as slots of one row
+const VERTEX_SLOTS: [&[&str]; 12] = [
+    &["x"],
+    &["y"],
+    &["z"],
+    &["nx"],
+    &["ny"],
+    &["nz"],
+    &["u", "s", "texture_u", "texture_s"],
+    &["v", "t", "texture_v", "texture_t"],
+    &["red", "r", "diffuse_red"],
+    &["green", "g", "diffuse_green"],
+    &["blue", "b", "diffuse_blue"],
+    &["radius"],
+];
+
+fn header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+    let end_marker = b"end_header";
+    let end = data
+        .windows(end_marker.len())
+        .position(|window| window == end_marker)
+        .ok_or("no end_header")?;
+    // the body starts on the line after end_header
+    let body = data[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|newline| end + newline + 1)
+        .ok_or("no body")?;
+    let text = std::str::from_utf8(&data[..end]).map_err(|_| "header isn't text")?;
+
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("not a ply file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["format", kind, _] => {
+                format = Some(match kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("unknown format {kind}")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("bad element count {count}"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::List {
+                    count: Scalar::parse(count)?,
+                    item: Scalar::parse(item)?,
+                    name: name.to_string(),
+                }),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::Scalar(Scalar::parse(scalar)?, name.to_string())),
+            _ => {}
+        }
+    }
+    Ok((format.ok_or("no format line")?, elements, body))
+}
+
+// list lengths and indices, which float typed or negative values can't be
+fn whole_number(value: f64, what: &str) -> Result<u32, String> {
+    if value < 0.0 || value.fract() != 0.0 || value > u32::MAX as f64 {
+        return Err(format!("bad {what} {value}"));
+    }
+    Ok(value as u32)
+}
+
+// ascii and binary ply, the vertex and face elements. everything else is read
+// past. faces of any size are split into fans
+pub fn parse_ply(data: &[u8]) -> Result<Ply, String> {
+    let (format, elements, body) = header(data)?;
+    let mut reader = Reader {
+        data,
+        position: body,
+        format,
+    };
+
+    let mut vertices: Vec<[f64; 12]> = Vec::new();
+    let mut present = [false; 12];
+    let mut color_range = 255.0;
+    let mut faces: Vec<u32> = Vec::new();
+    for element in &elements {
+        // where each scalar property goes in a vertex row, if anywhere
+        let slots: Vec<Option<usize>> = element
+            .properties
+            .iter()
+            .map(|property| match property {
+                Property::Scalar(scalar, name) if element.name == "vertex" => {
+                    let slot = VERTEX_SLOTS
+                        .iter()
+                        .position(|names| names.contains(&name.as_str()))?;
+                    present[slot] = true;
+                    if slot == 8 {
+                        color_range = scalar.color_range();
+                    }
+                    Some(slot)
+                }
+                _ => None,
+            })
+            .collect();
+
+        for _ in 0..element.count {
+            let mut row = [0.0; 12];
+            for (property, slot) in element.properties.iter().zip(&slots) {
+                match property {
+                    Property::Scalar(scalar, _) => {
+                        let value = reader.read(*scalar)?;
+                        if let Some(slot) = slot {
+                            row[*slot] = value;
+                        }
+                    }
+                    Property::List { count, item, name } => {
+                        let count = whole_number(reader.read(*count)?, "list length")? as usize;
+                        let is_face = element.name == "face"
+                            && (name == "vertex_indices" || name == "vertex_index");
+                        // the length comes from the file, so grow as the items
+                        // are actually read rather than trusting it up front
+                        let mut polygon = Vec::new();
+                        for _ in 0..count {
+                            polygon.push(whole_number(reader.read(*item)?, "vertex index")?);
+                        }
+                        if is_face {
+                            for i in 1..count.saturating_sub(1) {
+                                faces.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                vertices.push(row);
+            }
+        }
+    }
+
+    if !(present[0] && present[1] && present[2]) {
+        return Err("vertices have no x, y and z".to_string());
+    }
+    if let Some(&index) = faces.iter().find(|&&i| i as usize >= vertices.len()) {
+        return Err(format!("face uses vertex {index} of {}", vertices.len()));
+    }
+
+    let vec3 = |row: &[f64; 12], first: usize| {
+        Vec3::new(
+            row[first] as f32,
+            row[first + 1] as f32,
+            row[first + 2] as f32,
+        )
+    };
+    let has_normals = present[3] && present[4] && present[5];
+    let has_uvs = present[6] && present[7];
+    let has_colors = present[8] && present[9] && present[10];
+    let color = |row: &[f64; 12]| vec3(row, 8) * (255.0 / color_range);
+
+    if faces.is_empty() {
+        return Ok(Ply::Points(
+            vertices
+                .iter()
+                .map(|row| CloudPoint {
+                    position: vec3(row, 0),
+                    normal: if has_normals {
+                        vec3(row, 3).normalize_or_zero()
+                    } else {
+                        Vec3::ZERO
+                    },
+                    color: if has_colors {
+                        color(row)
+                    } else {
+                        Vec3::splat(255.0)
+                    },
+                    radius: row[11] as f32,
+                })
+                .collect(),
+        ));
+    }
+
+    let mut mesh = Mesh {
+        positions: vertices.iter().map(|row| vec3(row, 0)).collect(),
+        indices: faces,
+        ..Default::default()
+    };
+    mesh.uvs = if has_uvs {
+        vertices
+            .iter()
+            .map(|row| Vec2::new(row[6] as f32, 1.0 - row[7] as f32))
+            .collect()
+    } else {
+        vec![Vec2::ZERO; vertices.len()]
+    };
+    if has_colors {
+        mesh.colors = vertices.iter().map(color).collect();
+    }
+    if has_normals {
+        mesh.normals = vertices
+            .iter()
+            .map(|row| vec3(row, 3).normalize_or_zero())
+            .collect();
+    } else {
+        mesh.recompute_normals();
+    }
+    Ok(Ply::Mesh(mesh))
+}
+
+pub fn load_ply(filename: &str) -> Result<Ply, Error> {
+    let data = std::fs::read(filename).map_err(|error| Error::io(filename, error))?;
+    parse_ply(&data).map_err(|reason| Error::parse(filename, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    // a triangle with float positions and a uchar counted int list, in the given
+    // byte order
+    fn binary_triangle(
+        format: &str,
+        to_bytes: fn(f32) -> [u8; 4],
+        int: fn(i32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut data = format!(
+            "ply\nformat {format} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n"
+        )
+        .into_bytes();
+        for corner in TRIANGLE {
+            for value in corner {
+                data.extend_from_slice(&to_bytes(value));
+            }
+        }
+        data.push(3);
+        for index in [0, 1, 2] {
+            data.extend_from_slice(&int(index));
+        }
+        data
+    }
+
+    fn mesh(ply: Result<Ply, String>) -> Mesh {
+        match ply {
+            Ok(Ply::Mesh(mesh)) => mesh,
+            Ok(Ply::Points(_)) => panic!("expected a mesh, got points"),
+            Err(reason) => panic!("expected a mesh, got {reason}"),
+        }
+    }
+
+    fn error(ply: Result<Ply, String>) -> String {
+        match ply {
+            Ok(_) => panic!("expected an error"),
+            Err(reason) => reason,
+        }
+    }
+
+    #[test]
+    fn ascii_quad_is_split_into_a_fan() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\n\
+            property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        let mesh = mesh(parse_ply(data));
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2], Vec3::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        // no normals in the file, so they come from the winding
+        assert_eq!(mesh.normals[0], Vec3::Z);
+    }
+
+    #[test]
+    fn both_byte_orders_read_the_same() {
+        let little = binary_triangle("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        let big = binary_triangle("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        for data in [little, big] {
+            let mesh = mesh(parse_ply(&data));
+            assert_eq!(mesh.positions, TRIANGLE.map(Vec3::from_array).to_vec());
+            assert_eq!(mesh.indices, vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn vertices_without_faces_are_points() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+            property float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+            end_header\n0 0 0 255 0 0\n1 2 3 0 128 255\n";
+        let Ok(Ply::Points(points)) = parse_ply(data) else {
+            panic!("expected points");
+        };
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(points[1].color, Vec3::new(0.0, 128.0, 255.0));
+        assert_eq!(points[0].normal, Vec3::ZERO);
+    }
+
+    #[test]
+    fn truncated_binary_body_is_an_error() {
+        let data = binary_triangle("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        for length in [data.len() - 1, data.len() - 13, data.len() - 30] {
+            assert_eq!(error(parse_ply(&data[..length])), "file ends early");
+        }
+    }
+
+    #[test]
+    fn truncated_ascii_body_is_an_error() {
+        let data = b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+            property float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n3 0 1";
+        assert!(error(parse_ply(data)).starts_with("expected a number"));
+    }
+
+    #[test]
+    fn out_of_range_index_is_an_error() {
+        let mut data = binary_triangle("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        let last = data.len() - 4;
+        data[last..].copy_from_slice(&3i32.to_le_bytes());
+        assert_eq!(error(parse_ply(&data)), "face uses vertex 3 of 3");
+    }
+
+    #[test]
+    fn negative_list_lengths_and_indices_are_errors() {
+        let header =
+            "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+            property float z\nelement face 1\nproperty list char int vertex_indices\nend_header\n\
+            0 0 0\n1 0 0\n0 1 0\n";
+        let length = format!("{header}-3 0 1 2\n");
+        assert_eq!(error(parse_ply(length.as_bytes())), "bad list length -3");
+        let index = format!("{header}3 0 -1 2\n");
+        assert_eq!(error(parse_ply(index.as_bytes())), "bad vertex index -1");
+    }
+
+    #[test]
+    fn header_problems_are_errors() {
+        assert_eq!(
+            error(parse_ply(b"ply\nformat ascii 1.0\n")),
+            "no end_header"
+        );
+        assert_eq!(error(parse_ply(b"obj\nend_header\n")), "not a ply file");
+        let no_z = b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+            property float y\nend_header\n0 0\n";
+        assert_eq!(error(parse_ply(no_z)), "vertices have no x, y and z");
+    }
+}
@@ -0,0 +1,182 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    bvh::{Aabb, Bounded, Bvh},
+    error::{Error, Result},
+    material::Material,
+    ply::{load_ply, CloudPoint, Ply},
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+    utils::{gamma, perpendicular_to, solve_quadratic},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplatType {
+    Sphere,
+    // flat along the point's normal, or turned to the ray where it has none
+    Disk,
+}
+
+impl Bounded for CloudPoint {
+    fn aabb(&self) -> Aabb {
+        Aabb::new(
+            self.position - Vec3::splat(self.radius),
+            self.position + Vec3::splat(self.radius),
+        )
+    }
+}
+
+// a scan's points drawn as small spheres or disks, with a bvh over them like
+// TrisModel has over its triangles. the points' colors come through
+// TextureInput::VertexColor
+pub struct PointCloud {
+    pub points: Vec<CloudPoint>,
+    pub splat_type: SplatType,
+    pub material: Box<dyn Material>,
+    bvh: Bvh,
+}
+
+impl PointCloud {
+    // radius is for the points that don't bring their own
+    pub fn new(
+        points: Vec<CloudPoint>,
+        splat_type: SplatType,
+        radius: f32,
+        material: Box<dyn Material>,
+    ) -> PointCloud {
+        let points: Vec<CloudPoint> = points
+            .into_iter()
+            .map(|point| CloudPoint {
+                radius: if point.radius > 0.0 {
+                    point.radius
+                } else {
+                    radius
+                },
+                ..point
+            })
+            .collect();
+        let bvh = Bvh::build(&points);
+        PointCloud {
+            points,
+            splat_type,
+            material,
+            bvh,
+        }
+    }
+
+    // a ply with only vertices, scaled then moved like TrisModel::placed
+    pub fn from_ply(
+        filename: &str,
+        p: Vec3,
+        scale: f32,
+        splat_type: SplatType,
+        radius: f32,
+        material: Box<dyn Material>,
+    ) -> Result<PointCloud> {
+        let Ply::Points(points) = load_ply(filename)? else {
+            return Err(Error::parse(filename, "has faces, load it as a TrisModel"));
+        };
+        let points = points
+            .into_iter()
+            .map(|point| CloudPoint {
+                position: point.position * scale + p,
+                radius: point.radius * scale,
+                ..point
+            })
+            .collect();
+        Ok(PointCloud::new(points, splat_type, radius, material))
+    }
+
+    fn hit_sphere(
+        point: &CloudPoint,
+        ray: &Ray,
+        ray_tmin: f32,
+        ray_tmax: f32,
+    ) -> Option<HitRecord> {
+        let to_point = ray.origin - point.position;
+        let a = ray.dir.length_squared();
+        let half_b = to_point.dot(ray.dir);
+        let c = to_point.length_squared() - point.radius * point.radius;
+        let c_error = gamma(3) * (to_point.length_squared() + point.radius * point.radius);
+        let (near, far) = solve_quadratic(a, half_b, c, c_error)?;
+        let t = [near, far]
+            .into_iter()
+            .find(|&t| t >= ray_tmin && t <= ray_tmax)?;
+
+        let to_hit = ray.at(t) - point.position;
+        let to_hit = to_hit * (point.radius / to_hit.length());
+        let mut hit_record = HitRecord::new();
+        hit_record.t = t;
+        hit_record.p = point.position + to_hit;
+        hit_record.p_error = gamma(5) * to_hit.abs() + gamma(1) * hit_record.p.abs();
+        hit_record.set_face_normal(ray, to_hit / point.radius);
+        Some(hit_record)
+    }
+
+    fn hit_disk(point: &CloudPoint, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let normal = if point.normal == Vec3::ZERO {
+            -ray.dir.normalize()
+        } else {
+            point.normal
+        };
+        let denominator = ray.dir.dot(normal);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = (point.position - ray.origin).dot(normal) / denominator;
+        if t < ray_tmin || t > ray_tmax {
+            return None;
+        }
+        let p = ray.at(t);
+        if (p - point.position).length_squared() > point.radius * point.radius {
+            return None;
+        }
+
+        let mut hit_record = HitRecord::new();
+        hit_record.t = t;
+        hit_record.p = p;
+        hit_record.p_error = gamma(5) * (ray.origin.abs() + (ray.dir * t).abs());
+        hit_record.set_face_normal(ray, normal);
+        Some(hit_record)
+    }
+}
+
+impl Shape for PointCloud {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        self.bvh
+            .closest_hit(ray, ray_tmin, ray_tmax, |index, closest_so_far| {
+                let point = &self.points[index];
+                let mut hit_record = match self.splat_type {
+                    SplatType::Sphere => {
+                        PointCloud::hit_sphere(point, ray, ray_tmin, closest_so_far)
+                    }
+                    SplatType::Disk => PointCloud::hit_disk(point, ray, ray_tmin, closest_so_far),
+                }?;
+                hit_record.prim_index = index;
+                Some(hit_record)
+            })
+            .map(|(_, hit_record)| hit_record)
+    }
+
+    // splats are too small to texture, the points' colors are what they show
+    fn get_hit_uv(&self, _hit_record: &HitRecord) -> Vec2 {
+        Vec2::ZERO
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let tangent = perpendicular_to(hit_record.normal);
+        (tangent, hit_record.normal.cross(tangent))
+    }
+
+    fn get_hit_color(&self, hit_record: &HitRecord) -> Option<Vec3> {
+        Some(self.points[hit_record.prim_index].color)
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bvh.aabb()
+    }
+}
@@ -0,0 +1,912 @@
+use std::f32::consts::PI;
+
+use glam::{Mat3, Quat, Vec2, Vec3};
+
+use crate::{
+    bvh::Aabb,
+    material::Material,
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+    utils::{gamma, perpendicular_to, solve_quadratic},
+};
+
+// where an oriented shape sits. the shapes below are intersected in their own
+// space, with their base at the origin and their axis along y
+#[derive(Clone, Copy)]
+struct Frame {
+    position: Vec3,
+    to_world: Mat3,
+    to_local: Mat3,
+}
+
+// a hit in a shape's own space, part tells which of its surfaces it was on
+struct LocalHit {
+    t: f32,
+    p: Vec3,
+    p_error: Vec3,
+    normal: Vec3,
+    part: usize,
+}
+
+impl Frame {
+    fn new(position: Vec3, rotation: Quat) -> Frame {
+        let to_world = Mat3::from_quat(rotation);
+        Frame {
+            position,
+            to_world,
+            to_local: to_world.transpose(),
+        }
+    }
+
+    // y turned to point along axis. a zero axis, from endpoints that coincide,
+    // leaves y where it is
+    fn along(position: Vec3, axis: Vec3) -> Frame {
+        let axis = axis.try_normalize().unwrap_or(Vec3::Y);
+        Frame::new(position, Quat::from_rotation_arc(Vec3::Y, axis))
+    }
+
+    // rotating rounds the origin, maybe back into the surface a spawned ray just
+    // left, so the local ray starts past that error. also returns how far it skipped
+    fn ray_to_local(&self, ray: &Ray) -> (Ray, f32) {
+        let origin = self.to_local * (ray.origin - self.position);
+        let dir = self.to_local * ray.dir;
+        let origin_error =
+            gamma(5) * (abs_matrix(self.to_local) * (ray.origin.abs() + self.position.abs()));
+        let skip = dir.abs().dot(origin_error);
+        let local_ray = Ray::new(origin + dir * skip, dir)
+            .with_cone(ray.cone_width, ray.cone_spread)
+            .with_time(ray.time);
+        (local_ray, skip)
+    }
+
+    fn hit_to_world(&self, ray: &Ray, skip: f32, hit: LocalHit) -> HitRecord {
+        let abs_to_world = abs_matrix(self.to_world);
+        let mut hit_record = HitRecord::new();
+        hit_record.t = hit.t + skip;
+        hit_record.p = self.to_world * hit.p + self.position;
+        hit_record.p_error = (1.0 + gamma(3)) * (abs_to_world * hit.p_error)
+            + gamma(4) * (abs_to_world * hit.p.abs() + self.position.abs());
+        hit_record.prim_index = hit.part;
+        hit_record.set_face_normal(ray, (self.to_world * hit.normal).normalize());
+        hit_record
+    }
+
+    fn point_to_local(&self, p: Vec3) -> Vec3 {
+        self.to_local * (p - self.position)
+    }
+
+    fn tangents_to_world(&self, (dpdu, dpdv): (Vec3, Vec3)) -> (Vec3, Vec3) {
+        (self.to_world * dpdu, self.to_world * dpdv)
+    }
+}
+
+fn abs_matrix(m: Mat3) -> Mat3 {
+    Mat3::from_cols(m.x_axis.abs(), m.y_axis.abs(), m.z_axis.abs())
+}
+
+// how far a circle of radius around axis reaches along each world axis
+fn disk_extent(axis: Vec3, radius: f32) -> Vec3 {
+    let axis = axis.normalize();
+    let reach = |a: f32| radius * (1.0 - a * a).max(0.0).sqrt();
+    Vec3::new(reach(axis.x), reach(axis.y), reach(axis.z))
+}
+
+// error of o + t d in a shape's own space, for points that weren't reprojected
+fn ray_point_error(ray: &Ray, t: f32) -> Vec3 {
+    gamma(5) * (ray.origin.abs() + (ray.dir * t).abs())
+}
+
+// keeps the nearest (t, part) in range
+fn nearest(candidates: &[(f32, usize)], ray_tmin: f32, ray_tmax: f32) -> Option<(f32, usize)> {
+    candidates
+        .iter()
+        .copied()
+        .filter(|&(t, _)| t >= ray_tmin && t <= ray_tmax)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+// t where the ray crosses a circle of radius in the xz plane at height y
+fn cap_hit(ray: &Ray, y: f32, radius: f32) -> Option<f32> {
+    let t = (y - ray.origin.y) / ray.dir.y;
+    let p = ray.at(t);
+    (t.is_finite() && p.x * p.x + p.z * p.z <= radius * radius).then_some(t)
+}
+
+fn cap_local_hit(ray: &Ray, t: f32, y: f32, normal: Vec3, part: usize) -> LocalHit {
+    let mut p = ray.at(t);
+    p.y = y;
+    let mut p_error = ray_point_error(ray, t);
+    p_error.y = 0.0;
+    LocalHit {
+        t,
+        p,
+        p_error,
+        normal,
+        part,
+    }
+}
+
+fn cap_uv(p: Vec3, radius: f32) -> Vec2 {
+    Vec2::new(0.5 + p.x / (2.0 * radius), 0.5 + p.z / (2.0 * radius))
+}
+
+fn cap_tangents(radius: f32) -> (Vec3, Vec3) {
+    (Vec3::X * (2.0 * radius), Vec3::Z * (2.0 * radius))
+}
+
+// u goes once around the y axis
+fn azimuth_u(p: Vec3) -> f32 {
+    (p.z.atan2(p.x) + PI) / (2.0 * PI)
+}
+
+fn azimuth_dpdu(p: Vec3) -> Vec3 {
+    Vec3::new(-p.z, 0.0, p.x) * (2.0 * PI)
+}
+
+// an oriented box, axis aligned until rotated
+pub struct Cuboid {
+    pub center: Vec3,
+    pub half_size: Vec3,
+    pub orientation: Quat,
+    pub material: Box<dyn Material>,
+    frame: Frame,
+}
+
+impl Cuboid {
+    pub fn new(
+        center: Vec3,
+        half_size: Vec3,
+        material: Box<dyn Material>,
+        orientation: Quat,
+    ) -> Cuboid {
+        Cuboid {
+            center,
+            half_size,
+            orientation,
+            material,
+            frame: Frame::new(center, orientation),
+        }
+    }
+
+    pub fn from_corners(min: Vec3, max: Vec3, material: Box<dyn Material>) -> Cuboid {
+        Cuboid::new(
+            (min + max) * 0.5,
+            (max - min) * 0.5,
+            material,
+            Quat::IDENTITY,
+        )
+    }
+
+    // parts are 2 * axis, plus 1 on the positive side
+    fn local_hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<LocalHit> {
+        let inv_dir = ray.dir.recip();
+        let t0 = (-self.half_size - ray.origin) * inv_dir;
+        let t1 = (self.half_size - ray.origin) * inv_dir;
+        let (near, far) = (t0.min(t1), t0.max(t1));
+        let (t_near, t_far) = (near.max_element(), far.min_element());
+        if t_near > t_far {
+            return None;
+        }
+
+        // entering through the face towards the ray, or leaving through the one behind it
+        let (t, axis, side) = if t_near >= ray_tmin && t_near <= ray_tmax {
+            let axis = (0..3).find(|&axis| near[axis] == t_near)?;
+            (t_near, axis, -ray.dir[axis].signum())
+        } else if t_far >= ray_tmin && t_far <= ray_tmax {
+            let axis = (0..3).find(|&axis| far[axis] == t_far)?;
+            (t_far, axis, ray.dir[axis].signum())
+        } else {
+            return None;
+        };
+
+        let mut p = ray.at(t);
+        p[axis] = side * self.half_size[axis];
+        let mut p_error = ray_point_error(ray, t);
+        p_error[axis] = 0.0;
+        let mut normal = Vec3::ZERO;
+        normal[axis] = side;
+        Some(LocalHit {
+            t,
+            p,
+            p_error,
+            normal,
+            part: 2 * axis + (side > 0.0) as usize,
+        })
+    }
+
+    // the face's other two axes, in order
+    fn face_axes(part: usize) -> (usize, usize) {
+        let axis = part / 2;
+        ((axis + 1) % 3, (axis + 2) % 3)
+    }
+}
+
+impl Shape for Cuboid {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (local_ray, skip) = self.frame.ray_to_local(ray);
+        let hit = self.local_hit(&local_ray, ray_tmin, ray_tmax - skip)?;
+        Some(self.frame.hit_to_world(ray, skip, hit))
+    }
+
+    // each face maps to the whole of [0, 1]
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let p = self.frame.point_to_local(hit_record.p);
+        let (u, v) = Cuboid::face_axes(hit_record.prim_index);
+        Vec2::new(
+            0.5 + p[u] / (2.0 * self.half_size[u]),
+            0.5 + p[v] / (2.0 * self.half_size[v]),
+        )
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let (u, v) = Cuboid::face_axes(hit_record.prim_index);
+        let (mut dpdu, mut dpdv) = (Vec3::ZERO, Vec3::ZERO);
+        dpdu[u] = 2.0 * self.half_size[u];
+        dpdv[v] = 2.0 * self.half_size[v];
+        self.frame.tangents_to_world((dpdu, dpdv))
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let corners = Aabb::new(-self.half_size, self.half_size).corners();
+        Aabb::from_points(&corners.map(|corner| self.center + self.orientation * corner))
+    }
+}
+
+// a cylinder closed at both ends. part 0 is the side, 1 the base cap and 2 the top
+pub struct Cylinder {
+    pub base: Vec3,
+    pub top: Vec3,
+    pub radius: f32,
+    pub material: Box<dyn Material>,
+    height: f32,
+    frame: Frame,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: f32, material: Box<dyn Material>) -> Cylinder {
+        Cylinder {
+            base,
+            top,
+            radius,
+            material,
+            height: base.distance(top),
+            frame: Frame::along(base, top - base),
+        }
+    }
+
+    fn local_hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<LocalHit> {
+        let (o, d) = (ray.origin, ray.dir);
+        let mut candidates = [(f32::NEG_INFINITY, 0); 4];
+
+        let a = d.x * d.x + d.z * d.z;
+        let half_b = o.x * d.x + o.z * d.z;
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        let c_error = gamma(3) * (o.x * o.x + o.z * o.z + self.radius * self.radius);
+        // a flat cylinder has no side, only its caps
+        let side = if self.height > 0.0 {
+            solve_quadratic(a, half_b, c, c_error)
+        } else {
+            None
+        };
+        if let Some((near, far)) = side {
+            let on_side = |t: f32| (0.0..=self.height).contains(&ray.at(t).y);
+            candidates[0] = (
+                if on_side(near) {
+                    near
+                } else {
+                    f32::NEG_INFINITY
+                },
+                0,
+            );
+            candidates[1] = (if on_side(far) { far } else { f32::NEG_INFINITY }, 0);
+        }
+        if let Some(t) = cap_hit(ray, 0.0, self.radius) {
+            candidates[2] = (t, 1);
+        }
+        if let Some(t) = cap_hit(ray, self.height, self.radius) {
+            candidates[3] = (t, 2);
+        }
+
+        let (t, part) = nearest(&candidates, ray_tmin, ray_tmax)?;
+        Some(match part {
+            0 => {
+                // back onto the side, like the sphere
+                let p = ray.at(t);
+                let scale = self.radius / (p.x * p.x + p.z * p.z).sqrt();
+                let p = Vec3::new(p.x * scale, p.y, p.z * scale);
+                let p_error = Vec3::new(
+                    gamma(5) * p.x.abs(),
+                    ray_point_error(ray, t).y,
+                    gamma(5) * p.z.abs(),
+                );
+                let normal = Vec3::new(p.x, 0.0, p.z) / self.radius;
+                LocalHit {
+                    t,
+                    p,
+                    p_error,
+                    normal,
+                    part,
+                }
+            }
+            1 => cap_local_hit(ray, t, 0.0, -Vec3::Y, part),
+            _ => cap_local_hit(ray, t, self.height, Vec3::Y, part),
+        })
+    }
+}
+
+impl Shape for Cylinder {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (local_ray, skip) = self.frame.ray_to_local(ray);
+        let hit = self.local_hit(&local_ray, ray_tmin, ray_tmax - skip)?;
+        Some(self.frame.hit_to_world(ray, skip, hit))
+    }
+
+    // the side wraps u around the axis with v running base to top, the caps are planar
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let p = self.frame.point_to_local(hit_record.p);
+        match hit_record.prim_index {
+            0 => Vec2::new(azimuth_u(p), p.y / self.height),
+            _ => cap_uv(p, self.radius),
+        }
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let p = self.frame.point_to_local(hit_record.p);
+        self.frame.tangents_to_world(match hit_record.prim_index {
+            0 => (azimuth_dpdu(p), Vec3::Y * self.height),
+            _ => cap_tangents(self.radius),
+        })
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let extent = disk_extent(self.top - self.base, self.radius);
+        Aabb::new(
+            self.base.min(self.top) - extent,
+            self.base.max(self.top) + extent,
+        )
+    }
+}
+
+// a cone closed at its base. part 0 is the side, 1 the base
+pub struct Cone {
+    pub base: Vec3,
+    pub apex: Vec3,
+    pub radius: f32,
+    pub material: Box<dyn Material>,
+    height: f32,
+    frame: Frame,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: f32, material: Box<dyn Material>) -> Cone {
+        Cone {
+            base,
+            apex,
+            radius,
+            material,
+            height: base.distance(apex),
+            frame: Frame::along(base, apex - base),
+        }
+    }
+
+    fn local_hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<LocalHit> {
+        let (o, d) = (ray.origin, ray.dir);
+        let mut candidates = [(f32::NEG_INFINITY, 0); 3];
+
+        // x^2 + z^2 = (k (height - y))^2, k being how fast the radius shrinks
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let below_apex = self.height - o.y;
+        let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+        let half_b = o.x * d.x + o.z * d.z + k2 * below_apex * d.y;
+        let c = o.x * o.x + o.z * o.z - k2 * below_apex * below_apex;
+        let c_error = gamma(5) * (o.x * o.x + o.z * o.z + k2 * below_apex * below_apex);
+        // a flat cone is only its base, k is infinite and the side nan
+        let side = if self.height > 0.0 {
+            solve_quadratic(a, half_b, c, c_error)
+        } else {
+            None
+        };
+        if let Some((near, far)) = side {
+            // the equation is a double cone, only the half below the apex is ours
+            let on_side = |t: f32| (0.0..=self.height).contains(&ray.at(t).y);
+            candidates[0] = (
+                if on_side(near) {
+                    near
+                } else {
+                    f32::NEG_INFINITY
+                },
+                0,
+            );
+            candidates[1] = (if on_side(far) { far } else { f32::NEG_INFINITY }, 0);
+        }
+        if let Some(t) = cap_hit(ray, 0.0, self.radius) {
+            candidates[2] = (t, 1);
+        }
+
+        let (t, part) = nearest(&candidates, ray_tmin, ray_tmax)?;
+        if part == 1 {
+            return Some(cap_local_hit(ray, t, 0.0, -Vec3::Y, part));
+        }
+
+        let mut p = ray.at(t);
+        let rho = (p.x * p.x + p.z * p.z).sqrt();
+        let radius_here = k * (self.height - p.y);
+        if rho > 0.0 {
+            p.x *= radius_here / rho;
+            p.z *= radius_here / rho;
+        }
+        let normal = Vec3::new(p.x, k * radius_here, p.z).normalize_or_zero();
+        let normal = if normal == Vec3::ZERO {
+            Vec3::Y
+        } else {
+            normal
+        };
+        Some(LocalHit {
+            t,
+            p,
+            p_error: ray_point_error(ray, t) + gamma(5) * p.abs(),
+            normal,
+            part,
+        })
+    }
+}
+
+impl Shape for Cone {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (local_ray, skip) = self.frame.ray_to_local(ray);
+        let hit = self.local_hit(&local_ray, ray_tmin, ray_tmax - skip)?;
+        Some(self.frame.hit_to_world(ray, skip, hit))
+    }
+
+    // like the cylinder, v reaches 1 at the apex
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let p = self.frame.point_to_local(hit_record.p);
+        match hit_record.prim_index {
+            0 => Vec2::new(azimuth_u(p), p.y / self.height),
+            _ => cap_uv(p, self.radius),
+        }
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let p = self.frame.point_to_local(hit_record.p);
+        self.frame.tangents_to_world(match hit_record.prim_index {
+            0 => {
+                let phi = p.z.atan2(p.x);
+                let dpdv = Vec3::new(
+                    -self.radius * phi.cos(),
+                    self.height,
+                    -self.radius * phi.sin(),
+                );
+                (azimuth_dpdu(p), dpdv)
+            }
+            _ => cap_tangents(self.radius),
+        })
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let extent = disk_extent(self.apex - self.base, self.radius);
+        Aabb::new(
+            (self.base - extent).min(self.apex),
+            (self.base + extent).max(self.apex),
+        )
+    }
+}
+
+// a flat circle facing along normal
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub material: Box<dyn Material>,
+    frame: Frame,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: Box<dyn Material>) -> Disk {
+        Disk {
+            center,
+            normal: normal.normalize(),
+            radius,
+            material,
+            frame: Frame::along(center, normal),
+        }
+    }
+}
+
+impl Shape for Disk {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (local_ray, skip) = self.frame.ray_to_local(ray);
+        let t = cap_hit(&local_ray, 0.0, self.radius)?;
+        if t < ray_tmin || t > ray_tmax - skip {
+            return None;
+        }
+        let hit = cap_local_hit(&local_ray, t, 0.0, Vec3::Y, 0);
+        Some(self.frame.hit_to_world(ray, skip, hit))
+    }
+
+    // planar, the disk fills the middle of [0, 1]
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        cap_uv(self.frame.point_to_local(hit_record.p), self.radius)
+    }
+
+    fn get_hit_tangents(&self, _hit_record: &HitRecord) -> (Vec3, Vec3) {
+        self.frame.tangents_to_world(cap_tangents(self.radius))
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let extent = disk_extent(self.normal, self.radius);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+// a ring around axis, major_radius from the center to the middle of the tube
+pub struct Torus {
+    pub center: Vec3,
+    pub axis: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub material: Box<dyn Material>,
+    frame: Frame,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+        material: Box<dyn Material>,
+    ) -> Torus {
+        Torus {
+            center,
+            axis: axis.normalize(),
+            major_radius,
+            minor_radius,
+            material,
+            frame: Frame::along(center, axis),
+        }
+    }
+
+    fn local_hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<LocalHit> {
+        let (big_r, small_r) = (self.major_radius as f64, self.minor_radius as f64);
+
+        // start from where the ray meets the bounding sphere, the quartic is
+        // much better behaved near the torus than far from it
+        let bound = self.major_radius + self.minor_radius;
+        let half_b = ray.origin.dot(ray.dir);
+        let c = ray.origin.length_squared() - bound * bound;
+        let discriminant = half_b * half_b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let start = (-half_b - discriminant.sqrt()).max(0.0);
+
+        let o = ray.at(start).as_dvec3();
+        let d = ray.dir.as_dvec3();
+        let dd = d.length_squared();
+        let k = o.dot(d);
+        let m = o.length_squared() + big_r * big_r - small_r * small_r;
+        let ring = 4.0 * big_r * big_r;
+        let coefficients = [
+            4.0 * k / dd,
+            (4.0 * k * k + 2.0 * dd * m - ring * (d.x * d.x + d.z * d.z)) / (dd * dd),
+            (4.0 * k * m - 2.0 * ring * (o.x * d.x + o.z * d.z)) / (dd * dd),
+            (m * m - ring * (o.x * o.x + o.z * o.z)) / (dd * dd),
+        ];
+
+        let (roots, num_roots) = solve_quartic(coefficients);
+        let t = roots[..num_roots]
+            .iter()
+            .filter(|&&root| root > 0.0)
+            .map(|&root| start + root as f32)
+            .filter(|&t| t >= ray_tmin && t <= ray_tmax)
+            .min_by(|a, b| a.total_cmp(b))?;
+
+        // back onto the tube, around the nearest point of the ring
+        let p = ray.at(t);
+        let rho = (p.x * p.x + p.z * p.z).sqrt().max(f32::MIN_POSITIVE);
+        let ring_point = Vec3::new(p.x, 0.0, p.z) * (self.major_radius / rho);
+        let normal = (p - ring_point).normalize();
+        let p = ring_point + normal * self.minor_radius;
+        Some(LocalHit {
+            t,
+            p,
+            p_error: gamma(8) * (ring_point.abs() + (p - ring_point).abs()),
+            normal,
+            part: 0,
+        })
+    }
+}
+
+impl Shape for Torus {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (local_ray, skip) = self.frame.ray_to_local(ray);
+        let hit = self.local_hit(&local_ray, ray_tmin, ray_tmax - skip)?;
+        Some(self.frame.hit_to_world(ray, skip, hit))
+    }
+
+    // u goes around the axis, v around the tube starting from its inner edge
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let p = self.frame.point_to_local(hit_record.p);
+        let rho = (p.x * p.x + p.z * p.z).sqrt();
+        let v = (p.y.atan2(rho - self.major_radius) + PI) / (2.0 * PI);
+        Vec2::new(azimuth_u(p), v)
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let p = self.frame.point_to_local(hit_record.p);
+        let rho = (p.x * p.x + p.z * p.z).sqrt().max(f32::MIN_POSITIVE);
+        let dpdv = Vec3::new(-p.y * p.x / rho, rho - self.major_radius, -p.y * p.z / rho);
+        self.frame
+            .tangents_to_world((azimuth_dpdu(p), dpdv * (2.0 * PI)))
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let extent = disk_extent(self.axis, self.major_radius + self.minor_radius)
+            + self.axis.abs() * self.minor_radius;
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+// real roots of t^4 + b t^3 + c t^2 + d t + e by Ferrari's method, each
+// polished with a few newton steps on the quartic itself
+fn solve_quartic([b, c, d, e]: [f64; 4]) -> ([f64; 4], usize) {
+    // depressed, t = y - b / 4
+    let shift = b / 4.0;
+    let p = c - 6.0 * shift * shift;
+    let q = d - 2.0 * c * shift + 8.0 * shift * shift * shift;
+    let r = e - d * shift + c * shift * shift - 3.0 * shift * shift * shift * shift;
+
+    let mut roots = [0.0; 4];
+    let mut num_roots = 0;
+    let mut push = |y: f64| {
+        roots[num_roots] = y - shift;
+        num_roots += 1;
+    };
+
+    if q.abs() < 1e-12 * (1.0 + p.abs() + r.abs()) {
+        // biquadratic, a quadratic in y^2
+        for z in solve_quadratic_f64(1.0, p, r).into_iter().flatten() {
+            if z >= 0.0 {
+                push(z.sqrt());
+                push(-z.sqrt());
+            }
+        }
+    } else {
+        // splits into two quadratics through a positive root of the resolvent cubic
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return (roots, 0);
+        }
+        let s = (2.0 * m).sqrt();
+        for (sign, offset) in [(-1.0, q / (2.0 * s)), (1.0, -q / (2.0 * s))] {
+            for y in solve_quadratic_f64(1.0, sign * s, p / 2.0 + m + offset)
+                .into_iter()
+                .flatten()
+            {
+                push(y);
+            }
+        }
+    }
+
+    for root in roots.iter_mut().take(num_roots) {
+        for _ in 0..3 {
+            let t = *root;
+            let f = (((t + b) * t + c) * t + d) * t + e;
+            let df = ((4.0 * t + 3.0 * b) * t + 2.0 * c) * t + d;
+            if df == 0.0 {
+                break;
+            }
+            *root = t - f / df;
+        }
+    }
+    (roots, num_roots)
+}
+
+fn solve_quadratic_f64(a: f64, b: f64, c: f64) -> [Option<f64>; 2] {
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return [None, None];
+    }
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    if q == 0.0 {
+        return [Some(0.0), None];
+    }
+    [Some(q / a), Some(c / q)]
+}
+
+// of m^3 + a m^2 + b m + c
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // depressed, m = x - a / 3
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    let x = if discriminant > 0.0 {
+        let sqrt_d = discriminant.sqrt();
+        (-q / 2.0 + sqrt_d).cbrt() + (-q / 2.0 - sqrt_d).cbrt()
+    } else {
+        // three real roots, the first of the trigonometric ones is the largest
+        let radius = (-p / 3.0).sqrt();
+        let angle = if radius > 0.0 {
+            (-q / (2.0 * radius * radius * radius))
+                .clamp(-1.0, 1.0)
+                .acos()
+        } else {
+            0.0
+        };
+        2.0 * radius * (angle / 3.0).cos()
+    };
+
+    let mut m = x - a / 3.0;
+    for _ in 0..2 {
+        let f = ((m + a) * m + b) * m + c;
+        let df = (3.0 * m + 2.0 * a) * m + b;
+        if df == 0.0 {
+            break;
+        }
+        m -= f / df;
+    }
+    m
+}
+
+// a cylinder with round ends. part 0 is the side, 1 the end at a and 2 the end at b
+pub struct Capsule {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub radius: f32,
+    pub material: Box<dyn Material>,
+    height: f32,
+    frame: Frame,
+}
+
+impl Capsule {
+    pub fn new(a: Vec3, b: Vec3, radius: f32, material: Box<dyn Material>) -> Capsule {
+        Capsule {
+            a,
+            b,
+            radius,
+            material,
+            height: a.distance(b),
+            frame: Frame::along(a, b - a),
+        }
+    }
+
+    fn local_hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<LocalHit> {
+        let (o, d) = (ray.origin, ray.dir);
+        let r2 = self.radius * self.radius;
+        let mut candidates = [(f32::NEG_INFINITY, 0); 6];
+
+        let a = d.x * d.x + d.z * d.z;
+        let half_b = o.x * d.x + o.z * d.z;
+        let c = o.x * o.x + o.z * o.z - r2;
+        let c_error = gamma(3) * (o.x * o.x + o.z * o.z + r2);
+        if let Some((near, far)) = solve_quadratic(a, half_b, c, c_error) {
+            let on_side = |t: f32| (0.0..=self.height).contains(&ray.at(t).y);
+            candidates[0] = (
+                if on_side(near) {
+                    near
+                } else {
+                    f32::NEG_INFINITY
+                },
+                0,
+            );
+            candidates[1] = (if on_side(far) { far } else { f32::NEG_INFINITY }, 0);
+        }
+
+        // only the half of each end sphere beyond the side counts
+        for (part, center_y) in [(1, 0.0), (2, self.height)] {
+            let to_end = o - Vec3::Y * center_y;
+            let c = to_end.length_squared() - r2;
+            let c_error = gamma(3) * (to_end.length_squared() + r2);
+            if let Some((near, far)) =
+                solve_quadratic(d.length_squared(), to_end.dot(d), c, c_error)
+            {
+                let beyond = |t: f32| {
+                    let y = ray.at(t).y;
+                    if part == 1 {
+                        y <= 0.0
+                    } else {
+                        y >= self.height
+                    }
+                };
+                let slot = 2 * part;
+                candidates[slot] = (
+                    if beyond(near) {
+                        near
+                    } else {
+                        f32::NEG_INFINITY
+                    },
+                    part,
+                );
+                candidates[slot + 1] = (if beyond(far) { far } else { f32::NEG_INFINITY }, part);
+            }
+        }
+
+        let (t, part) = nearest(&candidates, ray_tmin, ray_tmax)?;
+        // back onto the surface around the nearest point of the segment
+        let p = ray.at(t);
+        let spine = Vec3::new(0.0, p.y.clamp(0.0, self.height), 0.0);
+        let normal = (p - spine).normalize();
+        let p = spine + normal * self.radius;
+        Some(LocalHit {
+            t,
+            p,
+            p_error: gamma(6) * (spine.abs() + (p - spine).abs()),
+            normal,
+            part,
+        })
+    }
+
+    // v is by distance along the outline, from the far end of a to the far end of b
+    fn outline_length(&self) -> f32 {
+        self.height + PI * self.radius
+    }
+}
+
+impl Shape for Capsule {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (local_ray, skip) = self.frame.ray_to_local(ray);
+        let hit = self.local_hit(&local_ray, ray_tmin, ray_tmax - skip)?;
+        Some(self.frame.hit_to_world(ray, skip, hit))
+    }
+
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let p = self.frame.point_to_local(hit_record.p);
+        let quarter = 0.5 * PI * self.radius;
+        let distance = match hit_record.prim_index {
+            0 => quarter + p.y,
+            1 => self.radius * (-p.y / self.radius).clamp(-1.0, 1.0).acos(),
+            _ => {
+                let above = (p.y - self.height) / self.radius;
+                quarter + self.height + self.radius * (0.5 * PI - above.clamp(-1.0, 1.0).acos())
+            }
+        };
+        Vec2::new(azimuth_u(p), distance / self.outline_length())
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let p = self.frame.point_to_local(hit_record.p);
+        let spine = Vec3::new(0.0, p.y.clamp(0.0, self.height), 0.0);
+        let normal = (p - spine).normalize_or_zero();
+
+        // up the outline, perpendicular to the normal
+        let along = (Vec3::Y - normal * normal.y).normalize_or_zero();
+        let along = if along == Vec3::ZERO {
+            perpendicular_to(normal)
+        } else {
+            along
+        };
+        self.frame
+            .tangents_to_world((azimuth_dpdu(p), along * self.outline_length()))
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        let extent = Vec3::splat(self.radius);
+        Aabb::new(self.a.min(self.b) - extent, self.a.max(self.b) + extent)
+    }
+}
@@ -0,0 +1,297 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+
+use crate::structures::SurfacePoint;
+
+// seeded lattice noise: improved perlin, simplex and worley cells
+// all share the same permutation table so one seed drives everything
+#[derive(Clone, PartialEq)]
+pub struct NoiseGen {
+    perm: [u8; 512],
+}
+
+impl NoiseGen {
+    pub fn new(seed: u64) -> NoiseGen {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut rng);
+
+        let mut perm = [0u8; 512];
+        for (i, entry) in perm.iter_mut().enumerate() {
+            *entry = table[i & 255];
+        }
+        NoiseGen { perm }
+    }
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> usize {
+        let x = (x & 255) as usize;
+        let y = (y & 255) as usize;
+        let z = (z & 255) as usize;
+        self.perm[self.perm[self.perm[x] as usize + y] as usize + z] as usize
+    }
+
+    // improved perlin noise, roughly in [-1, 1]
+    pub fn perlin(&self, p: Vec3) -> f32 {
+        let cell = p.floor();
+        let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let f = p - cell;
+        let u = Vec3::new(fade(f.x), fade(f.y), fade(f.z));
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let h = self.hash(x + dx, y + dy, z + dz);
+            grad(h, f.x - dx as f32, f.y - dy as f32, f.z - dz as f32)
+        };
+
+        let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), u.x);
+        let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), u.x);
+        let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), u.x);
+        let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), u.x);
+
+        let y0 = lerp(x00, x10, u.y);
+        let y1 = lerp(x01, x11, u.y);
+
+        lerp(y0, y1, u.z)
+    }
+
+    // 3d simplex noise (gustavson), roughly in [-1, 1]
+    pub fn simplex(&self, p: Vec3) -> f32 {
+        const F3: f32 = 1.0 / 3.0;
+        const G3: f32 = 1.0 / 6.0;
+
+        // skew into simplex cell space
+        let s = (p.x + p.y + p.z) * F3;
+        let i = (p.x + s).floor();
+        let j = (p.y + s).floor();
+        let k = (p.z + s).floor();
+        let t = (i + j + k) * G3;
+        let x0 = p - Vec3::new(i - t, j - t, k - t);
+
+        // which of the six simplices we are in
+        let (o1, o2) = if x0.x >= x0.y {
+            if x0.y >= x0.z {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0.x >= x0.z {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if x0.y < x0.z {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0.x < x0.z {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let offset = |o: (i32, i32, i32)| Vec3::new(o.0 as f32, o.1 as f32, o.2 as f32);
+        let corners = [
+            ((0, 0, 0), x0),
+            (o1, x0 - offset(o1) + Vec3::splat(G3)),
+            (o2, x0 - offset(o2) + Vec3::splat(2.0 * G3)),
+            ((1, 1, 1), x0 - Vec3::ONE + Vec3::splat(3.0 * G3)),
+        ];
+
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+        let mut total = 0.0;
+        for (o, d) in corners {
+            let falloff = 0.6 - d.length_squared();
+            if falloff > 0.0 {
+                let h = self.hash(i + o.0, j + o.1, k + o.2);
+                total += falloff.powi(4) * grad(h, d.x, d.y, d.z);
+            }
+        }
+
+        32.0 * total
+    }
+
+    // distances to the nearest and second nearest cell feature points
+    pub fn worley(&self, p: Vec3) -> (f32, f32) {
+        let cell = p.floor();
+        let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+        let mut f1 = f32::INFINITY;
+        let mut f2 = f32::INFINITY;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let h0 = self.hash(x + dx, y + dy, z + dz);
+                    let h1 = self.perm[h0] as usize;
+                    let h2 = self.perm[h1] as usize;
+                    let jitter = Vec3::new(h0 as f32, h1 as f32, h2 as f32) / 255.0;
+
+                    let feature =
+                        Vec3::new((x + dx) as f32, (y + dy) as f32, (z + dz) as f32) + jitter;
+                    let dist = feature.distance(p);
+                    if dist < f1 {
+                        f2 = f1;
+                        f1 = dist;
+                    } else if dist < f2 {
+                        f2 = dist;
+                    }
+                }
+            }
+        }
+
+        (f1, f2)
+    }
+
+    pub fn noise(&self, basis: NoiseBasis, p: Vec3) -> f32 {
+        match basis {
+            NoiseBasis::Perlin => self.perlin(p),
+            NoiseBasis::Simplex => self.simplex(p),
+        }
+    }
+
+    // fractal brownian motion: octaves of noise at rising frequency, falling amplitude
+    pub fn fbm(&self, basis: NoiseBasis, p: Vec3, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut norm = 0.0;
+        for _ in 0..octaves {
+            total += self.noise(basis, p * frequency) * amplitude;
+            norm += amplitude;
+            amplitude *= gain;
+            frequency *= lacunarity;
+        }
+        if norm > 0.0 {
+            total / norm
+        } else {
+            0.0
+        }
+    }
+
+    // like fbm but summing absolute values, giving the creased look marble needs
+    pub fn turbulence(&self, basis: NoiseBasis, p: Vec3, octaves: u32) -> f32 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut norm = 0.0;
+        for _ in 0..octaves {
+            total += self.noise(basis, p * frequency).abs() * amplitude;
+            norm += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        if norm > 0.0 {
+            total / norm
+        } else {
+            0.0
+        }
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// pick one of the 12 cube edge gradients from the low hash bits
+fn grad(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoiseBasis {
+    Perlin,
+    Simplex,
+}
+
+// uv space wraps a pattern onto the surface parameterization,
+// world space carves it out of a solid block (no seams, no stretching)
+#[derive(Clone, Copy, PartialEq)]
+pub enum TextureSpace {
+    Uv,
+    World,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Noise,
+    Fbm { octaves: u32 },
+    Turbulence { octaves: u32 },
+    Marble { octaves: u32, strength: f32 },
+    Wood { rings: f32, strength: f32 },
+    // distance to the nearest cell point
+    Voronoi,
+    // distance between the two nearest cell points, dark along cell borders
+    VoronoiEdges,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct ProceduralTexture {
+    pub pattern: Pattern,
+    pub basis: NoiseBasis,
+    pub space: TextureSpace,
+    pub frequency: f32,
+    noise: NoiseGen,
+}
+
+impl ProceduralTexture {
+    pub fn new(pattern: Pattern, frequency: f32, space: TextureSpace) -> ProceduralTexture {
+        ProceduralTexture {
+            pattern,
+            basis: NoiseBasis::Perlin,
+            space,
+            frequency,
+            noise: NoiseGen::new(0),
+        }
+    }
+
+    pub fn basis(mut self, basis: NoiseBasis) -> Self {
+        self.basis = basis;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.noise = NoiseGen::new(seed);
+        self
+    }
+
+    // pattern value in [0, 1]
+    pub fn value_at(&self, sp: &SurfacePoint) -> f32 {
+        let p = match self.space {
+            // uv lives in the xz plane so wood rings etc look the same in both spaces
+            TextureSpace::Uv => Vec3::new(sp.uv.x, 0.0, sp.uv.y),
+            TextureSpace::World => sp.p,
+        } * self.frequency;
+
+        let value = match self.pattern {
+            Pattern::Noise => 0.5 + 0.5 * self.noise.noise(self.basis, p),
+            Pattern::Fbm { octaves } => {
+                0.5 + 0.5 * self.noise.fbm(self.basis, p, octaves, 2.0, 0.5)
+            }
+            Pattern::Turbulence { octaves } => self.noise.turbulence(self.basis, p, octaves),
+            Pattern::Marble { octaves, strength } => {
+                let turbulence = self.noise.turbulence(self.basis, p, octaves);
+                0.5 + 0.5 * ((p.x + strength * turbulence) * PI).sin()
+            }
+            Pattern::Wood { rings, strength } => {
+                let radius = (p.x * p.x + p.z * p.z).sqrt();
+                let grain = strength * self.noise.noise(self.basis, p);
+                (radius * rings + grain).rem_euclid(1.0)
+            }
+            Pattern::Voronoi => self.noise.worley(p).0,
+            Pattern::VoronoiEdges => {
+                let (f1, f2) = self.noise.worley(p);
+                f2 - f1
+            }
+        };
+
+        value.clamp(0.0, 1.0)
+    }
+}
@@ -1,21 +1,35 @@
+use std::f32::consts::PI;
+
 use either::Either;
 use glam::Mat3;
-use glam::Vec2;
 use indicatif::ProgressIterator;
 use rand::rngs::SmallRng;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 
-use glam::{IVec2, Vec3};
+use glam::{IVec2, Vec2, Vec3, Vec4};
 use indicatif::ParallelProgressIterator;
 use rayon::iter::ParallelIterator;
 use rayon::prelude::*;
 
-use crate::scene::OptimizedScene;
-use crate::utils::random_vector_in_hemisphere;
+use crate::lights::Light;
+use crate::material::HairMaterial;
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::scene::{OptimizedScene, Shutter};
+use crate::structures::{HitRecord, SurfacePoint};
+use crate::utils::{perpendicular_to, random_vector_in_hemisphere};
 use crate::{shapes::Shape, structures::Ray, utils::random_vector_in_unit_disk};
 
 pub const FAUX_LIGHTING_DIFFUSION: bool = true;
 pub const FAUX_LIGHTING_SPECULAR: bool = true;
+// shadow rays towards the environment per shading point
+pub const ENVIRONMENT_SAMPLES: u32 = 2;
+// the environment seen by bounced rays is clamped to this, rough reflections
+// that stumble onto a tiny bright sun would otherwise leave fireflies
+pub const MAX_BOUNCE_ENVIRONMENT_RADIANCE: f32 = 255.0 * 4.0;
+// past this many positioned lights only LIGHT_TREE_SAMPLES of them are picked
+// per shading point, weighted by how much they could contribute
+pub const LIGHT_TREE_THRESHOLD: usize = 16;
+pub const LIGHT_TREE_SAMPLES: u32 = 4;
 
 #[allow(clippy::needless_range_loop)]
 pub fn render_scene(
@@ -28,21 +42,7 @@ pub fn render_scene(
 
     use_progress_bar: bool,
 ) -> Vec<Vec<Vec3>> {
-    let cam = &scene.cam;
-
-    let right = scene.cam.right;
-    let left = -right;
-    let up = scene.cam.up;
-    let down = -up;
-
-    let viewport_dims = scene.cam.viewport_dims;
-
-    let viewport_center = cam.pos + cam.dir * cam.viewport_dist;
-    let target_right_step = right * (cam.viewport_dims.x / resolution.x as f32);
-    let target_down_step = down * (cam.viewport_dims.y / resolution.y as f32);
-
-    let viewport_top_left =
-        viewport_center + left * (viewport_dims.x / 2.0) + up * (viewport_dims.y / 2.0);
+    let (viewport_top_left, target_right_step, target_down_step) = scene.cam.viewport(resolution);
 
     if !multithreaded {
         render_scene_inner(
@@ -98,35 +98,17 @@ pub fn render_scene_inner(
 
     let pixels: Vec<Vec<Vec3>> = row_iter_with_maybe_progress_bar
         .map(|y| {
-            let mut rng = SmallRng::from_seed(rng_seed); //rng.gen::<f32>()
-            let mut row = Vec::with_capacity(resolution.x as usize);
-            for x in 0..resolution.x as usize {
-                let target = viewport_top_left
-                    + (target_right_step * (x as f32))
-                    + (target_down_step * (y as f32));
-                // .progress_count(resolution.y as u64)
-
-                let color = if num_samples_per_pixel == 1 {
-                    let ray = Ray::new(scene.cam.pos, target - scene.cam.pos);
-                    raytrace(&ray, scene, max_bounces, 0, &mut rng)
-                } else {
-                    let mut color = Vec3::ZERO;
-
-                    for _ in 0..num_samples_per_pixel {
-                        let random_offset = random_vector_in_unit_disk(&mut rng);
-                        let scaled_offset = random_offset.x * target_right_step
-                            + random_offset.y * target_down_step;
-                        let starting_position = scene.cam.pos + scaled_offset;
-                        let ray = Ray::new(starting_position, target - scene.cam.pos);
-                        color += raytrace(&ray, scene, max_bounces, 0, &mut rng);
-                    }
-                    color /= num_samples_per_pixel as f32;
-                    color
-                };
-                row.push(color);
-            }
-
-            row
+            render_row(
+                scene,
+                y,
+                resolution,
+                num_samples_per_pixel,
+                max_bounces,
+                row_rng(rng_seed, y),
+                viewport_top_left,
+                target_right_step,
+                target_down_step,
+            )
         })
         .collect(); // Collect rows into a vector of rows
     pixels
@@ -156,40 +138,115 @@ pub fn render_scene_inner_multithreaded(
 
     let pixels: Vec<Vec<Vec3>> = row_iter_with_maybe_progress_bar
         .map(|y| {
-            let mut rng = SmallRng::from_seed(rng_seed); //rng.gen::<f32>()
-            let mut row = Vec::with_capacity(resolution.x as usize);
-            for x in 0..resolution.x as usize {
-                let target = viewport_top_left
-                    + (target_right_step * (x as f32))
-                    + (target_down_step * (y as f32));
-                // .progress_count(resolution.y as u64)
-
-                let color = if num_samples_per_pixel == 1 {
-                    let ray = Ray::new(scene.cam.pos, target - scene.cam.pos);
-                    raytrace(&ray, scene, max_bounces, 0, &mut rng)
-                } else {
-                    let mut color = Vec3::ZERO;
-
-                    for _ in 0..num_samples_per_pixel {
-                        let random_offset = random_vector_in_unit_disk(&mut rng);
-                        let scaled_offset = random_offset.x * target_right_step
-                            + random_offset.y * target_down_step;
-                        let starting_position = scene.cam.pos + scaled_offset;
-                        let ray = Ray::new(starting_position, target - scene.cam.pos);
-                        color += raytrace(&ray, scene, max_bounces, 0, &mut rng);
-                    }
-                    color /= num_samples_per_pixel as f32;
-                    color
-                };
-                row.push(color);
-            }
-
-            row
+            render_row(
+                scene,
+                y,
+                resolution,
+                num_samples_per_pixel,
+                max_bounces,
+                row_rng(rng_seed, y),
+                viewport_top_left,
+                target_right_step,
+                target_down_step,
+            )
         })
         .collect(); // Collect rows into a vector of rows
     pixels
 }
 
+// neighbouring pixels are traced as packets, sample by sample
+#[allow(clippy::too_many_arguments)]
+fn render_row(
+    scene: &OptimizedScene,
+    y: usize,
+    resolution: IVec2,
+    num_samples_per_pixel: u32,
+    max_bounces: u32,
+    mut rng: SmallRng,
+
+    viewport_top_left: Vec3,
+    target_right_step: Vec3,
+    target_down_step: Vec3,
+) -> Vec<Vec3> {
+    // angle one pixel subtends, primary ray cones grow by this per unit distance
+    let pixel_spread = target_right_step.length() / scene.cam.viewport_dist;
+
+    // a moving camera has its own viewport at each ray's time
+    let viewport_at = |time: f32| match scene.cam_end {
+        Some(_) => {
+            let cam = scene.cam_at(time);
+            (cam.pos, cam.viewport(resolution))
+        }
+        None => (
+            scene.cam.pos,
+            (viewport_top_left, target_right_step, target_down_step),
+        ),
+    };
+
+    let width = resolution.x as usize;
+    let mut row = Vec::with_capacity(width);
+    for packet_x in (0..width).step_by(PACKET_SIZE) {
+        let num_lanes = PACKET_SIZE.min(width - packet_x);
+        let target = |lane: usize, (top_left, right_step, down_step): (Vec3, Vec3, Vec3)| {
+            top_left + (right_step * ((packet_x + lane) as f32)) + (down_step * (y as f32))
+        };
+
+        let colors = if num_samples_per_pixel == 1 {
+            let rays: [Ray; PACKET_SIZE] = std::array::from_fn(|lane| {
+                let time = shutter_time(&scene.shutter, 0, 1, &mut rng);
+                let (cam_pos, viewport) = viewport_at(time);
+                Ray::new(cam_pos, target(lane, viewport) - cam_pos)
+                    .with_cone(0.0, pixel_spread)
+                    .with_time(time)
+            });
+            raytrace_packet(&rays[..num_lanes], scene, max_bounces, &mut rng)
+        } else {
+            let mut colors = [Vec3::ZERO; PACKET_SIZE];
+
+            for sample in 0..num_samples_per_pixel {
+                let rays: [Ray; PACKET_SIZE] = std::array::from_fn(|lane| {
+                    let random_offset = random_vector_in_unit_disk(&mut rng);
+                    let time =
+                        shutter_time(&scene.shutter, sample, num_samples_per_pixel, &mut rng);
+                    let (cam_pos, viewport) = viewport_at(time);
+                    let (_, right_step, down_step) = viewport;
+                    let scaled_offset = random_offset.x * right_step + random_offset.y * down_step;
+                    let starting_position = cam_pos + scaled_offset;
+                    Ray::new(starting_position, target(lane, viewport) - cam_pos)
+                        .with_cone(0.0, pixel_spread)
+                        .with_time(time)
+                });
+                let samples = raytrace_packet(&rays[..num_lanes], scene, max_bounces, &mut rng);
+                for (color, sample) in colors.iter_mut().zip(samples) {
+                    *color += sample;
+                }
+            }
+            colors.map(|color| color / num_samples_per_pixel as f32)
+        };
+        row.extend_from_slice(&colors[..num_lanes]);
+    }
+    row
+}
+
+// a time across the shutter for one of a pixel's samples. stratified, so however
+// few samples there are they spread over the whole exposure
+fn shutter_time(shutter: &Shutter, sample: u32, num_samples: u32, rng: &mut SmallRng) -> f32 {
+    if shutter.is_instant() {
+        return 0.0;
+    }
+    (sample as f32 + rng.gen::<f32>()) / num_samples as f32
+}
+
+// every row gets its own stream, identical streams show up as vertical streaks
+// once lighting is sampled
+fn row_rng(rng_seed: [u8; 32], y: usize) -> SmallRng {
+    let mut seed = rng_seed;
+    for (byte, y_byte) in seed.iter_mut().zip((y as u64).to_le_bytes()) {
+        *byte ^= y_byte;
+    }
+    SmallRng::from_seed(seed)
+}
+
 pub fn raytrace(
     ray: &Ray,
     scene: &OptimizedScene,
@@ -201,10 +258,6 @@ pub fn raytrace(
         return Vec3::ZERO;
     }
 
-    let mut shape_hit: Option<&Box<dyn Shape>> = None;
-    let mut closest_hit_record = None;
-    let mut closest_so_far = std::f32::INFINITY;
-
     // old code before bvh was implemented
     // for shape in scene.get_shapes_slice() {
     //     if let Some(hit_record) = shape.hit(ray, 0.001, std::f32::INFINITY) {
@@ -217,40 +270,66 @@ pub fn raytrace(
     // }
 
     // new code using bvh
-    let wrapped_shapes = scene.raycast(ray);
-    for wrapped_shape in wrapped_shapes {
-        let shape = wrapped_shape.get_shape();
-        if let Some(hit_record) = shape.hit(ray, 0.001, std::f32::INFINITY) {
-            if hit_record.t < closest_so_far {
-                shape_hit = Some(shape);
-                closest_so_far = hit_record.t;
-                closest_hit_record = Some(hit_record);
-            }
-        }
+    let hit = scene.closest_hit(ray, 0.0, f32::INFINITY);
+    shade(ray, scene, hit, max_bounces, depth, rng)
+}
+
+// primary rays up to four at a time, they stay together until the first hit
+// and get shaded one by one from there
+pub fn raytrace_packet(
+    rays: &[Ray],
+    scene: &OptimizedScene,
+    max_bounces: u32,
+    rng: &mut SmallRng,
+) -> [Vec3; PACKET_SIZE] {
+    let mut colors = [Vec3::ZERO; PACKET_SIZE];
+    if max_bounces == 0 {
+        return colors;
+    }
+
+    let packet = RayPacket::from_slice(rays);
+    let hits = scene.closest_hit_packet(&packet, 0.0, f32::INFINITY);
+    for ((color, ray), hit) in colors.iter_mut().zip(rays).zip(hits) {
+        *color = shade(ray, scene, hit, max_bounces, 0, rng);
     }
+    colors
+}
 
-    match shape_hit {
-        None => Vec3::ZERO,
-        Some(shape) => {
-            let hit_record = closest_hit_record.unwrap();
+// what the ray sees, given its closest hit
+fn shade(
+    ray: &Ray,
+    scene: &OptimizedScene,
+    hit: Option<(&dyn Shape, HitRecord)>,
+    max_bounces: u32,
+    depth: u32,
+    rng: &mut SmallRng,
+) -> Vec3 {
+    match hit {
+        None if depth > 0 => scene
+            .background(ray.dir)
+            .min(Vec3::splat(MAX_BOUNCE_ENVIRONMENT_RADIANCE)),
+        None => scene.background(ray.dir),
+        Some((shape, hit_record)) => {
             let material = shape.material();
             let mut hit_normal = hit_record.normal;
-            let hit_pos = ray.at(hit_record.t);
-            let uv = shape.get_hit_uv(hit_pos);
+            let footprint = ray.cone_width_at(hit_record.t);
+            let (dpdu, dpdv) = shape.get_hit_tangents(&hit_record);
+            let sp = surface_point(shape, ray, &hit_record, dpdu, dpdv, footprint);
 
             //////// NORMAL MAPPING ////////
-            if material.normal_map_magnitude_multiplier() > 0.0 {
-                let mut sampled_normal = material.normal_at(&uv);
-                sampled_normal = sampled_normal.normalize();
-
-                // Calculate the TBN matrix
-                let tangent = hit_normal.cross(Vec3::Y).normalize();
-                let bitangent = tangent.cross(hit_normal).normalize();
+            let strength = material.normal_map_magnitude_multiplier();
+            if strength > 0.0 {
+                // strength scales the tilt away from the surface normal
+                let sampled_normal = material.normal_at(&sp);
+                let sampled_normal = Vec3::new(
+                    sampled_normal.x * strength,
+                    sampled_normal.y * strength,
+                    sampled_normal.z,
+                );
 
                 // Transform the normal from tangent space to world space
-                let normal_matrix = Mat3::from_cols(tangent, bitangent, hit_normal);
-                hit_normal = normal_matrix * sampled_normal;
-                hit_normal = hit_normal.normalize();
+                let normal_matrix = tangent_frame(hit_normal, dpdu, dpdv);
+                hit_normal = (normal_matrix * sampled_normal).normalize();
             }
 
             let mut color = Vec3::ZERO;
@@ -259,35 +338,39 @@ pub fn raytrace(
             let corrected_normal = if outside { hit_normal } else { -hit_normal };
 
             //////// REFLECTION ////////
-            let reflectiveness = material.reflection_at(&uv);
+            let reflectiveness = material.reflection_at(&sp);
             if reflectiveness > 0.0 {
                 // latest update is bounce_dir is randomly biased towards the hemisphere of the normal
                 let mut bounce_dir =
                     ray.dir - 2.0 * ray.dir.dot(corrected_normal) * corrected_normal;
 
-                let roughness = material.roughness_at(&uv);
+                let roughness = material.roughness_at(&sp);
                 if roughness > 0.0 {
                     // let scattered_bounce_dir = random_vector_in_unit_sphere(rng) * roughness;
                     let scattered_bounce_dir = random_vector_in_hemisphere(corrected_normal, rng);
                     bounce_dir = bounce_dir.lerp(scattered_bounce_dir, roughness);
                 }
 
-                let bounce_ray = Ray::new(hit_pos + bounce_dir * 0.001, bounce_dir);
+                let bounce_ray = hit_record
+                    .spawn_ray(bounce_dir)
+                    .with_cone(footprint, ray.cone_spread + roughness);
                 color += raytrace(&bounce_ray, scene, max_bounces, depth + 1, rng) * reflectiveness;
             }
 
             //////// REFRACTION ////////
-            let refractiveness = material.refraction_at(&uv);
+            let refractiveness = material.refraction_at(&sp);
             if refractiveness > 0.0 {
                 let refracted_dir = refract(
                     ray.dir,
                     corrected_normal,
-                    material.refractive_index_at(&uv),
+                    material.refractive_index_at(&sp),
                     outside,
                 );
 
                 if let Some(refracted_dir) = refracted_dir {
-                    let refracted_ray = Ray::new(hit_pos + refracted_dir * 0.001, refracted_dir);
+                    let refracted_ray = hit_record
+                        .spawn_ray(refracted_dir)
+                        .with_cone(footprint, ray.cone_spread);
                     let refracted_color =
                         raytrace(&refracted_ray, scene, max_bounces, depth + 1, rng);
                     color += refracted_color * refractiveness;
@@ -295,20 +378,81 @@ pub fn raytrace(
             }
 
             //////// DIRECT LIGHTING ////////
-            color += color_at(scene, ray, shape, &hit_pos, &hit_normal, &uv);
+            color += color_at(scene, ray, shape, &hit_record, &hit_normal, &sp, rng);
 
             color
         }
     }
 }
 
+// orthonormal tbn basis, the bitangent keeps dpdv's side so mirrored uvs work
+fn tangent_frame(normal: Vec3, dpdu: Vec3, dpdv: Vec3) -> Mat3 {
+    let mut tangent = (dpdu - normal * normal.dot(dpdu)).normalize_or_zero();
+    if tangent == Vec3::ZERO {
+        tangent = perpendicular_to(normal);
+    }
+    let mut bitangent = normal.cross(tangent);
+    if bitangent.dot(dpdv) < 0.0 {
+        bitangent = -bitangent;
+    }
+    Mat3::from_cols(tangent, bitangent, normal)
+}
+
+// uv and its footprint derivatives: the ray cone is projected onto the surface,
+// stretched along the ray's in-plane direction at grazing angles, then mapped
+// into uv through the shape's dp/du and dp/dv
+fn surface_point(
+    shape: &dyn Shape,
+    ray: &Ray,
+    hit_record: &HitRecord,
+    dpdu: Vec3,
+    dpdv: Vec3,
+    footprint: f32,
+) -> SurfacePoint {
+    let uv = shape.get_hit_uv(hit_record);
+    let mut sp = SurfacePoint::new(uv, hit_record.p);
+    sp.dpdu = dpdu;
+    sp.dpdv = dpdv;
+    if let Some(color) = shape.get_hit_color(hit_record) {
+        sp.vertex_color = color;
+    }
+    if footprint <= 0.0 {
+        return sp;
+    }
+
+    // solve offset = dpdu * du + dpdv * dv in the least squares sense
+    let (a, b, c) = (dpdu.dot(dpdu), dpdu.dot(dpdv), dpdv.dot(dpdv));
+    let det = a * c - b * b;
+    if det <= f32::EPSILON * a * c {
+        return sp;
+    }
+    let delta = |offset: Vec3| {
+        let (pu, pv) = (offset.dot(dpdu), offset.dot(dpdv));
+        Vec2::new(c * pu - b * pv, a * pv - b * pu) / det
+    };
+
+    let normal = hit_record.normal;
+    let cos_theta = normal.dot(ray.dir).abs().max(0.05);
+    let in_plane = ray.dir - normal * ray.dir.dot(normal);
+    let along = if in_plane.length_squared() > 1e-12 {
+        in_plane.normalize()
+    } else {
+        perpendicular_to(normal)
+    };
+    let across = normal.cross(along);
+
+    sp.duv_dx = delta(along * (footprint / cos_theta));
+    sp.duv_dy = delta(across * footprint);
+    sp
+}
+
 fn refract(incident: Vec3, normal: Vec3, refraction_index: f32, outside: bool) -> Option<Vec3> {
     let n = if outside {
         1.0 / refraction_index
     } else {
         refraction_index
     };
-    let cosi = -normal.dot(incident).min(1.0).max(-1.0);
+    let cosi = -normal.dot(incident).clamp(-1.0, 1.0);
     let sin_t2 = n * n * (1.0 - cosi * cosi);
 
     if sin_t2 > 1.0 {
@@ -321,35 +465,122 @@ fn refract(incident: Vec3, normal: Vec3, refraction_index: f32, outside: bool) -
 pub fn color_at(
     scene: &OptimizedScene,
     ray: &Ray,
-    shape_hit: &Box<dyn Shape>,
-    hit_pos: &Vec3,
+    shape_hit: &dyn Shape,
+    hit_record: &HitRecord,
     hit_normal: &Vec3,
-    uv: &Vec2,
+    sp: &SurfacePoint,
+    rng: &mut SmallRng,
 ) -> Vec3 {
     let material = shape_hit.material();
+    let hit_pos = &hit_record.p;
+    let hair = material.hair();
+    let tangent = sp.dpdu.normalize_or_zero();
 
     // Ambient lighting
-    let mut color = material.color_at(uv) * material.ambient_at(uv);
+    let mut color = material.color_at(sp) * material.ambient_at(sp);
+
+    // Environment lighting, importance sampled. radiance 255 all around lights
+    // a surface the same as a head on white light
+    if let Some(environment) = &scene.environment {
+        let diffuse = material.diffuse_at(sp);
+        if diffuse > 0.0 {
+            let mut samples = [(Vec3::ZERO, 0.0); ENVIRONMENT_SAMPLES as usize];
+            let mut num_samples = 0;
+            for _ in 0..ENVIRONMENT_SAMPLES {
+                let (dir, pdf) = environment.sample(rng);
+                if pdf <= 0.0 {
+                    continue;
+                }
+                // hair is lit from every side, not just above its ribbon
+                let falloff = match hair {
+                    Some(_) => HairMaterial::diffuse_falloff(tangent, dir),
+                    None => hit_normal.dot(dir),
+                };
+                if falloff <= 0.0 {
+                    continue;
+                }
+                samples[num_samples] = (dir, falloff / (pdf * PI));
+                num_samples += 1;
+            }
 
-    for light in &scene.lights {
-        let to_light = (light.pos - *hit_pos).normalize();
-        let to_cam = (scene.cam.pos - *hit_pos).normalize();
+            // all the shadow rays leave the same point, so they go out as packets
+            let mut irradiance = Vec3::ZERO;
+            for packet_samples in samples[..num_samples].chunks(PACKET_SIZE) {
+                let rays: [Ray; PACKET_SIZE] = std::array::from_fn(|lane| {
+                    let (dir, _) = packet_samples[lane.min(packet_samples.len() - 1)];
+                    hit_record.spawn_ray(dir)
+                });
+                let packet = RayPacket::new(rays);
+                let occluded = scene
+                    .occluded_packet(&packet, 0.0, Vec4::INFINITY)
+                    .bitmask();
+                for (lane, &(dir, weight)) in packet_samples.iter().enumerate() {
+                    if occluded & (1 << lane) == 0 {
+                        irradiance += environment.radiance(dir) * weight;
+                    }
+                }
+            }
+            irradiance /= ENVIRONMENT_SAMPLES as f32 * 255.0;
+            color += material.color_at(sp) * diffuse * irradiance;
+        }
+    }
+
+    let to_cam = (scene.cam_at(ray.time).pos - *hit_pos).normalize();
+    let light_contribution = |light: &dyn Light, weight: f32, rng: &mut SmallRng| {
+        let sample = light.sample(*hit_pos, rng);
+        let light_color = light.evaluate(*hit_pos, sample.dir) * weight;
+        if light_color == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+        if scene.light_shadows {
+            let shadow_ray = hit_record.spawn_ray(sample.dir);
+            if scene.occluded(&shadow_ray, 0.0, sample.distance) {
+                return Vec3::ZERO;
+            }
+        }
+
+        let to_light = sample.dir;
+        if let Some(hair) = hair {
+            return light_color * hair.evaluate(material.color_at(sp), tangent, to_light, to_cam);
+        }
+        let mut color = Vec3::ZERO;
 
         // Diffuse lighting
         if FAUX_LIGHTING_DIFFUSION {
-            color += material.color_at(uv)
-                * material.diffuse_at(uv)
+            color += material.color_at(sp)
+                * material.diffuse_at(sp)
+                * (light_color / 255.0)
                 * f32::max(hit_normal.dot(to_light), 0.0);
         }
 
         if FAUX_LIGHTING_SPECULAR {
             // Specular lighting
             let halfway = (to_light + to_cam).normalize();
-            color += light.color
-                * material.specular_at(uv)
+            color += light_color
+                * material.specular_at(sp)
                 * f32::max(hit_normal.dot(halfway), 0.0).powi(30);
         }
-    }
+        color
+    };
 
+    let light_tree = &scene.light_tree;
+    if light_tree.num_positioned_lights() <= LIGHT_TREE_THRESHOLD {
+        for light in &scene.lights {
+            color += light_contribution(light.as_ref(), 1.0, rng);
+        }
+    } else {
+        for &index in light_tree.infinite_lights() {
+            color += light_contribution(scene.lights[index].as_ref(), 1.0, rng);
+        }
+        for _ in 0..LIGHT_TREE_SAMPLES {
+            let u = rng.gen::<f32>();
+            if let Some((index, pdf)) =
+                light_tree.sample(*hit_pos, hair.is_none().then_some(*hit_normal), u)
+            {
+                let weight = 1.0 / (pdf * LIGHT_TREE_SAMPLES as f32);
+                color += light_contribution(scene.lights[index].as_ref(), weight, rng);
+            }
+        }
+    }
     color
 }
@@ -1,11 +1,20 @@
-use bvh::{bvh::BVH, Point3, Vector3};
-use glam::{Vec2, Vec3};
+use std::sync::Arc;
+
+use glam::{BVec4A, IVec2, Vec2, Vec3, Vec4};
 
 use crate::{
+    bvh::{Bounded, Bvh},
+    environment::Environment,
+    error::Result,
     generate::{ProceduralSceneModifier, SceneModifier},
+    light_tree::LightTree,
+    lights::Light,
+    material::Material,
+    mesh::{Displacement, Mesh},
+    packet::{PacketHits, RayPacket, PACKET_SIZE},
     shape_bvh_node::ShapeBVHNodeWrapper,
-    shapes::Shape,
-    structures::Light,
+    shapes::{Shape, TrisGeometry, TrisModel},
+    structures::{HitRecord, Ray},
 }; // Rng trait provides methods for random number generation
 
 #[derive(Clone, Copy)]
@@ -19,7 +28,7 @@ pub struct Cam {
 }
 
 impl Cam {
-    pub fn new(scale: f32, viewport_aspect_ratio: f32) -> Cam {
+    pub fn new(scale: f32, _viewport_aspect_ratio: f32) -> Cam {
         // viewport is 1 meter wide at scale 1.0
         // viewport height depends on the render aspect ratio
         Cam {
@@ -39,13 +48,77 @@ impl Cam {
         self.right = self.dir.cross(world_up).normalize();
         self.up = self.right.cross(self.dir).normalize();
     }
+
+    // part way to other, turning rather than sliding the view between them
+    pub fn lerp(&self, other: &Cam, t: f32) -> Cam {
+        let dir = self.dir.lerp(other.dir, t).normalize();
+        let right = dir.cross(self.up.lerp(other.up, t)).normalize();
+        Cam {
+            pos: self.pos.lerp(other.pos, t),
+            dir,
+            up: right.cross(dir).normalize(),
+            right,
+            viewport_dist: self.viewport_dist + (other.viewport_dist - self.viewport_dist) * t,
+            viewport_dims: self.viewport_dims.lerp(other.viewport_dims, t),
+        }
+    }
+
+    // the viewport's top left corner and the steps one pixel right and one down
+    pub fn viewport(&self, resolution: IVec2) -> (Vec3, Vec3, Vec3) {
+        let viewport_center = self.pos + self.dir * self.viewport_dist;
+        let top_left = viewport_center - self.right * (self.viewport_dims.x / 2.0)
+            + self.up * (self.viewport_dims.y / 2.0);
+        (
+            top_left,
+            self.right * (self.viewport_dims.x / resolution.x as f32),
+            -self.up * (self.viewport_dims.y / resolution.y as f32),
+        )
+    }
+}
+
+// when a frame's exposure opens and closes, in frames from the frame itself.
+// procedural mods place moving instances and cameras at both ends and rays are
+// spread between them. open and close equal is an instant, nothing blurs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl Shutter {
+    pub fn new(open: f32, close: f32) -> Shutter {
+        Shutter { open, close }
+    }
+
+    pub fn instant() -> Shutter {
+        Shutter::new(0.0, 0.0)
+    }
+
+    pub fn is_instant(&self) -> bool {
+        self.open == self.close
+    }
+
+    // the frame's time as the shutter opens and as it closes, in frames
+    pub fn frame_times(&self, frame: u32) -> (f32, f32) {
+        (frame as f32 + self.open, frame as f32 + self.close)
+    }
 }
 
 pub struct Scene {
     pub scale: f32,
     pub cam: Cam,
-    pub lights: Vec<Light>,
+    // where the camera is as the shutter closes, when it moves during the exposure
+    pub cam_end: Option<Cam>,
+    pub shutter: Shutter,
+    pub lights: Vec<Box<dyn Light>>,
+    // off by default, lights sitting inside light_ball style glowing spheres
+    // would shadow everything
+    pub light_shadows: bool,
     pub shapes: Vec<Box<dyn Shape>>,
+    // tessellated into TrisModels by optimize
+    pub displaced_meshes: Vec<(Mesh, Displacement, Box<dyn Material>)>,
+    // seen by escaping rays, black when there is none
+    pub environment: Option<Box<dyn Environment>>,
 }
 
 impl Scene {
@@ -53,12 +126,17 @@ impl Scene {
         Scene {
             scale,
             cam,
+            cam_end: None,
+            shutter: Shutter::instant(),
             lights: vec![],
+            light_shadows: false,
             shapes: vec![],
+            displaced_meshes: vec![],
+            environment: None,
         }
     }
 
-    pub fn add_light(&mut self, light: Light) {
+    pub fn add_light(&mut self, light: Box<dyn Light>) {
         self.lights.push(light);
     }
 
@@ -66,45 +144,266 @@ impl Scene {
         self.shapes.push(shape);
     }
 
-    pub fn optimize(mut self) -> OptimizedScene {
-        let mut wrapped_shapes: Vec<ShapeBVHNodeWrapper> = self
+    // trace a shadow ray towards every light sample
+    pub fn set_light_shadows(&mut self, light_shadows: bool) {
+        self.light_shadows = light_shadows;
+    }
+
+    pub fn set_environment(&mut self, environment: Box<dyn Environment>) {
+        self.environment = Some(environment);
+    }
+
+    // the camera as the shutter opens and as it closes. with an instant shutter
+    // only start is used
+    pub fn set_moving_cam(&mut self, start: Cam, end: Cam) {
+        self.cam = start;
+        self.cam_end = (!self.shutter.is_instant()).then_some(end);
+    }
+
+    pub fn add_displaced_mesh(
+        &mut self,
+        mesh: Mesh,
+        displacement: Displacement,
+        material: Box<dyn Material>,
+    ) {
+        self.displaced_meshes.push((mesh, displacement, material));
+    }
+
+    pub fn optimize(self) -> OptimizedScene {
+        self.optimize_reusing(None, Vec::new())
+    }
+
+    // for the next frame of an animation. when the shapes line up one to one with
+    // the previous frame's, its top level bvh is refit rather than rebuilt, and
+    // displaced meshes that didn't change keep their baked geometry
+    pub fn optimize_from(self, previous: OptimizedScene) -> OptimizedScene {
+        self.optimize_reusing(Some(previous.bvh), previous.displaced)
+    }
+
+    fn optimize_reusing(
+        mut self,
+        previous_bvh: Option<Bvh>,
+        previous_displaced: Vec<BakedDisplacement>,
+    ) -> OptimizedScene {
+        let mut displaced = Vec::with_capacity(self.displaced_meshes.len());
+        for (mesh, displacement, material) in self.displaced_meshes.drain(..) {
+            let geometry = match previous_displaced
+                .iter()
+                .find(|(m, d, _)| *m == mesh && *d == displacement)
+            {
+                Some((_, _, geometry)) => geometry.clone(),
+                None => Arc::new(TrisGeometry::from_mesh(
+                    &mesh.displaced(&displacement),
+                    false,
+                )),
+            };
+            self.shapes.push(Box::new(TrisModel {
+                geometry: geometry.clone(),
+                material,
+            }));
+            displaced.push((mesh, displacement, geometry));
+        }
+
+        let (wrapped_shapes, unbounded): (Vec<_>, Vec<_>) = self
             .shapes
             .drain(..)
             .map(ShapeBVHNodeWrapper::new)
+            .partition(|wrapped_shape| wrapped_shape.aabb().is_finite());
+        let unbounded = unbounded
+            .into_iter()
+            .map(ShapeBVHNodeWrapper::into_shape)
             .collect();
-        let bvh = BVH::build(&mut wrapped_shapes);
+        let bvh = match previous_bvh {
+            Some(mut bvh) if bvh.num_primitives() == wrapped_shapes.len() => {
+                bvh.refit(&wrapped_shapes);
+                if bvh.degraded() {
+                    Bvh::build(&wrapped_shapes)
+                } else {
+                    bvh
+                }
+            }
+            _ => Bvh::build(&wrapped_shapes),
+        };
+        let light_tree = LightTree::new(&self.lights);
 
         OptimizedScene {
             scale: self.scale,
             cam: self.cam,
-            lights: self.lights.clone(),
+            cam_end: self.cam_end,
+            shutter: self.shutter,
+            lights: self.lights,
+            light_shadows: self.light_shadows,
+            light_tree,
+            environment: self.environment,
             wrapped_shapes,
             bvh,
+            unbounded,
+            displaced,
         }
     }
 }
 
+// a displaced mesh as the scene gave it and the geometry that was baked from it
+type BakedDisplacement = (Mesh, Displacement, Arc<TrisGeometry>);
+
 pub struct OptimizedScene {
     pub scale: f32,
     pub cam: Cam,
-    pub lights: Vec<Light>,
+    pub cam_end: Option<Cam>,
+    pub shutter: Shutter,
+    pub lights: Vec<Box<dyn Light>>,
+    pub light_shadows: bool,
+    // for picking a few of many lights instead of visiting them all
+    pub light_tree: LightTree,
+    pub environment: Option<Box<dyn Environment>>,
     wrapped_shapes: Vec<ShapeBVHNodeWrapper>,
-    pub bvh: BVH,
+    pub bvh: Bvh,
+    // planes and the like, their boxes would swallow the whole bvh so every
+    // ray tests them directly
+    unbounded: Vec<Box<dyn Shape>>,
+    // kept for the next frame, see optimize_from
+    displaced: Vec<BakedDisplacement>,
 }
 
 impl OptimizedScene {
-    pub fn raycast(&self, ray: &crate::structures::Ray) -> Vec<&ShapeBVHNodeWrapper> {
-        let bvh_ray: bvh::ray::Ray = bvh::ray::Ray::new(
-            Point3::new(ray.origin.x, ray.origin.y, ray.origin.z),
-            Vector3::new(ray.dir.x, ray.dir.y, ray.dir.z),
-        );
-        self.bvh.traverse(&bvh_ray, &self.wrapped_shapes)
+    // the camera at a ray's time
+    pub fn cam_at(&self, time: f32) -> Cam {
+        match &self.cam_end {
+            Some(cam_end) => self.cam.lerp(cam_end, time),
+            None => self.cam,
+        }
+    }
+
+    // nearest shape the ray hits within the range
+    pub fn closest_hit(
+        &self,
+        ray: &Ray,
+        ray_tmin: f32,
+        ray_tmax: f32,
+    ) -> Option<(&dyn Shape, HitRecord)> {
+        let mut closest = None;
+        let mut closest_so_far = ray_tmax;
+        for shape in &self.unbounded {
+            if let Some(hit_record) = shape.hit(ray, ray_tmin, closest_so_far) {
+                closest_so_far = hit_record.t;
+                closest = Some((shape.as_ref(), hit_record));
+            }
+        }
+
+        // the unbounded hit shortens the range, so anything the bvh finds is nearer
+        self.bvh
+            .closest_hit(ray, ray_tmin, closest_so_far, |index, closest_so_far| {
+                self.wrapped_shapes[index]
+                    .get_shape()
+                    .hit(ray, ray_tmin, closest_so_far)
+            })
+            .map(|(index, hit_record)| (self.wrapped_shapes[index].get_shape(), hit_record))
+            .or(closest)
+            .map(|(shape, mut hit_record)| {
+                // rays spawned from the hit go out at the same time
+                hit_record.time = ray.time;
+                (shape, hit_record)
+            })
+    }
+
+    // true if anything blocks the ray before ray_tmax, for shadow rays
+    pub fn occluded(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> bool {
+        if self
+            .unbounded
+            .iter()
+            .any(|shape| shape.hit(ray, ray_tmin, ray_tmax).is_some())
+        {
+            return true;
+        }
+        self.bvh.any_hit(ray, ray_tmin, ray_tmax, |index| {
+            self.wrapped_shapes[index]
+                .get_shape()
+                .hit(ray, ray_tmin, ray_tmax)
+                .is_some()
+        })
+    }
+
+    // closest_hit for four rays at once
+    pub fn closest_hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: f32,
+    ) -> [Option<(&dyn Shape, HitRecord)>; PACKET_SIZE] {
+        let mut closest: [Option<(&dyn Shape, HitRecord)>; PACKET_SIZE] = Default::default();
+        let mut closest_so_far = Vec4::splat(ray_tmax);
+        for shape in &self.unbounded {
+            let hits = shape.hit_packet(packet, ray_tmin, closest_so_far, BVec4A::splat(true));
+            for (lane, hit) in hits.into_iter().enumerate() {
+                if let Some(hit_record) = hit {
+                    closest_so_far[lane] = hit_record.t;
+                    closest[lane] = Some((shape.as_ref(), hit_record));
+                }
+            }
+        }
+
+        let bvh_hits =
+            self.bvh
+                .closest_hit_packet(packet, ray_tmin, closest_so_far, |index, active, tmax| {
+                    self.wrapped_shapes[index]
+                        .get_shape()
+                        .hit_packet(packet, ray_tmin, tmax, active)
+                });
+        for (lane, hit) in bvh_hits.into_iter().enumerate() {
+            if let Some((index, hit_record)) = hit {
+                closest[lane] = Some((self.wrapped_shapes[index].get_shape(), hit_record));
+            }
+        }
+        for (lane, hit) in closest.iter_mut().enumerate() {
+            if let Some((_, hit_record)) = hit {
+                hit_record.time = packet.rays[lane].time;
+            }
+        }
+        closest
+    }
+
+    // occluded for four shadow rays at once, with their own ranges
+    pub fn occluded_packet(&self, packet: &RayPacket, ray_tmin: f32, ray_tmax: Vec4) -> BVec4A {
+        let mut occluded = BVec4A::splat(false);
+        for shape in &self.unbounded {
+            let hits = shape.hit_packet(packet, ray_tmin, ray_tmax, !occluded);
+            occluded |= any_lane_hit(&hits);
+        }
+
+        // lanes already blocked are done, an empty range keeps the bvh from testing them
+        let ray_tmax = Vec4::select(occluded, Vec4::NEG_INFINITY, ray_tmax);
+        occluded
+            | self
+                .bvh
+                .any_hit_packet(packet, ray_tmin, ray_tmax, |index, active, tmax| {
+                    let hits = self.wrapped_shapes[index]
+                        .get_shape()
+                        .hit_packet(packet, ray_tmin, tmax, active);
+                    any_lane_hit(&hits)
+                })
+    }
+
+    pub fn background(&self, dir: glam::Vec3) -> glam::Vec3 {
+        match &self.environment {
+            Some(environment) => environment.radiance(dir),
+            None => glam::Vec3::ZERO,
+        }
     }
 }
 
+fn any_lane_hit(hits: &PacketHits) -> BVec4A {
+    BVec4A::new(
+        hits[0].is_some(),
+        hits[1].is_some(),
+        hits[2].is_some(),
+        hits[3].is_some(),
+    )
+}
+
 pub struct SceneBuilder {
     pub scale: f32,
     pub cam: Cam,
+    pub shutter: Shutter,
 
     pub scene_modifiers: Vec<SceneModifier>,
     pub procedural_scene_modifiers: Vec<ProceduralSceneModifier>,
@@ -115,6 +414,7 @@ impl SceneBuilder {
         SceneBuilder {
             scale,
             cam: Cam::new(scale, viewport_aspect_ratio),
+            shutter: Shutter::instant(),
             scene_modifiers: Vec::new(),
             procedural_scene_modifiers: Vec::new(),
         }
@@ -128,19 +428,24 @@ impl SceneBuilder {
         self.procedural_scene_modifiers.push(proc_scene_modifier);
     }
 
-    pub fn generate_static(&self) -> Scene {
+    pub fn set_shutter(&mut self, shutter: Shutter) {
+        self.shutter = shutter;
+    }
+
+    pub fn generate_static(&self) -> Result<Scene> {
         self.generate(1, 0)
     }
 
-    pub fn generate(&self, num_frames: u32, frame: u32) -> Scene {
+    pub fn generate(&self, num_frames: u32, frame: u32) -> Result<Scene> {
         let mut scene = Scene::new(self.scale, self.cam);
+        scene.shutter = self.shutter;
 
         for pre_scene_builder in self.scene_modifiers.as_slice() {
-            pre_scene_builder(&mut scene);
+            pre_scene_builder(&mut scene)?;
         }
 
         for psb in self.procedural_scene_modifiers.as_slice() {
-            psb(&mut scene, num_frames, frame);
+            psb(&mut scene, num_frames, frame)?;
         }
 
         // let mut shape_wrappers: Vec<ShapeBVHNodeWrapper> = scene
@@ -148,9 +453,20 @@ impl SceneBuilder {
         //     .into_iter()
         //     .map(|shape| ShapeBVHNodeWrapper::new(shape))
         //     .collect();
-        // let bvh = BVH::build(&mut shape_wrappers);
+        // let bvh = Bvh::build(&shape_wrappers);
 
         // scene.bvh = Some(bvh);
-        scene
+        Ok(scene)
+    }
+
+    // builds the first frame's scene without rendering it, so each file the mods
+    // load is found and decoded up front. what they load stays in the caches
+    // for the real frames. building every frame would double the work of a long
+    // animation, so files only later frames load aren't checked
+    pub fn validate(&self, num_frames: u32) -> Result<()> {
+        if num_frames > 0 {
+            self.generate(num_frames, 0)?;
+        }
+        Ok(())
     }
 }
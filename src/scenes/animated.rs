@@ -1,13 +1,17 @@
 use std::f32::consts::PI;
-use std::sync::Mutex;
+use std::sync::Arc;
 
+use crate::error::Result;
+use crate::heightfield::Heightfield;
+use crate::instance::Instance;
 use crate::material::BasicMaterial;
+use crate::mesh_cache::MeshOptions;
 use crate::scene::Scene;
-use crate::shapes::{Quad, Sphere};
-use glam::Vec3;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use crate::shapes::{Shape, Sphere, TrisModel};
+use crate::sky::{sun_direction_at, PreethamSky};
+use glam::{Affine3A, Quat, Vec2, Vec3};
 
-pub fn pidgeon_camera(scene: &mut Scene, num_frames: u32, frame: u32) {
+pub fn pidgeon_camera(scene: &mut Scene, num_frames: u32, frame: u32) -> Result<()> {
     let start_time = 0.0;
     let end_time = PI * 2.0;
     let interval = (end_time - start_time) / num_frames as f32;
@@ -26,30 +30,51 @@ pub fn pidgeon_camera(scene: &mut Scene, num_frames: u32, frame: u32) {
     );
 
     scene.cam.look_at(center);
+    Ok(())
 }
 
-pub fn orbit_camera(scene: &mut Scene, num_frames: u32, frame: u32) {
+pub fn orbit_camera(scene: &mut Scene, num_frames: u32, frame: u32) -> Result<()> {
     let start_time = 0.0;
     let end_time = PI * 2.0;
     // let end_time = PI * 0.5;
     let interval = (end_time - start_time) / num_frames as f32;
 
-    let t = start_time + frame as f32 * interval;
-
     let center = Vec3::ZERO;
 
     let orbit_offset = scene.scale * 1.5;
 
-    scene.cam.pos = Vec3::new(
-        center.x + t.cos() * orbit_offset,
-        center.y + scene.scale * 0.5,
-        center.z + t.sin() * orbit_offset,
-    );
+    // placed as the shutter opens and as it closes, so the orbit blurs
+    let cam_at = |frame_time: f32| {
+        let t = start_time + frame_time * interval;
+        let mut cam = scene.cam;
+        cam.pos = Vec3::new(
+            center.x + t.cos() * orbit_offset,
+            center.y + scene.scale * 0.5,
+            center.z + t.sin() * orbit_offset,
+        );
+        cam.look_at(center);
+        cam
+    };
+    let (open, close) = scene.shutter.frame_times(frame);
+    let (start, end) = (cam_at(open), cam_at(close));
+    scene.set_moving_cam(start, end);
+    Ok(())
+}
 
-    scene.cam.look_at(center);
+// sunrise to sunset over the animation
+pub fn time_of_day(scene: &mut Scene, num_frames: u32, frame: u32) -> Result<()> {
+    let start_hours = 5.5;
+    let end_hours = 18.5;
+    let interval = (end_hours - start_hours) / num_frames as f32;
+
+    let hours = start_hours + frame as f32 * interval;
+
+    let sky = PreethamSky::new(sun_direction_at(hours, PI / 3.0)).turbidity(3.0);
+    scene.set_environment(Box::new(sky));
+    Ok(())
 }
 
-pub fn interweaved_xbox_spinny(scene: &mut Scene, num_frames: u32, frame: u32) {
+pub fn interweaved_xbox_spinny(scene: &mut Scene, num_frames: u32, frame: u32) -> Result<()> {
     let center = Vec3::ZERO;
     let cam_offset = scene.scale * 0.5;
     scene.cam.pos = Vec3::new(
@@ -65,7 +90,9 @@ pub fn interweaved_xbox_spinny(scene: &mut Scene, num_frames: u32, frame: u32) {
     let end_time = PI * 1.0;
     let interval = (end_time - start_time) / num_frames as f32;
 
-    let t = start_time + frame as f32 * interval;
+    // where the spheres are as the shutter opens and as it closes
+    let (open, close) = scene.shutter.frame_times(frame);
+    let (t_open, t_close) = (start_time + open * interval, start_time + close * interval);
 
     // lets make a sphere go around in a circle around the center of the screen
     let offset = scene.scale / 4.0;
@@ -81,49 +108,49 @@ pub fn interweaved_xbox_spinny(scene: &mut Scene, num_frames: u32, frame: u32) {
             .refraction(0.85)
             .build(),
     );
-    for k in 0..6 {
+    let horizontal_ring = |t: f32, k: u32| {
         let tt = t - (PI / 3.0 * k as f32);
         let offset_x_mod = tt.cos() * offset;
         let offset_y_mod = tt.sin() * offset;
-        let p = scene_center + Vec3::new(offset_x_mod, 0.0, offset_y_mod);
-        let sphere = Sphere {
-            center: p,
-            radius,
-            material: material.clone(),
-            orientation: glam::Quat::IDENTITY,
-        };
-        scene.add_shape(Box::new(sphere));
-    }
+        scene_center + Vec3::new(offset_x_mod, 0.0, offset_y_mod)
+    };
 
-    let scene_center = Vec3::ZERO;
     let shift = Vec3::new(0.0, 0.0, scene.scale / 4.0);
-    for k in 0..6 {
+    let vertical_ring = |t: f32, k: u32| {
         let tt = t - (PI / 3.0 * k as f32);
         let offset_x_mod = tt.cos() * offset;
         let offset_y_mod = tt.sin() * offset;
-        let p = scene_center + Vec3::new(0.0, offset_x_mod, offset_y_mod) + shift;
-        let sphere = Sphere {
-            center: p,
-            radius,
-            material: material.clone(),
-            orientation: glam::Quat::IDENTITY,
-        };
-        scene.add_shape(Box::new(sphere));
+        scene_center + Vec3::new(0.0, offset_x_mod, offset_y_mod) + shift
+    };
+
+    // one sphere, every ball is an instance of it sliding along its ring while
+    // the shutter is open
+    let sphere: Arc<dyn Shape> = Arc::new(Sphere {
+        center: Vec3::ZERO,
+        radius,
+        material,
+        orientation: glam::Quat::IDENTITY,
+    });
+    let rings: [&dyn Fn(f32, u32) -> Vec3; 2] = [&horizontal_ring, &vertical_ring];
+    for ring in rings {
+        for k in 0..6 {
+            scene.add_shape(Box::new(Instance::moving(
+                sphere.clone(),
+                Affine3A::from_translation(ring(t_open, k)),
+                Affine3A::from_translation(ring(t_close, k)),
+            )));
+        }
     }
+    Ok(())
 }
 
-pub fn wave_sheet(scene: &mut Scene, num_frames: u32, frame: u32) {
-    let center = Vec3::ZERO;
-
-    let radius = scene.scale / 12.0;
-
+pub fn wave_sheet(scene: &mut Scene, num_frames: u32, frame: u32) -> Result<()> {
     let start_time = 0.0;
     let end_time = PI * 2.0;
     let interval = (end_time - start_time) / num_frames as f32;
 
     let t = start_time + frame as f32 * interval;
 
-    // were going to make a sheet of quads, lets start with 10x10
     let mat = BasicMaterial::builder()
         .color(Vec3::new(255.0, 255.0, 255.0))
         .ambient(0.00)
@@ -141,48 +168,52 @@ pub fn wave_sheet(scene: &mut Scene, num_frames: u32, frame: u32) {
         (t + x * freq).sin() + (t + y * freq).cos()
     };
 
-    let num = 100;
+    let num = 101;
     let h_scale = scene.scale / 10.0;
-    let quads = Mutex::new(Vec::new());
-
-    (0..num).into_par_iter().for_each(|i| {
-        (0..num).into_par_iter().for_each(|j| {
-            let x = i as f32 / num as f32 - 0.5;
-            let y = j as f32 / num as f32 - 0.5;
-
-            let next_x = (i as f32 + 1.1) / num as f32 - 0.5;
-            let next_y = (j as f32 + 1.1) / num as f32 - 0.5;
-
-            let p = Vec3::new(x * scene.scale, height(x, y) * h_scale, y * scene.scale);
-            let tr = Vec3::new(
-                next_x * scene.scale,
-                height(next_x, y) * h_scale,
-                y * scene.scale,
-            );
-            let bl = Vec3::new(
-                x * scene.scale,
-                height(x, next_y) * h_scale,
-                next_y * scene.scale,
-            );
-            let br = Vec3::new(
-                next_x * scene.scale,
-                height(next_x, next_y) * h_scale,
-                next_y * scene.scale,
-            );
-
-            let mut quads = quads.lock().unwrap();
-            quads.push((p, tr, bl, br));
-        });
-    });
+    scene.add_shape(Box::new(Heightfield::from_fn(
+        Vec3::new(-0.5, 0.0, -0.5) * scene.scale,
+        Vec2::ONE * scene.scale,
+        num,
+        num,
+        |uv| height(uv.x - 0.5, uv.y - 0.5) * h_scale,
+        Box::new(mat),
+    )));
+    Ok(())
+}
 
-    let quads = quads.lock().unwrap();
-    for quad in &*quads {
-        scene.add_shape(Box::new(Quad::new_from_points(
-            quad.0,
-            quad.1,
-            quad.2,
-            quad.3,
-            Box::new(mat.clone()),
-        )));
+// a ring of ducks spinning around the center, each turning on the spot.
+// the model is loaded once and every duck is an instance of it, so frames
+// only move instances around
+pub fn duck_carousel(scene: &mut Scene, num_frames: u32, frame: u32) -> Result<()> {
+    let start_time = 0.0;
+    let end_time = PI * 2.0;
+    let interval = (end_time - start_time) / num_frames as f32;
+
+    let t = start_time + frame as f32 * interval;
+
+    let material = BasicMaterial::builder()
+        .color(Vec3::new(255.0, 220.0, 40.0))
+        .ambient(0.05)
+        .diffuse(0.5)
+        .specular(0.2)
+        .reflection(0.2)
+        .build();
+    let duck: Arc<dyn Shape> = Arc::new(TrisModel::load(
+        "./assets/duck.obj",
+        MeshOptions::default(),
+        Box::new(material),
+    )?);
+
+    let num = 6;
+    let radius = scene.scale * 0.4;
+    for k in 0..num {
+        let angle = t + k as f32 / num as f32 * PI * 2.0;
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::ONE * scene.scale * 0.05,
+            Quat::from_rotation_y(-angle * 2.0),
+            Vec3::new(angle.cos() * radius, 0.0, angle.sin() * radius),
+        );
+        scene.add_shape(Box::new(Instance::new(duck.clone(), transform)));
     }
+    Ok(())
 }
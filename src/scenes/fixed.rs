@@ -1,3 +1,4 @@
+use glam::Affine3A;
 use glam::Quat;
 use glam::Vec2;
 use glam::Vec3;
@@ -5,64 +6,145 @@ use rand::rngs::SmallRng;
 use rand::Rng;
 use rand::SeedableRng;
 use std::f32::consts::PI;
+use std::sync::Arc;
 
+use crate::bvh::Aabb;
+use crate::csg::Csg;
+use crate::curves::{CurveType, Curves, Strand};
+use crate::environment::EnvironmentMap;
+use crate::error::Result;
+use crate::heightfield::Heightfield;
+use crate::instance::Instance;
+use crate::lights::{DirectionalLight, IesLight, IesProfile, PointLight, SpotLight};
+use crate::material::GraphMaterial;
+use crate::material::HairMaterial;
+use crate::material::ProceduralMaterial;
 use crate::material::TexturedMaterial;
 use crate::material::TexturedMaterialWithNormal;
 use crate::material::{BasicMaterial, CheckerMaterial};
+use crate::mesh::{Displacement, Mesh};
+use crate::mesh_cache::MeshOptions;
+use crate::ply::CloudPoint;
+use crate::point_cloud::{PointCloud, SplatType};
+use crate::primitives::{Capsule, Cone, Cuboid, Cylinder, Disk, Torus};
+use crate::procedural::{NoiseBasis, NoiseGen, Pattern, ProceduralTexture, TextureSpace};
 use crate::scene::Scene;
+use crate::sdf::{Sdf, SdfShape};
 use crate::shapes::Quad;
 use crate::shapes::Tri;
 use crate::shapes::TrisModel;
 use crate::shapes::{Plane, Sphere};
-use crate::structures::Light;
+use crate::sky::{sun_direction_at, PreethamSky};
+use crate::subdivision::{Subdivision, SubdivisionScheme};
+use crate::texture::AddressMode;
+use crate::texture_input::TextureInput;
 
-pub fn single_centered_light(scene: &mut Scene) {
-    let light = Light::new(
+pub fn single_centered_light(scene: &mut Scene) -> Result<()> {
+    let light = PointLight::new(
         Vec3::new(0.5, 0.5, 0.5) * scene.scale * 5.0,
         Vec3::new(255.0, 255.0, 255.0),
     );
-    scene.add_light(light);
+    scene.add_light(Box::new(light));
+    Ok(())
 }
 
-pub fn quad_light(scene: &mut Scene) {
+// a dim moonlight fill, a warm spot from the left and a downlight with a
+// photometric profile from the right, all with physical falloff
+pub fn stage_lights(scene: &mut Scene) -> Result<()> {
+    // spots read as spots when what they light casts a shadow
+    scene.set_light_shadows(true);
+
+    scene.add_light(Box::new(
+        DirectionalLight::new(Vec3::new(0.3, -1.0, 0.5), Vec3::new(120.0, 140.0, 255.0))
+            .intensity(0.2),
+    ));
+
+    let spot_pos = Vec3::new(-1.0, 1.5, -0.5) * scene.scale;
+    scene.add_light(Box::new(
+        SpotLight::new(
+            spot_pos,
+            -spot_pos,
+            Vec3::new(255.0, 200.0, 140.0),
+            PI / 16.0,
+            PI / 10.0,
+        )
+        .falloff(spot_pos.length()),
+    ));
+
+    // a wide batwing: dim straight down, brightest around 40 degrees
+    let profile = IesProfile::symmetric(
+        vec![0.0, 20.0, 40.0, 60.0, 80.0, 90.0],
+        vec![400.0, 700.0, 1000.0, 500.0, 50.0, 0.0],
+    );
+    let ies_pos = Vec3::new(1.0, 1.2, 0.0) * scene.scale;
+    scene.add_light(Box::new(
+        IesLight::new(ies_pos, Vec3::new(255.0, 255.0, 255.0), profile)
+            .intensity(2.0)
+            .falloff(ies_pos.y),
+    ));
+    Ok(())
+}
+
+pub fn quad_light(scene: &mut Scene) -> Result<()> {
     let vertical_offset = 10.0 * scene.scale;
     let lateral_offset = 2.0 * scene.scale;
-    scene.add_light(Light::new(
+    scene.add_light(Box::new(PointLight::new(
         Vec3::new(lateral_offset, vertical_offset, lateral_offset),
         Vec3::new(255.0, 255.0, 255.0),
-    ));
-    scene.add_light(Light::new(
+    )));
+    scene.add_light(Box::new(PointLight::new(
         Vec3::new(-lateral_offset, vertical_offset, -lateral_offset),
         Vec3::new(255.0, 255.0, 255.0),
-    ));
-    scene.add_light(Light::new(
+    )));
+    scene.add_light(Box::new(PointLight::new(
         Vec3::new(lateral_offset, vertical_offset, -lateral_offset),
         Vec3::new(255.0, 255.0, 255.0),
-    ));
-    scene.add_light(Light::new(
+    )));
+    scene.add_light(Box::new(PointLight::new(
         Vec3::new(-lateral_offset, vertical_offset, lateral_offset),
         Vec3::new(255.0, 255.0, 255.0),
-    ));
+    )));
+    Ok(())
 }
 
-pub fn some_random_lights(scene: &mut Scene) {
+pub fn some_random_lights(scene: &mut Scene) -> Result<()> {
     let seed = [0u8; 32]; // All zeros
     let mut rng = SmallRng::from_seed(seed); //rng.gen::<f32>()
 
     for _ in 0..3 {
-        let light = Light::new(
+        let light = PointLight::new(
             Vec3::new(
                 (rng.gen::<f32>() - 0.5) * scene.scale,
                 (rng.gen::<f32>() - 0.5) * scene.scale,
                 rng.gen::<f32>() * scene.scale,
             ),
-            Vec3::new(rng.gen(), rng.gen(), rng.gen()),
+            Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 255.0,
         );
-        scene.add_light(light);
+        scene.add_light(Box::new(light));
+    }
+    Ok(())
+}
+
+// a swarm of small colored lights hovering just over the floor, each lighting
+// its own little pool. goes through the light tree rather than every light
+pub fn many_lights(scene: &mut Scene) -> Result<()> {
+    let seed = [0u8; 32]; // All zeros
+    let mut rng = SmallRng::from_seed(seed);
+
+    for _ in 0..2000 {
+        let pos = Vec3::new(
+            (rng.gen::<f32>() - 0.5) * 6.0,
+            -0.95 + rng.gen::<f32>() * 0.1,
+            rng.gen::<f32>() * 6.0,
+        ) * scene.scale;
+        let light = PointLight::new(pos, Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 255.0)
+            .falloff(0.05 * scene.scale);
+        scene.add_light(Box::new(light));
     }
+    Ok(())
 }
 
-pub fn grid_of_balls(scene: &mut Scene) {
+pub fn grid_of_balls(scene: &mut Scene) -> Result<()> {
     let seed = [0u8; 32]; // All zeros
     let mut rng = SmallRng::from_seed(seed);
 
@@ -91,9 +173,10 @@ pub fn grid_of_balls(scene: &mut Scene) {
             }
         }
     }
+    Ok(())
 }
 
-pub fn some_random_balls(scene: &mut Scene) {
+pub fn some_random_balls(scene: &mut Scene) -> Result<()> {
     let seed = [0u8; 32]; // All zeros
     let mut rng = SmallRng::from_seed(seed);
 
@@ -119,35 +202,35 @@ pub fn some_random_balls(scene: &mut Scene) {
         );
         scene.add_shape(Box::new(sphere));
     }
+    Ok(())
 }
 
-pub fn sky_sphere(scene: &mut Scene) {
-    let basic_material = BasicMaterial::builder()
-        .color(Vec3::new(255.0, 255.0, 255.0))
-        .ambient(1.0)
-        .build();
-    let material = TexturedMaterial::new(
+pub fn sky_sphere(scene: &mut Scene) -> Result<()> {
+    let environment = EnvironmentMap::new(
         // "./assets/skysphere.jpg",
         "./assets/envmap.jpg",
-        // Vec2::ONE * 0.6,
-        Vec2::ONE * 1.0,
-        true,
-        basic_material,
-    );
+    )?;
+    scene.set_environment(Box::new(environment));
+    Ok(())
+}
 
-    // center
-    scene.add_shape(Box::new(Sphere::new(
-        Vec3::new(0.0, scene.scale * 20.0, 0.0),
-        // Vec3::ZERO,
-        scene.scale * 100.0,
-        Box::new(material),
-        // shift down
-        Quat::IDENTITY,
-        // Quat::from_rotation_x(std::f32::consts::PI / 6.0),
-    )));
+pub fn hdr_sky(scene: &mut Scene) -> Result<()> {
+    let environment = EnvironmentMap::new("./assets/sky.hdr")?
+        .rotation(Quat::from_rotation_y(PI / 4.0))
+        .intensity(1.0);
+    scene.set_environment(Box::new(environment));
+    Ok(())
+}
+
+pub fn afternoon_sky(scene: &mut Scene) -> Result<()> {
+    let sky = PreethamSky::new(sun_direction_at(15.0, PI / 3.0))
+        .turbidity(3.0)
+        .ground_albedo(Vec3::new(0.3, 0.25, 0.2));
+    scene.set_environment(Box::new(sky));
+    Ok(())
 }
 
-pub fn test_balls(scene: &mut Scene) {
+pub fn test_balls(scene: &mut Scene) -> Result<()> {
     // center
     scene.add_shape(Box::new(Sphere::new(
         Vec3::new(0.0, 0.0, 0.0),
@@ -230,7 +313,7 @@ pub fn test_balls(scene: &mut Scene) {
             // "./assets/kirby.jpg",
             "/home/vega/Coding/Graphics/raytrace-rs/assets/lroc_color_poles_small.tif",
             Vec2::ONE / 1.0,
-            false,
+            AddressMode::Clamp,
             BasicMaterial::builder()
                 .color(Vec3::new(255.0, 255.0, 255.0))
                 .ambient(0.0)
@@ -238,12 +321,13 @@ pub fn test_balls(scene: &mut Scene) {
                 .specular(0.01)
                 .reflection(0.01)
                 .build(),
-        )),
+        )?),
         glam::Quat::IDENTITY,
     )));
+    Ok(())
 }
 
-pub fn infinite_checkered_floor(scene: &mut Scene) {
+pub fn infinite_checkered_floor(scene: &mut Scene) -> Result<()> {
     // a plane
     let basic_material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 0.0, 0.0))
@@ -264,9 +348,10 @@ pub fn infinite_checkered_floor(scene: &mut Scene) {
         Box::new(material),
     );
     scene.add_shape(Box::new(plane));
+    Ok(())
 }
 
-pub fn test_tris(scene: &mut Scene) {
+pub fn test_tris(scene: &mut Scene) -> Result<()> {
     // center
     let a = Vec3::new(0.0, 0.0, 0.0);
     let b = Vec3::new(0.0, 0.0, scene.scale);
@@ -288,10 +373,11 @@ pub fn test_tris(scene: &mut Scene) {
                 // .refraction(0.7)
                 .build(),
         ),
-    )))
+    )));
+    Ok(())
 }
 
-pub fn checkered_floor(scene: &mut Scene) {
+pub fn checkered_floor(scene: &mut Scene) -> Result<()> {
     // a plane
     let basic_material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 0.0, 0.0))
@@ -317,9 +403,10 @@ pub fn checkered_floor(scene: &mut Scene) {
         Box::new(material),
     );
     scene.add_shape(Box::new(plane));
+    Ok(())
 }
 
-pub fn textured_floor(scene: &mut Scene) {
+pub fn textured_floor(scene: &mut Scene) -> Result<()> {
     // a plane
     let basic_material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 0.0, 0.0))
@@ -332,9 +419,9 @@ pub fn textured_floor(scene: &mut Scene) {
         "./assets/kirby.jpg",
         // "/home/vega/Coding/Graphics/raytrace-rs/assets/lroc_color_poles_small.tif",
         Vec2::ONE / 1.0,
-        false,
+        AddressMode::Clamp,
         basic_material,
-    );
+    )?;
 
     let size = scene.scale * 5.0;
 
@@ -346,9 +433,10 @@ pub fn textured_floor(scene: &mut Scene) {
         Box::new(material),
     );
     scene.add_shape(Box::new(plane));
+    Ok(())
 }
 
-pub fn matte_floor(scene: &mut Scene) {
+pub fn matte_floor(scene: &mut Scene) -> Result<()> {
     // a plane
     let material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 0.0, 0.0))
@@ -363,14 +451,15 @@ pub fn matte_floor(scene: &mut Scene) {
         Box::new(material),
     );
     scene.add_shape(Box::new(plane));
+    Ok(())
 }
 
-pub fn scene_4(scene: &mut Scene) {
+pub fn scene_4(scene: &mut Scene) -> Result<()> {
     let scene_center = Vec3::ZERO;
 
     // one light at 000
-    let light = Light::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(255.0, 255.0, 255.0));
-    scene.add_light(light);
+    let light = PointLight::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(255.0, 255.0, 255.0));
+    scene.add_light(Box::new(light));
 
     // lights
     let offset = scene.scale / 2.0;
@@ -381,8 +470,8 @@ pub fn scene_4(scene: &mut Scene) {
         let p = scene_center + Vec3::new(offset_x_mod, 0.0, offset_y_mod);
 
         // make a ring of lights
-        let light = Light::new(p, Vec3::new(1.0, 1.0, 1.0));
-        scene.add_light(light);
+        let light = PointLight::new(p, Vec3::new(255.0, 255.0, 255.0));
+        scene.add_light(Box::new(light));
 
         // make a ring of spheres
         let basic_material = BasicMaterial::builder()
@@ -428,9 +517,10 @@ pub fn scene_4(scene: &mut Scene) {
         glam::Quat::IDENTITY,
     );
     scene.add_shape(Box::new(sphere));
+    Ok(())
 }
 
-pub fn light_ball(scene: &mut Scene) {
+pub fn light_ball(scene: &mut Scene) -> Result<()> {
     let material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 255.0, 255.0) * 10.0)
         .ambient(1.0)
@@ -442,9 +532,10 @@ pub fn light_ball(scene: &mut Scene) {
         glam::Quat::IDENTITY,
     );
     scene.add_shape(Box::new(sphere));
+    Ok(())
 }
 
-pub fn centered_ball_with_normals(scene: &mut Scene) {
+pub fn centered_ball_with_normals(scene: &mut Scene) -> Result<()> {
     let basic_material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 255.0, 255.0))
         .ambient(0.0)
@@ -461,10 +552,10 @@ pub fn centered_ball_with_normals(scene: &mut Scene) {
         "./assets/latlon-normal-map.png",
         // "./assets/skysphere.jpg",
         Vec2::ONE * 1.0,
-        false,
-        0.01,
+        AddressMode::Clamp,
+        1.0,
         basic_material,
-    );
+    )?;
 
     let sphere = Sphere::new(
         Vec3::ZERO,
@@ -473,9 +564,10 @@ pub fn centered_ball_with_normals(scene: &mut Scene) {
         glam::Quat::IDENTITY,
     );
     scene.add_shape(Box::new(sphere));
+    Ok(())
 }
 
-pub fn centered_ball(scene: &mut Scene) {
+pub fn centered_ball(scene: &mut Scene) -> Result<()> {
     let material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 255.0, 255.0))
         .ambient(0.0)
@@ -492,18 +584,504 @@ pub fn centered_ball(scene: &mut Scene) {
         glam::Quat::IDENTITY,
     );
     scene.add_shape(Box::new(sphere));
+    Ok(())
 }
 
-pub fn set_cam(scene: &mut Scene) {
+// one of each analytic primitive in a row on the floor
+pub fn primitives(scene: &mut Scene) -> Result<()> {
+    let material = |color: Vec3| -> Box<BasicMaterial> {
+        Box::new(
+            BasicMaterial::builder()
+                .color(color)
+                .ambient(0.05)
+                .diffuse(0.8)
+                .specular(0.3)
+                .reflection(0.1)
+                .build(),
+        )
+    };
+    let s = scene.scale;
+
+    scene.add_shape(Box::new(Cuboid::new(
+        Vec3::new(-1.5, 0.2, 0.0) * s,
+        Vec3::splat(0.2 * s),
+        material(Vec3::new(230.0, 80.0, 60.0)),
+        Quat::from_rotation_y(PI / 6.0),
+    )));
+    scene.add_shape(Box::new(Cylinder::new(
+        Vec3::new(-0.9, 0.0, 0.0) * s,
+        Vec3::new(-0.9, 0.5, 0.0) * s,
+        0.2 * s,
+        material(Vec3::new(240.0, 170.0, 50.0)),
+    )));
+    scene.add_shape(Box::new(Cone::new(
+        Vec3::new(-0.3, 0.0, 0.0) * s,
+        Vec3::new(-0.3, 0.6, 0.0) * s,
+        0.22 * s,
+        material(Vec3::new(120.0, 200.0, 80.0)),
+    )));
+    scene.add_shape(Box::new(Torus::new(
+        Vec3::new(0.3, 0.3, 0.0) * s,
+        Vec3::new(0.0, 0.5, 1.0),
+        0.2 * s,
+        0.07 * s,
+        material(Vec3::new(60.0, 180.0, 220.0)),
+    )));
+    scene.add_shape(Box::new(Capsule::new(
+        Vec3::new(0.8, 0.15, -0.1) * s,
+        Vec3::new(1.1, 0.45, 0.1) * s,
+        0.15 * s,
+        material(Vec3::new(120.0, 90.0, 230.0)),
+    )));
+    scene.add_shape(Box::new(Disk::new(
+        Vec3::new(1.6, 0.25, 0.0) * s,
+        Vec3::new(-0.5, 0.3, -1.0),
+        0.22 * s,
+        material(Vec3::new(230.0, 90.0, 180.0)),
+    )));
+    Ok(())
+}
+
+// solids built from others: a box with a ball carved out, a lens where two
+// balls overlap, a dome cut by a plane and a capped post
+pub fn csg(scene: &mut Scene) -> Result<()> {
+    let material = |color: Vec3| -> Box<BasicMaterial> {
+        Box::new(
+            BasicMaterial::builder()
+                .color(color)
+                .ambient(0.05)
+                .diffuse(0.8)
+                .specular(0.3)
+                .reflection(0.1)
+                .build(),
+        )
+    };
+    let s = scene.scale;
+
+    let carved_center = Vec3::new(-1.2, 0.25, 0.0) * s;
+    scene.add_shape(Box::new(Csg::difference(
+        Box::new(Cuboid::new(
+            carved_center,
+            Vec3::splat(0.25 * s),
+            material(Vec3::new(230.0, 80.0, 60.0)),
+            Quat::from_rotation_y(PI / 6.0),
+        )),
+        Box::new(Sphere::new(
+            carved_center,
+            0.32 * s,
+            material(Vec3::ZERO),
+            Quat::IDENTITY,
+        )),
+    )));
+
+    let lens_center = Vec3::new(-0.4, 0.3, 0.0) * s;
+    scene.add_shape(Box::new(Csg::intersection(
+        Box::new(Sphere::new(
+            lens_center - Vec3::X * 0.3 * s,
+            0.4 * s,
+            material(Vec3::new(60.0, 180.0, 220.0)),
+            Quat::IDENTITY,
+        )),
+        Box::new(Sphere::new(
+            lens_center + Vec3::X * 0.3 * s,
+            0.4 * s,
+            material(Vec3::ZERO),
+            Quat::IDENTITY,
+        )),
+    )));
+
+    // the plane's back is the inside, so this keeps the ball's top half
+    let dome_center = Vec3::new(0.4, 0.1, 0.0) * s;
+    scene.add_shape(Box::new(Csg::intersection(
+        Box::new(Sphere::new(
+            dome_center,
+            0.3 * s,
+            material(Vec3::new(120.0, 200.0, 80.0)),
+            Quat::IDENTITY,
+        )),
+        Box::new(Plane::new(dome_center, -Vec3::Y, material(Vec3::ZERO))),
+    )));
+
+    scene.add_shape(Box::new(Csg::union(
+        Box::new(Cylinder::new(
+            Vec3::new(1.2, 0.0, 0.0) * s,
+            Vec3::new(1.2, 0.4, 0.0) * s,
+            0.08 * s,
+            material(Vec3::new(240.0, 170.0, 50.0)),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(1.2, 0.45, 0.0) * s,
+            0.15 * s,
+            material(Vec3::ZERO),
+            Quat::IDENTITY,
+        )),
+    )));
+    Ok(())
+}
+
+// distance field shapes: a blob of smoothly merged balls and a ring, and a mandelbulb
+pub fn sdf(scene: &mut Scene) -> Result<()> {
+    let material = |color: Vec3| -> Box<BasicMaterial> {
+        Box::new(
+            BasicMaterial::builder()
+                .color(color)
+                .ambient(0.05)
+                .diffuse(0.8)
+                .specular(0.3)
+                .reflection(0.1)
+                .build(),
+        )
+    };
+    let s = scene.scale;
+
+    let blob_center = Vec3::new(-0.6, 0.35, 0.0) * s;
+    let blob = Sdf::smooth_union(
+        Sdf::smooth_union(
+            Sdf::sphere(0.2 * s),
+            Sdf::sphere(0.12 * s).translate(Vec3::new(0.18, 0.12, 0.0) * s),
+            0.08 * s,
+        ),
+        Sdf::torus(0.25 * s, 0.04 * s).rotate(Quat::from_rotation_x(PI / 3.0)),
+        0.06 * s,
+    )
+    .translate(blob_center);
+    let blob_bounds = Aabb::new(
+        blob_center - Vec3::splat(0.4 * s),
+        blob_center + Vec3::splat(0.4 * s),
+    );
+    scene.add_shape(Box::new(SdfShape::new(
+        blob,
+        blob_bounds,
+        material(Vec3::new(230.0, 110.0, 150.0)),
+    )));
+
+    let bulb_center = Vec3::new(0.6, 0.35, 0.0) * s;
+    let bulb = Sdf::mandelbulb(8.0, 10)
+        .rotate(Quat::from_rotation_x(-PI / 2.0))
+        .scale(0.3 * s)
+        .translate(bulb_center);
+    let bulb_bounds = Aabb::new(
+        bulb_center - Vec3::splat(0.36 * s),
+        bulb_center + Vec3::splat(0.36 * s),
+    );
+    scene.add_shape(Box::new(
+        SdfShape::new(bulb, bulb_bounds, material(Vec3::new(240.0, 190.0, 90.0))).step_scale(0.8),
+    ));
+    Ok(())
+}
+
+pub fn marble_ball(scene: &mut Scene) -> Result<()> {
+    let basic_material = BasicMaterial::builder()
+        .ambient(0.05)
+        .diffuse(0.6)
+        .specular(0.3)
+        .reflection(0.1)
+        .build();
+
+    // solid texture, so the veins run through the ball instead of wrapping it
+    let marble = ProceduralTexture::new(
+        Pattern::Marble {
+            octaves: 6,
+            strength: 4.0,
+        },
+        4.0 / scene.scale,
+        TextureSpace::World,
+    );
+    let material = ProceduralMaterial::new(
+        marble,
+        Vec3::new(60.0, 60.0, 70.0),
+        Vec3::new(240.0, 240.0, 235.0),
+        basic_material,
+    );
+
+    let sphere = Sphere::new(
+        Vec3::ZERO,
+        scene.scale / 2.0,
+        Box::new(material),
+        glam::Quat::IDENTITY,
+    );
+    scene.add_shape(Box::new(sphere));
+    Ok(())
+}
+
+pub fn wood_floor(scene: &mut Scene) -> Result<()> {
+    let wood = ProceduralTexture::new(
+        Pattern::Wood {
+            rings: 12.0,
+            strength: 0.4,
+        },
+        1.0,
+        TextureSpace::Uv,
+    );
+    let grain = ProceduralTexture::new(Pattern::Fbm { octaves: 4 }, 40.0, TextureSpace::Uv)
+        .basis(NoiseBasis::Simplex);
+
+    // the grain roughens and dents the varnish
+    let channels = GraphMaterial::builder()
+        .ambient(0.05)
+        .diffuse(0.5)
+        .specular(0.2)
+        .reflection(0.3)
+        .roughness(TextureInput::multiply(0.2, grain.clone()))
+        .bump(grain, 0.05)
+        .build();
+    let material = ProceduralMaterial::new(
+        wood,
+        Vec3::new(90.0, 50.0, 20.0),
+        Vec3::new(190.0, 130.0, 70.0),
+        channels,
+    );
+
+    let size = scene.scale * 5.0;
+
+    let plane = Quad::new(
+        Vec3::new(-size / 2.0, -scene.scale * 0.3, -size / 2.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(size, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, size),
+        Box::new(material),
+    );
+    scene.add_shape(Box::new(plane));
+    Ok(())
+}
+
+// a furry ball lit with the hair bsdf next to a patch of grass blades
+pub fn curves(scene: &mut Scene) -> Result<()> {
+    let s = scene.scale;
+    let mut rng = SmallRng::from_seed([0u8; 32]);
+
+    let center = Vec3::new(-0.45, 0.0, 0.1) * s;
+    let radius = 0.18 * s;
+    scene.add_shape(Box::new(Sphere::new(
+        center,
+        radius,
+        Box::new(
+            BasicMaterial::builder()
+                .color(Vec3::new(60.0, 35.0, 20.0))
+                .diffuse(0.8)
+                .build(),
+        ),
+        Quat::IDENTITY,
+    )));
+
+    // out from the skin, drooping under their own weight towards the tips
+    let fur = (0..20000)
+        .map(|_| {
+            let normal = (Vec3::new(
+                rng.gen::<f32>() - 0.5,
+                rng.gen::<f32>() - 0.5,
+                rng.gen::<f32>() - 0.5,
+            ))
+            .normalize();
+            let length = (0.07 + 0.03 * rng.gen::<f32>()) * s;
+            let jitter = Vec3::new(rng.gen(), rng.gen(), rng.gen()) - 0.5;
+            let root = center + normal * radius;
+            let droop = Vec3::new(0.0, -0.35, 0.0) + jitter * 0.4;
+            Strand::tapered(
+                vec![
+                    root,
+                    root + normal * length * 0.35,
+                    root + (normal * 0.65 + droop * 0.3) * length,
+                    root + (normal * 0.85 + droop * 0.6) * length,
+                ],
+                0.004 * s,
+                0.0005 * s,
+            )
+        })
+        .collect();
+    scene.add_shape(Box::new(Curves::new(
+        fur,
+        CurveType::Cylinder,
+        Box::new(HairMaterial::new(Vec3::new(110.0, 65.0, 30.0))),
+    )));
+
+    // blades leaning every way, curving over as they get taller
+    let grass = (0..10000)
+        .map(|_| {
+            let root = Vec3::new(
+                0.2 + 0.7 * rng.gen::<f32>(),
+                -0.3,
+                -0.2 + 0.6 * rng.gen::<f32>(),
+            ) * s;
+            let height = (0.06 + 0.12 * rng.gen::<f32>()) * s;
+            let angle = rng.gen::<f32>() * 2.0 * PI;
+            let lean = Vec3::new(angle.cos(), 0.0, angle.sin()) * rng.gen::<f32>() * 0.6;
+            Strand::tapered(
+                vec![
+                    root,
+                    root + Vec3::Y * height * 0.4,
+                    root + (Vec3::Y * 0.8 + lean * 0.4) * height,
+                    root + (Vec3::Y * 0.9 + lean) * height,
+                ],
+                0.008 * s,
+                0.0,
+            )
+        })
+        .collect();
+    scene.add_shape(Box::new(Curves::new(
+        grass,
+        CurveType::Flat,
+        Box::new(
+            BasicMaterial::builder()
+                .color(Vec3::new(50.0, 110.0, 30.0))
+                .ambient(0.1)
+                .diffuse(0.8)
+                .specular(0.1)
+                .build(),
+        ),
+    )));
+    Ok(())
+}
+
+// two scans of a ball colored by direction, as disks along their normals on the
+// left and as little spheres on the right. the color is each point's own
+pub fn point_cloud(scene: &mut Scene) -> Result<()> {
+    let s = scene.scale;
+    let material = || {
+        Box::new(
+            GraphMaterial::builder()
+                .color(TextureInput::VertexColor)
+                .ambient(0.1)
+                .diffuse(0.8)
+                .specular(0.2)
+                .build(),
+        )
+    };
+
+    // evenly spread over the sphere along a fibonacci spiral
+    let scan = |center: Vec3| -> Vec<CloudPoint> {
+        let count = 20000;
+        let golden_angle = PI * (3.0 - 5f32.sqrt());
+        (0..count)
+            .map(|i| {
+                let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                let ring = (1.0 - y * y).sqrt();
+                let angle = golden_angle * i as f32;
+                let normal = Vec3::new(ring * angle.cos(), y, ring * angle.sin());
+                CloudPoint {
+                    position: center + normal * 0.3 * s,
+                    normal,
+                    color: (normal * 0.5 + 0.5) * 255.0,
+                    radius: 0.0,
+                }
+            })
+            .collect()
+    };
+
+    scene.add_shape(Box::new(PointCloud::new(
+        scan(Vec3::new(-0.4, 0.05, 0.0) * s),
+        SplatType::Disk,
+        0.008 * s,
+        material(),
+    )));
+    scene.add_shape(Box::new(PointCloud::new(
+        scan(Vec3::new(0.4, 0.05, 0.0) * s),
+        SplatType::Sphere,
+        0.005 * s,
+        material(),
+    )));
+    Ok(())
+}
+
+pub fn graph_ball(scene: &mut Scene) -> Result<()> {
+    let fbm = ProceduralTexture::new(Pattern::Fbm { octaves: 5 }, 6.0, TextureSpace::Uv);
+    let cells = ProceduralTexture::new(Pattern::Voronoi, 12.0, TextureSpace::Uv);
+
+    // rough cells on a polished ball, with a checkered reflectivity mask on top
+    let material = GraphMaterial::builder()
+        .color(TextureInput::mix(
+            Vec3::new(30.0, 90.0, 160.0),
+            Vec3::new(220.0, 200.0, 120.0),
+            fbm,
+        ))
+        .ambient(0.05)
+        .diffuse(0.4)
+        .specular(0.3)
+        .roughness(TextureInput::remap(
+            cells,
+            Vec2::new(0.0, 1.0),
+            Vec2::new(0.0, 0.3),
+        ))
+        .reflection(TextureInput::checker(8.0, 0.8, 0.1))
+        .build();
+
+    let sphere = Sphere::new(
+        Vec3::ZERO,
+        scene.scale / 2.0,
+        Box::new(material),
+        glam::Quat::IDENTITY,
+    );
+    scene.add_shape(Box::new(sphere));
+    Ok(())
+}
+
+pub fn displaced_terrain(scene: &mut Scene) -> Result<()> {
+    // the same noise raises the hills and paints the rock on them
+    let hills = ProceduralTexture::new(Pattern::Fbm { octaves: 5 }, 4.0, TextureSpace::Uv).seed(3);
+    let grit = ProceduralTexture::new(Pattern::Fbm { octaves: 3 }, 200.0, TextureSpace::Uv)
+        .basis(NoiseBasis::Simplex);
+
+    let material = GraphMaterial::builder()
+        .color(TextureInput::mix(
+            Vec3::new(60.0, 110.0, 40.0),
+            Vec3::new(120.0, 110.0, 100.0),
+            TextureInput::remap(hills.clone(), Vec2::new(0.45, 0.65), Vec2::new(0.0, 1.0)),
+        ))
+        .ambient(0.05)
+        .diffuse(0.4)
+        .specular(0.05)
+        .bump(grit, 0.002)
+        .build();
+
+    let size = scene.scale * 5.0;
+    let ground = Mesh::quad(
+        Vec3::new(-size / 2.0, -scene.scale * 0.6, -size / 2.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(size, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, size),
+    );
+    let displacement = Displacement::new(hills, scene.scale * 0.8).subdivisions(7);
+    scene.add_displaced_mesh(ground, displacement, Box::new(material));
+    Ok(())
+}
+
+// the same kind of hills as a single heightfield, no mesh to displace
+pub fn heightfield_terrain(scene: &mut Scene) -> Result<()> {
+    let material = BasicMaterial::builder()
+        .color(Vec3::new(90.0, 120.0, 60.0))
+        .ambient(0.05)
+        .diffuse(0.6)
+        .specular(0.05)
+        .build();
+
+    let noise = NoiseGen::new(3);
+    let size = scene.scale * 5.0;
+    let height = scene.scale * 0.8;
+    scene.add_shape(Box::new(Heightfield::from_fn(
+        Vec3::new(-size / 2.0, -scene.scale * 0.6, -size / 2.0),
+        Vec2::ONE * size,
+        512,
+        512,
+        |uv| {
+            let p = Vec3::new(uv.x, 0.0, uv.y) * 4.0;
+            (0.5 + 0.5 * noise.fbm(NoiseBasis::Perlin, p, 6, 2.0, 0.5)) * height
+        },
+        Box::new(material),
+    )));
+    Ok(())
+}
+
+pub fn set_cam(scene: &mut Scene) -> Result<()> {
     let center = Vec3::ZERO;
 
     scene.cam.pos.x = 0.0;
     scene.cam.pos.y = scene.scale * 0.5;
     scene.cam.pos.z = center.z - scene.scale * 1.0;
     scene.cam.look_at(center);
+    Ok(())
 }
 
-pub fn duck(scene: &mut Scene) {
+pub fn duck(scene: &mut Scene) -> Result<()> {
     let material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 255.0, 255.0))
         .ambient(0.0)
@@ -518,25 +1096,88 @@ pub fn duck(scene: &mut Scene) {
     let scale = scene.scale * 0.15;
     let p = Vec3::new(-scale * 5.0, scale * 2.5, scale * 8.0);
 
-    let duck = TrisModel::new(
+    let duck = TrisModel::placed(
         "./assets/duck.obj",
         p,
         Vec3::ONE * scale,
         Box::new(material),
-    );
+    )?;
     scene.add_shape(Box::new(duck));
+    Ok(())
+}
+
+// the duck smoothed two ways, Loop on the left and Catmull-Clark on the right
+pub fn subdivided_ducks(scene: &mut Scene) -> Result<()> {
+    let material = BasicMaterial::builder()
+        .color(Vec3::new(230.0, 190.0, 40.0))
+        .ambient(0.0)
+        .diffuse(0.6)
+        .specular(0.4)
+        .reflection(0.1)
+        .roughness(0.2)
+        .refraction(0.0)
+        .build();
+
+    let scale = scene.scale * 0.15;
+    for (x, scheme) in [
+        (-scale * 1.5, SubdivisionScheme::Loop),
+        (scale * 1.5, SubdivisionScheme::CatmullClark),
+    ] {
+        let duck = TrisModel::subdivided(
+            "./assets/duck.obj",
+            Vec3::new(x, scale * 2.5, scale * 8.0),
+            Vec3::ONE * scale,
+            Subdivision::new(scheme, 2),
+            Box::new(material.clone()),
+        )?;
+        scene.add_shape(Box::new(duck));
+    }
+    Ok(())
+}
+
+// the same stl sphere as the file has it, faceted, and welded smooth. the
+// file is written here, wound inside out like some exporters do, so both are
+// flipped back
+pub fn stl_spheres(scene: &mut Scene) -> Result<()> {
+    let path = std::env::temp_dir().join("raytrace-uvsphere.stl");
+    let path = path.to_string_lossy();
+    Mesh::uv_sphere(48, 24).flipped().write_stl(&path)?;
+
+    let material = BasicMaterial::builder()
+        .color(Vec3::new(90.0, 160.0, 230.0))
+        .ambient(0.0)
+        .diffuse(0.6)
+        .specular(0.5)
+        .reflection(0.1)
+        .roughness(0.1)
+        .refraction(0.0)
+        .build();
+
+    let scale = scene.scale * 0.15;
+    for (x, weld) in [(-scale * 1.5, false), (scale * 1.5, true)] {
+        let options = MeshOptions::default().fit(true).flip(true).weld(weld);
+        let sphere = TrisModel::load(&path, options, Box::new(material.clone()))?;
+        let transform = Affine3A::from_scale_rotation_translation(
+            Vec3::splat(scale * 2.0),
+            Quat::IDENTITY,
+            Vec3::new(x, scale * 2.5, scale * 8.0),
+        );
+        scene.add_shape(Box::new(Instance::new(Arc::new(sphere), transform)));
+    }
+    Ok(())
 }
 
-pub fn set_cam_raised_looking_down(scene: &mut Scene) {
+pub fn set_cam_raised_looking_down(scene: &mut Scene) -> Result<()> {
     let center = Vec3::ZERO;
 
     scene.cam.pos.x = 0.0;
     scene.cam.pos.y = scene.scale * 1.0;
     scene.cam.pos.z = center.z - scene.scale * 0.7;
     scene.cam.look_at(center);
+    Ok(())
 }
 
-pub fn basic_quad(scene: &mut Scene) {
+pub fn basic_quad(scene: &mut Scene) -> Result<()> {
     scene.add_shape(Box::new(Quad::new(
         Vec3::new(0.0, -0.5, 0.0),
         Vec3::new(0.0, 0.1, 0.0),
@@ -554,9 +1195,10 @@ pub fn basic_quad(scene: &mut Scene) {
                 .build(),
         ),
     )));
+    Ok(())
 }
 
-pub fn light_box(scene: &mut Scene) {
+pub fn light_box(scene: &mut Scene) -> Result<()> {
     let material = BasicMaterial::builder()
         .color(Vec3::new(255.0, 255.0, 255.0))
         .ambient(0.0)
@@ -614,12 +1256,15 @@ pub fn light_box(scene: &mut Scene) {
         Vec3::new(0.0, 0.0, -width),
         Box::new(material.clone()),
     )));
+    Ok(())
 }
 
-pub fn raised_cam(scene: &mut Scene) {
+pub fn raised_cam(scene: &mut Scene) -> Result<()> {
     scene.cam.pos.y = scene.scale / 6.0;
+    Ok(())
 }
 
-pub fn shifted_cam(scene: &mut Scene) {
+pub fn shifted_cam(scene: &mut Scene) -> Result<()> {
     scene.cam.pos.x += scene.scale / 20.0;
+    Ok(())
 }
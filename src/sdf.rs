@@ -0,0 +1,408 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use glam::{Quat, Vec2, Vec3};
+
+use crate::{
+    bvh::Aabb,
+    material::Material,
+    shapes::Shape,
+    structures::{HitRecord, Ray},
+    utils::perpendicular_to,
+};
+
+// a node in a signed distance field: the distance from p to the nearest surface,
+// negative inside. primitives sit at the origin, the transform nodes move them.
+// nodes that bend distances (smooth blends, fractals) only promise not to
+// overestimate by much, shapes tracing them can step more carefully
+#[derive(Clone)]
+pub enum Sdf {
+    Sphere {
+        radius: f32,
+    },
+    Cuboid {
+        half_size: Vec3,
+    },
+    // around the y axis
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    // along the y axis, closed at both ends
+    Cylinder {
+        radius: f32,
+        half_height: f32,
+    },
+    // the power 8 bulb fits in a radius of about 1.2
+    Mandelbulb {
+        power: f32,
+        iterations: u32,
+    },
+    // anything else, it should be a true distance or an underestimate of one
+    Custom(Arc<dyn Fn(Vec3) -> f32 + Send + Sync>),
+
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    // a with b carved out of it
+    Difference(Box<Sdf>, Box<Sdf>),
+    // blended over a distance of about k where the two meet
+    SmoothUnion {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f32,
+    },
+    SmoothIntersection {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f32,
+    },
+    SmoothDifference {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        k: f32,
+    },
+
+    Translate {
+        input: Box<Sdf>,
+        offset: Vec3,
+    },
+    Rotate {
+        input: Box<Sdf>,
+        rotation: Quat,
+    },
+    // uniform only, a stretch would stop the distances being distances
+    Scale {
+        input: Box<Sdf>,
+        factor: f32,
+    },
+    // the surface pushed out by radius, rounding its edges
+    Round {
+        input: Box<Sdf>,
+        radius: f32,
+    },
+}
+
+impl Sdf {
+    pub fn sphere(radius: f32) -> Sdf {
+        Sdf::Sphere { radius }
+    }
+
+    pub fn cuboid(half_size: Vec3) -> Sdf {
+        Sdf::Cuboid { half_size }
+    }
+
+    pub fn torus(major_radius: f32, minor_radius: f32) -> Sdf {
+        Sdf::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn capsule(a: Vec3, b: Vec3, radius: f32) -> Sdf {
+        Sdf::Capsule { a, b, radius }
+    }
+
+    pub fn cylinder(radius: f32, half_height: f32) -> Sdf {
+        Sdf::Cylinder {
+            radius,
+            half_height,
+        }
+    }
+
+    pub fn mandelbulb(power: f32, iterations: u32) -> Sdf {
+        Sdf::Mandelbulb { power, iterations }
+    }
+
+    pub fn custom(distance: impl Fn(Vec3) -> f32 + Send + Sync + 'static) -> Sdf {
+        Sdf::Custom(Arc::new(distance))
+    }
+
+    pub fn union(a: Sdf, b: Sdf) -> Sdf {
+        Sdf::Union(Box::new(a), Box::new(b))
+    }
+
+    pub fn intersection(a: Sdf, b: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(a), Box::new(b))
+    }
+
+    pub fn difference(a: Sdf, b: Sdf) -> Sdf {
+        Sdf::Difference(Box::new(a), Box::new(b))
+    }
+
+    pub fn smooth_union(a: Sdf, b: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothUnion {
+            a: Box::new(a),
+            b: Box::new(b),
+            k,
+        }
+    }
+
+    pub fn smooth_intersection(a: Sdf, b: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothIntersection {
+            a: Box::new(a),
+            b: Box::new(b),
+            k,
+        }
+    }
+
+    pub fn smooth_difference(a: Sdf, b: Sdf, k: f32) -> Sdf {
+        Sdf::SmoothDifference {
+            a: Box::new(a),
+            b: Box::new(b),
+            k,
+        }
+    }
+
+    pub fn translate(self, offset: Vec3) -> Sdf {
+        Sdf::Translate {
+            input: Box::new(self),
+            offset,
+        }
+    }
+
+    pub fn rotate(self, rotation: Quat) -> Sdf {
+        Sdf::Rotate {
+            input: Box::new(self),
+            rotation,
+        }
+    }
+
+    pub fn scale(self, factor: f32) -> Sdf {
+        Sdf::Scale {
+            input: Box::new(self),
+            factor,
+        }
+    }
+
+    pub fn round(self, radius: f32) -> Sdf {
+        Sdf::Round {
+            input: Box::new(self),
+            radius,
+        }
+    }
+
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Cuboid { half_size } => {
+                let q = p.abs() - *half_size;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = Vec2::new(p.x, p.z).length() - major_radius;
+                Vec2::new(ring, p.y).length() - minor_radius
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - *a, *b - *a);
+                let h = (pa.dot(ba) / ba.length_squared()).clamp(0.0, 1.0);
+                (pa - ba * h).length() - radius
+            }
+            Sdf::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let d = Vec2::new(
+                    Vec2::new(p.x, p.z).length() - radius,
+                    p.y.abs() - half_height,
+                );
+                d.max(Vec2::ZERO).length() + d.max_element().min(0.0)
+            }
+            Sdf::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            Sdf::Custom(distance) => distance(p),
+
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::Difference(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion { a, b, k } => smooth_min(a.distance(p), b.distance(p), *k),
+            Sdf::SmoothIntersection { a, b, k } => -smooth_min(-a.distance(p), -b.distance(p), *k),
+            Sdf::SmoothDifference { a, b, k } => -smooth_min(-a.distance(p), b.distance(p), *k),
+
+            Sdf::Translate { input, offset } => input.distance(p - *offset),
+            Sdf::Rotate { input, rotation } => input.distance(rotation.inverse() * p),
+            Sdf::Scale { input, factor } => input.distance(p / *factor) * factor,
+            Sdf::Round { input, radius } => input.distance(p) - radius,
+        }
+    }
+
+    // the gradient, which is the outward normal on the surface. four samples
+    // at the corners of a tetrahedron, h apart
+    pub fn normal(&self, p: Vec3, h: f32) -> Vec3 {
+        let corners = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        corners
+            .iter()
+            .map(|&corner| corner * self.distance(p + corner * h))
+            .sum::<Vec3>()
+            .normalize_or_zero()
+    }
+}
+
+// polynomial smooth minimum, a and b blend over a band k wide
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+// distance estimate from the escape speed of z -> z^power + p in spherical coordinates
+fn mandelbulb(p: Vec3, power: f32, iterations: u32) -> f32 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 {
+            break;
+        }
+        let theta = (z.z / r).clamp(-1.0, 1.0).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z =
+            zr * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) + p;
+        r = z.length();
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+// a surface given by a distance field, found by sphere tracing: the field says
+// how far the ray can safely step, so it walks until the distance is ~0.
+// the field can't bound itself, so the bounds are given and nothing is traced outside them
+pub struct SdfShape {
+    pub sdf: Sdf,
+    pub bounds: Aabb,
+    pub material: Box<dyn Material>,
+    // how close counts as on the surface, in world units
+    pub epsilon: f32,
+    pub max_steps: u32,
+    // under 1 for fields that overestimate, like heavy blends or fractals
+    pub step_scale: f32,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, bounds: Aabb, material: Box<dyn Material>) -> SdfShape {
+        SdfShape {
+            sdf,
+            bounds,
+            material,
+            epsilon: 1e-4 * (bounds.max - bounds.min).length(),
+            max_steps: 256,
+            step_scale: 1.0,
+        }
+    }
+
+    pub fn epsilon(mut self, epsilon: f32) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn step_scale(mut self, step_scale: f32) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+
+    // where the ray is inside the bounds, if it ever is
+    fn clip(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<(f32, f32)> {
+        let inv_dir = ray.dir.recip();
+        let t0 = (self.bounds.min - ray.origin) * inv_dir;
+        let t1 = (self.bounds.max - ray.origin) * inv_dir;
+        let t_near = t0.min(t1).max_element().max(ray_tmin);
+        let t_far = t0.max(t1).min_element().min(ray_tmax);
+        (t_near <= t_far).then_some((t_near, t_far))
+    }
+}
+
+impl Shape for SdfShape {
+    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (mut t, t_far) = self.clip(ray, ray_tmin, ray_tmax)?;
+
+        // stepping by the absolute distance traces from inside too, for refraction
+        let mut steps = 0;
+        loop {
+            let distance = self.sdf.distance(ray.at(t)).abs();
+            if distance < self.epsilon {
+                break;
+            }
+            t += distance * self.step_scale;
+            steps += 1;
+            if t > t_far || steps >= self.max_steps {
+                return None;
+            }
+        }
+
+        let mut hit_record = HitRecord::new();
+        hit_record.t = t;
+        hit_record.p = ray.at(t);
+        // the hit is only within epsilon of the surface. spawned rays start a few
+        // epsilons off it so they don't stop right where they began
+        hit_record.p_error = Vec3::splat(4.0 * self.epsilon);
+        let normal = self.sdf.normal(hit_record.p, self.epsilon);
+        let normal = if normal == Vec3::ZERO {
+            -ray.dir
+        } else {
+            normal
+        };
+        hit_record.set_face_normal(ray, normal);
+        Some(hit_record)
+    }
+
+    // fields have no parameterization of their own, this wraps a sphere
+    // around the middle of the bounds
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let local = hit_record.p - self.bounds.center();
+        let phi = local.z.atan2(local.x);
+        let theta = local.y.atan2(Vec2::new(local.x, local.z).length());
+        Vec2::new((phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
+    }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let extent = (self.bounds.max - self.bounds.min).length();
+        // along the wrapped sphere's dp/du and dp/dv, u east round y and v up,
+        // whichever side the ray came from
+        let normal = if hit_record.front_face {
+            hit_record.normal
+        } else {
+            -hit_record.normal
+        };
+        let u_direction = normal.cross(Vec3::Y).normalize_or_zero();
+        let u_direction = if u_direction == Vec3::ZERO {
+            perpendicular_to(normal)
+        } else {
+            u_direction
+        };
+        let v_direction = u_direction.cross(normal);
+        (u_direction * PI * extent, v_direction * 0.5 * PI * extent)
+    }
+
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.bounds
+    }
+}
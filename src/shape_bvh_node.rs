@@ -1,42 +1,41 @@
-use bvh::{
-    aabb::{Bounded, AABB},
-    bounding_hierarchy::BHShape,
-};
+use glam::Vec3;
 
-use crate::shapes::Shape;
+use crate::{
+    bvh::{Aabb, Bounded},
+    shapes::Shape,
+};
 
 pub struct ShapeBVHNodeWrapper {
     shape: Box<dyn Shape>,
-    node_index: usize,
+    // worked out once, csg shapes bound themselves from their whole tree
+    aabb: Aabb,
 }
 
 impl ShapeBVHNodeWrapper {
     pub fn new(shape: Box<dyn Shape>) -> ShapeBVHNodeWrapper {
-        ShapeBVHNodeWrapper {
-            shape,
-            node_index: 0,
-        }
+        let aabb = shape.aabb();
+        // a csg intersection of shapes that don't overlap has nothing to bound.
+        // a point keeps it out of the way instead of poisoning the bvh's bins
+        let aabb = if aabb.is_empty() {
+            Aabb::new(Vec3::ZERO, Vec3::ZERO)
+        } else {
+            aabb
+        };
+        ShapeBVHNodeWrapper { shape, aabb }
     }
 
-    pub fn get_shape(&self) -> &Box<dyn Shape> {
-        &self.shape
-    }
-}
-
-impl BHShape for ShapeBVHNodeWrapper {
-    fn set_bh_node_index(&mut self, index: usize) {
-        self.node_index = index;
+    pub fn get_shape(&self) -> &dyn Shape {
+        self.shape.as_ref()
     }
 
-    fn bh_node_index(&self) -> usize {
-        self.node_index
+    pub fn into_shape(self) -> Box<dyn Shape> {
+        self.shape
     }
 }
 
 impl Bounded for ShapeBVHNodeWrapper {
-    fn aabb(&self) -> AABB {
-        // delegate to  shape's aabb method
-        self.shape.aabb()
+    fn aabb(&self) -> Aabb {
+        self.aabb
     }
 }
 
@@ -47,4 +46,4 @@ impl Bounded for ShapeBVHNodeWrapper {
 // }).collect();
 
 // // Now you can build the BVH with shape_wrappers
-// let bvh = BVH::build(&mut shape_wrappers);
+// let bvh = Bvh::build(&shape_wrappers);
@@ -1,22 +1,73 @@
-use bvh::{
-    aabb::{Bounded, AABB},
-    bounding_hierarchy::BHShape,
-    bvh::BVH,
-    Point3, Vector3,
-};
-use glam::{Vec2, Vec3};
+use glam::{Affine3A, BVec4A, Quat, Vec2, Vec3, Vec4};
+use std::{f32::consts::PI, sync::Arc};
 
 use crate::{
+    bvh::{Aabb, Bounded, Bvh},
+    error::Result,
+    instance::Instance,
     material::Material,
+    mesh::{uv_gradients, Mesh},
+    mesh_cache::{MeshCache, MeshOptions},
+    packet::{self, lanes, PacketHits, RayPacket, Vec3x4},
     structures::{HitRecord, Ray},
-    utils::perpendicular_to,
+    subdivision::Subdivision,
+    triangle::{hit_triangle, hit_triangle_packet, triangle_point, RayShear},
+    utils::{gamma, perpendicular_to, solve_quadratic},
 };
 
-pub trait Shape: Sync {
+pub trait Shape: Send + Sync {
     fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord>;
-    fn get_hit_uv(&self, hit_pos: Vec3) -> Vec2;
-    fn material(&self) -> &Box<dyn Material>;
-    fn aabb(&self) -> AABB;
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2;
+    // dp/du and dp/dv at the hit, matching get_hit_uv. not normalized,
+    // the lengths size texture footprints and the directions orient normal maps
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3);
+    fn material(&self) -> &dyn Material;
+    // Aabb::infinite for shapes that go on forever, the scene tests those
+    // separately instead of putting them in its bvh
+    fn aabb(&self) -> Aabb;
+    // per vertex or per point color at the hit, for shapes loaded with one.
+    // materials pick it up through TextureInput::VertexColor
+    fn get_hit_color(&self, _hit_record: &HitRecord) -> Option<Vec3> {
+        None
+    }
+
+    // every hit along the ray in order, not just the nearest. csg needs them
+    // to tell where the ray is inside a shape. each step restarts just past
+    // the last hit, the way a spawned ray would, so it can't find it again
+    fn hit_all(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Vec<HitRecord> {
+        let mut hits = Vec::new();
+        let mut step = *ray;
+        let mut step_start = 0.0;
+        let mut step_tmin = ray_tmin;
+        while let Some(mut hit_record) = self.hit(&step, step_tmin, ray_tmax - step_start) {
+            let next = hit_record
+                .spawn_ray(ray.dir)
+                .with_cone(ray.cone_width, ray.cone_spread)
+                .with_time(ray.time);
+            hit_record.t += step_start;
+            step_start = (next.origin - ray.origin).dot(ray.dir);
+            hits.push(hit_record);
+            step = next;
+            step_tmin = 0.0;
+        }
+        hits
+    }
+
+    // one hit per lane, only the active lanes need answers. shapes that can
+    // test four rays at once for about the price of one override this
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        active: BVec4A,
+    ) -> PacketHits {
+        let mut hits = PacketHits::default();
+        for lane in lanes(active) {
+            hits[lane] = self.hit(&packet.rays[lane], ray_tmin, ray_tmax[lane]);
+        }
+        hits
+    }
 }
 
 pub struct Sphere {
@@ -40,16 +91,28 @@ impl Sphere {
             orientation,
         }
     }
+
+    // the point is snapped back onto the sphere, which bounds its error
+    // much tighter than t from the quadratic would
+    fn hit_record(&self, ray: &Ray, t: f32) -> HitRecord {
+        let to_hit = ray.at(t) - self.center;
+        let to_hit = to_hit * (self.radius / to_hit.length());
+
+        let mut hit_record = HitRecord::new();
+        hit_record.t = t;
+        hit_record.p = self.center + to_hit;
+        hit_record.p_error = gamma(5) * to_hit.abs() + gamma(1) * hit_record.p.abs();
+        hit_record.set_face_normal(ray, to_hit / self.radius);
+        hit_record
+    }
 }
 
 impl Shape for Sphere {
-    fn aabb(&self) -> AABB {
+    fn aabb(&self) -> Aabb {
         let half_size = Vec3::new(self.radius, self.radius, self.radius);
         let min = self.center - half_size;
         let max = self.center + half_size;
-        let min = Vector3::new(min.x, min.y, min.z);
-        let max = Vector3::new(max.x, max.y, max.z);
-        AABB::with_bounds(min, max)
+        Aabb::new(min, max)
     }
 
     fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
@@ -58,38 +121,61 @@ impl Shape for Sphere {
         let half_b = to_sphere.dot(ray.dir);
         let c = to_sphere.length_squared() - self.radius * self.radius;
 
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return None;
-        }
-
-        let sqrt_d = discriminant.sqrt();
+        let c_error = gamma(3) * (to_sphere.length_squared() + self.radius * self.radius);
+        let (near, far) = solve_quadratic(a, half_b, c, c_error)?;
 
         // Find the nearest root that lies in the acceptable range.
-        let mut root = (-half_b - sqrt_d) / a;
-        if root < ray_tmin || ray_tmax < root {
-            root = (-half_b + sqrt_d) / a;
-            if root < ray_tmin || ray_tmax < root {
-                return None;
-            }
-        }
+        let in_range = |root: f32| root >= ray_tmin && root <= ray_tmax;
+        let root = if in_range(near) {
+            near
+        } else if in_range(far) {
+            far
+        } else {
+            return None;
+        };
 
-        let mut hit_record = HitRecord::new();
-        hit_record.t = root;
-        hit_record.p = ray.at(root);
-        let outward_normal = (hit_record.p - self.center) / self.radius;
-        hit_record.set_face_normal(ray, outward_normal);
+        Some(self.hit_record(ray, root))
+    }
 
-        Some(hit_record)
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        active: BVec4A,
+    ) -> PacketHits {
+        // same as hit, directions are normalized so a is 1
+        let to_sphere = packet.origin - Vec3x4::splat(self.center);
+        let half_b = to_sphere.dot(packet.dir);
+        let c = to_sphere.dot(to_sphere) - Vec4::splat(self.radius * self.radius);
+        let discriminant = half_b * half_b - c;
+        let sqrt_d = packet::sqrt(discriminant.max(Vec4::ZERO));
+
+        let q = -(half_b + sqrt_d.copysign(half_b));
+        let t_error = Vec4::splat(gamma(3))
+            * (to_sphere.dot(to_sphere) + Vec4::splat(self.radius * self.radius))
+            / q.abs();
+        let tmin = Vec4::splat(ray_tmin);
+        let in_range = |root: Vec4| root.cmpge(tmin) & root.cmple(ray_tmax) & root.cmpgt(t_error);
+        let near = q.min(c / q);
+        let far = q.max(c / q);
+        let root = Vec4::select(in_range(near), near, far);
+        let hit = active & discriminant.cmpge(Vec4::ZERO) & in_range(root);
+
+        let mut hits = PacketHits::default();
+        for lane in lanes(hit) {
+            hits[lane] = Some(self.hit_record(&packet.rays[lane], root[lane]));
+        }
+        hits
     }
 
-    fn material(&self) -> &Box<dyn Material> {
-        &self.material
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
     }
 
-    fn get_hit_uv(&self, hit_pos: Vec3) -> Vec2 {
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
         // Convert world space hit position to local space
-        let local_hit_pos = self.orientation.inverse() * (hit_pos - self.center);
+        let local_hit_pos = self.orientation.inverse() * (hit_record.p - self.center);
 
         // Calculate spherical coordinates
         let theta = local_hit_pos
@@ -105,6 +191,19 @@ impl Shape for Sphere {
 
         Vec2::new(u, 1.0 - v)
     }
+
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let local = self.orientation.inverse() * (hit_record.p - self.center);
+        let rho = (local.x * local.x + local.z * local.z)
+            .sqrt()
+            .max(1e-6 * self.radius);
+
+        // u runs against the azimuth and v against the latitude, see get_hit_uv
+        let dpdu = Vec3::new(local.z, 0.0, -local.x) * (2.0 * PI);
+        let dpdv = Vec3::new(local.y * local.x / rho, -rho, local.y * local.z / rho) * PI;
+
+        (self.orientation * dpdu, self.orientation * dpdv)
+    }
 }
 
 pub struct Quad {
@@ -137,7 +236,7 @@ impl Quad {
         p1: Vec3,
         p2: Vec3,
         p3: Vec3,
-        p4: Vec3,
+        _p4: Vec3,
         material: Box<dyn Material>,
     ) -> Quad {
         let edge1 = p2 - p1;
@@ -155,7 +254,7 @@ impl Quad {
 }
 
 impl Shape for Quad {
-    fn aabb(&self) -> AABB {
+    fn aabb(&self) -> Aabb {
         let mut min_x = self.point.x;
         let mut max_x = self.point.x;
         let mut min_y = self.point.y;
@@ -180,10 +279,10 @@ impl Shape for Quad {
             max_z = max_z.max(vertex.z);
         }
 
-        let min = Point3::new(min_x, min_y, min_z);
-        let max = Point3::new(max_x, max_y, max_z);
+        let min = Vec3::new(min_x, min_y, min_z);
+        let max = Vec3::new(max_x, max_y, max_z);
 
-        AABB::with_bounds(min, max)
+        Aabb::new(min, max)
     }
 
     fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
@@ -195,8 +294,8 @@ impl Shape for Quad {
 
         let v = self.point - ray.origin;
         let t = v.dot(self.normal) / denominator;
-        if t < 0.0 {
-            // The intersection is behind the ray's origin
+        if t < ray_tmin || t > ray_tmax {
+            // The intersection is outside the valid range
             return None;
         }
 
@@ -213,6 +312,7 @@ impl Shape for Quad {
             let mut hit_record = HitRecord::new();
             hit_record.t = t;
             hit_record.p = hit_point;
+            hit_record.p_error = flat_p_error(ray, t, self.point);
             hit_record.set_face_normal(ray, self.normal);
 
             Some(hit_record)
@@ -221,14 +321,14 @@ impl Shape for Quad {
         }
     }
 
-    fn material(&self) -> &Box<dyn Material> {
-        &self.material
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
     }
 
     // UNTESTED
-    fn get_hit_uv(&self, hit_pos: Vec3) -> Vec2 {
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
         // Transform the hit position to the local space of the quad
-        let local_hit_pos = hit_pos - self.point;
+        let local_hit_pos = hit_record.p - self.point;
 
         // Project the local hit position onto the edges of the quad
         let u = local_hit_pos.dot(self.edge1.normalize());
@@ -240,6 +340,17 @@ impl Shape for Quad {
 
         Vec2::new(u_normalized, v_normalized)
     }
+
+    fn get_hit_tangents(&self, _hit_record: &HitRecord) -> (Vec3, Vec3) {
+        (self.edge1, self.edge2)
+    }
+}
+
+// error of ray.at(t) when t came from intersecting a plane through point. t
+// itself may be well off at grazing angles, but that error runs along the
+// plane, off the plane it's bounded by the magnitudes involved
+fn flat_p_error(ray: &Ray, t: f32, point: Vec3) -> Vec3 {
+    gamma(7) * (ray.origin.abs() + (ray.dir * t).abs() + point.abs())
 }
 
 pub struct Plane {
@@ -259,21 +370,8 @@ impl Plane {
 }
 
 impl Shape for Plane {
-    fn aabb(&self) -> AABB {
-        // Define the extent of the AABB in each axis.
-        // These values should be large enough to encompass the area where the plane has an effect.
-        let extent = 1e5f32;
-
-        // Calculate the min and max points of the AABB.
-        let min = self.point - Vec3::new(extent, extent, extent);
-        let max = self.point + Vec3::new(extent, extent, extent);
-
-        //convert
-        let min = Vector3::new(min.x, min.y, min.z);
-        let max = Vector3::new(max.x, max.y, max.z);
-
-        // Return the AABB
-        AABB::with_bounds(min, max)
+    fn aabb(&self) -> Aabb {
+        Aabb::infinite()
     }
 
     fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
@@ -282,10 +380,11 @@ impl Shape for Plane {
             // Check not parallel (not zero)
             let v = self.point - ray.origin;
             let distance = v.dot(self.normal) / denom;
-            if distance >= 0.0 {
+            if distance >= ray_tmin && distance <= ray_tmax {
                 let mut hit_record = HitRecord::new();
                 hit_record.t = distance;
                 hit_record.p = ray.at(distance);
+                hit_record.p_error = flat_p_error(ray, distance, self.point);
                 hit_record.set_face_normal(ray, self.normal);
 
                 return Some(hit_record);
@@ -294,19 +393,17 @@ impl Shape for Plane {
         None
     }
 
-    fn material(&self) -> &Box<dyn Material> {
-        &self.material
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
     }
 
     // UNTESTED
-    fn get_hit_uv(&self, hit_pos: Vec3) -> Vec2 {
-        // Generate two perpendicular vectors on the plane
-        let u_direction = perpendicular_to(self.normal);
-        let v_direction = self.normal.cross(u_direction).normalize();
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let (u_direction, v_direction) = self.get_hit_tangents(hit_record);
 
         // Project hit position onto these vectors
-        let u = hit_pos.dot(u_direction);
-        let v = hit_pos.dot(v_direction);
+        let u = hit_record.p.dot(u_direction);
+        let v = hit_record.p.dot(v_direction);
 
         // Optionally, modulate u and v for a repeating pattern
         let u_modulated = u % 1.0;
@@ -314,6 +411,13 @@ impl Shape for Plane {
 
         Vec2::new(u_modulated, v_modulated)
     }
+
+    fn get_hit_tangents(&self, _hit_record: &HitRecord) -> (Vec3, Vec3) {
+        // Generate two perpendicular vectors on the plane
+        let u_direction = perpendicular_to(self.normal);
+        let v_direction = self.normal.cross(u_direction).normalize();
+        (u_direction, v_direction)
+    }
 }
 
 pub struct Tri {
@@ -330,7 +434,7 @@ impl Tri {
 }
 
 impl Shape for Tri {
-    fn aabb(&self) -> AABB {
+    fn aabb(&self) -> Aabb {
         let mut min_x = self.a.x;
         let mut max_x = self.a.x;
         let mut min_y = self.a.y;
@@ -350,63 +454,37 @@ impl Shape for Tri {
             max_z = max_z.max(vertex.z);
         }
 
-        let min = Point3::new(min_x, min_y, min_z);
-        let max = Point3::new(max_x, max_y, max_z);
+        let min = Vec3::new(min_x, min_y, min_z);
+        let max = Vec3::new(max_x, max_y, max_z);
 
-        AABB::with_bounds(min, max)
+        Aabb::new(min, max)
     }
 
     fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
-        let edge1 = self.b - self.a;
-        let edge2 = self.c - self.a;
-        let h = ray.dir.cross(edge2);
-        let a = edge1.dot(h);
-
-        if a.abs() < 1e-6 {
-            return None; // Ray is parallel to the triangle
-        }
-
-        let f = 1.0 / a;
-        let s = ray.origin - self.a;
-        let u = f * s.dot(h);
-
-        if !(0.0..=1.0).contains(&u) {
-            return None; // Intersection is outside the triangle
-        }
-
-        let q = s.cross(edge1);
-        let v = f * ray.dir.dot(q);
-
-        if v < 0.0 || u + v > 1.0 {
-            return None; // Intersection is outside the triangle
-        }
-
-        let t = f * edge2.dot(q);
-
-        if t < ray_tmin || t > ray_tmax {
-            return None; // Intersection is outside the valid range
-        }
-
-        let p = ray.at(t);
-        let normal = edge1.cross(edge2).normalize();
+        let vertices = [self.a, self.b, self.c];
+        let (t, weights) =
+            hit_triangle(ray, &RayShear::new(ray.dir), vertices, ray_tmin, ray_tmax)?;
+        let (p, p_error) = triangle_point(vertices, weights);
+        let normal = (self.b - self.a).cross(self.c - self.a).normalize();
 
         let mut hit_record = HitRecord::new();
         hit_record.t = t;
         hit_record.p = p;
+        hit_record.p_error = p_error;
         hit_record.set_face_normal(ray, normal);
 
         Some(hit_record)
     }
 
-    fn material(&self) -> &Box<dyn Material> {
-        &self.material
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
     }
 
     // UNTESTED
-    fn get_hit_uv(&self, hit_pos: Vec3) -> Vec2 {
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
         let edge1 = self.b - self.a;
         let edge2 = self.c - self.a;
-        let hit_vec = hit_pos - self.a;
+        let hit_vec = hit_record.p - self.a;
 
         // Calculate barycentric coordinates
         let dot00 = edge1.dot(edge1);
@@ -421,13 +499,23 @@ impl Shape for Tri {
 
         Vec2::new(u, v)
     }
+
+    fn get_hit_tangents(&self, _hit_record: &HitRecord) -> (Vec3, Vec3) {
+        (self.b - self.a, self.c - self.a)
+    }
 }
 
 pub struct PrimitiveTri {
     pub a: Vec3,
     pub b: Vec3,
     pub c: Vec3,
-    pub node_index: usize, // for the bvh
+    // per corner shading attributes
+    pub normals: [Vec3; 3],
+    pub uvs: [Vec2; 3],
+    pub colors: [Vec3; 3],
+    pub tangents: [Vec3; 3],
+    pub bitangents: [Vec3; 3],
+    pub index: usize, // position in the owning model
 }
 
 impl PrimitiveTri {
@@ -436,65 +524,81 @@ impl PrimitiveTri {
             a,
             b,
             c,
-            node_index: 0,
+            normals: [Vec3::ZERO; 3],
+            uvs: [Vec2::ZERO; 3],
+            colors: [Vec3::splat(255.0); 3],
+            tangents: [Vec3::ZERO; 3],
+            bitangents: [Vec3::ZERO; 3],
+            index: 0,
         }
     }
 
-    fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
-        let edge1 = self.b - self.a;
-        let edge2 = self.c - self.a;
-        let h = ray.dir.cross(edge2);
-        let a = edge1.dot(h);
-
-        if a.abs() < 1e-6 {
-            return None; // Ray is parallel to the triangle
-        }
-
-        let f = 1.0 / a;
-        let s = ray.origin - self.a;
-        let u = f * s.dot(h);
-
-        if !(0.0..=1.0).contains(&u) {
-            return None; // Intersection is outside the triangle
-        }
-
-        let q = s.cross(edge1);
-        let v = f * ray.dir.dot(q);
+    fn interpolate<T>(&self, values: [T; 3], barycentric: Vec2) -> T
+    where
+        T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+    {
+        let [a, b, c] = values;
+        a * (1.0 - barycentric.x - barycentric.y) + b * barycentric.x + c * barycentric.y
+    }
 
-        if v < 0.0 || u + v > 1.0 {
-            return None; // Intersection is outside the triangle
-        }
+    // dp/du and dp/dv of the flat triangle, None when the uvs are degenerate
+    fn uv_gradients(&self) -> Option<(Vec3, Vec3)> {
+        uv_gradients(
+            [self.a, self.b, self.c],
+            [self.uvs[0], self.uvs[1], self.uvs[2]],
+        )
+    }
 
-        let t = f * edge2.dot(q);
+    // shear is the ray's, worked out once for all the triangles it's tested against
+    fn hit(&self, ray: &Ray, shear: &RayShear, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
+        let (t, weights) = hit_triangle(ray, shear, [self.a, self.b, self.c], ray_tmin, ray_tmax)?;
+        Some(self.hit_record(ray, t, weights))
+    }
 
-        if t < ray_tmin || t > ray_tmax {
-            return None; // Intersection is outside the valid range
+    // hit for four rays at once
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        active: BVec4A,
+    ) -> PacketHits {
+        let (hit, t, weights) =
+            hit_triangle_packet(packet, [self.a, self.b, self.c], ray_tmin, ray_tmax, active);
+
+        let mut hits = PacketHits::default();
+        for lane in lanes(hit) {
+            let lane_weights = Vec3::new(weights.x[lane], weights.y[lane], weights.z[lane]);
+            hits[lane] = Some(self.hit_record(&packet.rays[lane], t[lane], lane_weights));
         }
+        hits
+    }
 
-        let p = ray.at(t);
-        let normal = edge1.cross(edge2).normalize();
+    fn hit_record(&self, ray: &Ray, t: f32, weights: Vec3) -> HitRecord {
+        let (p, p_error) = triangle_point([self.a, self.b, self.c], weights);
+        let normal = (self.b - self.a).cross(self.c - self.a).normalize();
 
         let mut hit_record = HitRecord::new();
         hit_record.t = t;
         hit_record.p = p;
+        hit_record.p_error = p_error;
         hit_record.set_face_normal(ray, normal);
-
-        Some(hit_record)
-    }
-}
-
-impl BHShape for PrimitiveTri {
-    fn set_bh_node_index(&mut self, index: usize) {
-        self.node_index = index;
-    }
-
-    fn bh_node_index(&self) -> usize {
-        self.node_index
+        // smooth over the vertex normals, flat meshes have none
+        let shading_normal = (self.normals[0] * weights.x
+            + self.normals[1] * weights.y
+            + self.normals[2] * weights.z)
+            .normalize_or_zero();
+        if shading_normal != Vec3::ZERO {
+            hit_record.set_shading_normal(shading_normal);
+        }
+        hit_record.prim_index = self.index;
+        hit_record.barycentric = Vec2::new(weights.y, weights.z);
+        hit_record
     }
 }
 
 impl Bounded for PrimitiveTri {
-    fn aabb(&self) -> AABB {
+    fn aabb(&self) -> Aabb {
         let mut min_x = self.a.x;
         let mut max_x = self.a.x;
         let mut min_y = self.a.y;
@@ -514,131 +618,201 @@ impl Bounded for PrimitiveTri {
             max_z = max_z.max(vertex.z);
         }
 
-        let min = Point3::new(min_x, min_y, min_z);
-        let max = Point3::new(max_x, max_y, max_z);
+        let min = Vec3::new(min_x, min_y, min_z);
+        let max = Vec3::new(max_x, max_y, max_z);
 
-        AABB::with_bounds(min, max)
+        Aabb::new(min, max)
     }
 }
 
-pub struct TrisModel {
+// the triangles of a model and the bvh over them, everything but the material.
+// shared between frames and models through the mesh cache
+pub struct TrisGeometry {
     pub tris: Vec<PrimitiveTri>,
-    pub bvh: BVH,
-    pub bounding_box: AABB,
-    pub material: Box<dyn Material>,
+    pub bvh: Bvh,
+    pub bounding_box: Aabb,
+    pub has_colors: bool,
 }
 
-impl TrisModel {
-    // TODO: add rotation matrix
-    pub fn new(filename: &str, p: Vec3, scale: Vec3, material: Box<dyn Material>) -> TrisModel {
-        let options = tobj::LoadOptions {
-            triangulate: false,
-            ..Default::default()
-        };
-
-        let (models, _materials) =
-            tobj::load_obj(filename, &options).expect("Failed to OBJ load file");
+impl TrisGeometry {
+    // smooth shades with the mesh's vertex normals, otherwise every triangle
+    // is flat like the file's faces
+    pub fn from_mesh(mesh: &Mesh, smooth: bool) -> TrisGeometry {
+        let (tangents, bitangents) = mesh.tangents();
+        let has_colors = !mesh.colors.is_empty();
 
         // collect tris, and calculate bounding box
-        let mut tris = vec![];
-        let mut min_x = std::f32::MAX;
-        let mut max_x = std::f32::MIN;
-        let mut min_y = std::f32::MAX;
-        let mut max_y = std::f32::MIN;
-        let mut min_z = std::f32::MAX;
-        let mut max_z = std::f32::MIN;
-
-        for m in models.iter() {
-            let mesh = &m.mesh;
-
-            let num_faces = mesh.indices.len() / 3;
-
-            for face in 0..num_faces {
-                let face_indices = &mesh.indices[3 * face..3 * face + 3];
-
-                // this could be wrong
-                let a_i = 3 * face_indices[0] as usize;
-                let b_i = 3 * face_indices[1] as usize;
-                let c_i = 3 * face_indices[2] as usize;
-
-                let a = Vec3::new(
-                    mesh.positions[a_i] * scale.x + p.x,
-                    mesh.positions[a_i + 1] * scale.y + p.y,
-                    mesh.positions[a_i + 2] * scale.z + p.z,
-                );
-
-                let b = Vec3::new(
-                    mesh.positions[b_i] * scale.x + p.x,
-                    mesh.positions[b_i + 1] * scale.y + p.y,
-                    mesh.positions[b_i + 2] * scale.z + p.z,
-                );
-
-                let c = Vec3::new(
-                    mesh.positions[c_i] * scale.x + p.x,
-                    mesh.positions[c_i + 1] * scale.y + p.y,
-                    mesh.positions[c_i + 2] * scale.z + p.z,
-                );
-
-                min_x = min_x.min(a.x).min(b.x).min(c.x);
-                max_x = max_x.max(a.x).max(b.x).max(c.x);
-                min_y = min_y.min(a.y).min(b.y).min(c.y);
-                max_y = max_y.max(a.y).max(b.y).max(c.y);
-                min_z = min_z.min(a.z).min(b.z).min(c.z);
-                max_z = max_z.max(a.z).max(b.z).max(c.z);
-
-                let tri = PrimitiveTri::new(a, b, c);
-                tris.push(tri);
+        let mut tris = Vec::with_capacity(mesh.num_tris());
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+
+        for face in mesh.indices.chunks_exact(3) {
+            let [a_i, b_i, c_i] = [face[0] as usize, face[1] as usize, face[2] as usize];
+            let (a, b, c) = (
+                mesh.positions[a_i],
+                mesh.positions[b_i],
+                mesh.positions[c_i],
+            );
+
+            min = min.min(a).min(b).min(c);
+            max = max.max(a).max(b).max(c);
+
+            let mut tri = PrimitiveTri::new(a, b, c);
+            if smooth {
+                tri.normals = [mesh.normals[a_i], mesh.normals[b_i], mesh.normals[c_i]];
+            }
+            tri.uvs = [mesh.uvs[a_i], mesh.uvs[b_i], mesh.uvs[c_i]];
+            if has_colors {
+                tri.colors = [mesh.colors[a_i], mesh.colors[b_i], mesh.colors[c_i]];
             }
+            tri.tangents = [tangents[a_i], tangents[b_i], tangents[c_i]];
+            tri.bitangents = [bitangents[a_i], bitangents[b_i], bitangents[c_i]];
+            tri.index = tris.len();
+            tris.push(tri);
         }
 
         // finalize bounding_box
-        let min = Point3::new(min_x, min_y, min_z);
-        let max = Point3::new(max_x, max_y, max_z);
-        let aabb = AABB::with_bounds(min, max);
+        let aabb = Aabb::new(min, max);
 
-        let bvh = BVH::build(&mut tris);
+        let bvh = Bvh::build(&tris);
 
-        TrisModel {
+        TrisGeometry {
             tris,
             bvh,
             bounding_box: aabb,
+            has_colors,
+        }
+    }
+}
+
+pub struct TrisModel {
+    pub geometry: Arc<TrisGeometry>,
+    pub material: Box<dyn Material>,
+}
+
+impl TrisModel {
+    // loads and builds the file once, later models and frames reuse it. the
+    // model stays where the file put it, wrap it in an Instance to place it
+    pub fn load(
+        filename: &str,
+        options: MeshOptions,
+        material: Box<dyn Material>,
+    ) -> Result<TrisModel> {
+        Ok(TrisModel {
+            geometry: MeshCache::global().load(filename, options)?,
+            material,
+        })
+    }
+
+    // the file's model scaled then moved to p
+    pub fn placed(
+        filename: &str,
+        p: Vec3,
+        scale: Vec3,
+        material: Box<dyn Material>,
+    ) -> Result<Instance> {
+        let model = TrisModel::load(filename, MeshOptions::default(), material)?;
+        Ok(instance_at(model, p, scale))
+    }
+
+    // whatever the file's units and origin, centered on center with its longest
+    // side size long
+    pub fn fitted(
+        filename: &str,
+        center: Vec3,
+        size: f32,
+        material: Box<dyn Material>,
+    ) -> Result<Instance> {
+        let model = TrisModel::load(filename, MeshOptions::default().fit(true), material)?;
+        Ok(instance_at(model, center, Vec3::splat(size)))
+    }
+
+    // smoothed at load time, the subdivided mesh is cached like the plain one
+    pub fn subdivided(
+        filename: &str,
+        p: Vec3,
+        scale: Vec3,
+        subdivision: Subdivision,
+        material: Box<dyn Material>,
+    ) -> Result<Instance> {
+        let options = MeshOptions::default().subdivision(subdivision);
+        let model = TrisModel::load(filename, options, material)?;
+        Ok(instance_at(model, p, scale))
+    }
+
+    pub fn from_mesh(mesh: &Mesh, material: Box<dyn Material>) -> TrisModel {
+        TrisModel {
+            geometry: Arc::new(TrisGeometry::from_mesh(mesh, false)),
             material,
         }
     }
 }
 
+fn instance_at(model: TrisModel, p: Vec3, scale: Vec3) -> Instance {
+    let transform = Affine3A::from_scale_rotation_translation(scale, Quat::IDENTITY, p);
+    Instance::new(Arc::new(model), transform)
+}
+
 impl Shape for TrisModel {
-    fn aabb(&self) -> AABB {
-        self.bounding_box
+    fn aabb(&self) -> Aabb {
+        self.geometry.bounding_box
     }
 
     fn hit(&self, ray: &Ray, ray_tmin: f32, ray_tmax: f32) -> Option<HitRecord> {
-        // we need to use the bvh
-
-        let bvh_ray = bvh::ray::Ray::new(
-            Point3::new(ray.origin.x, ray.origin.y, ray.origin.z),
-            Vector3::new(ray.dir.x, ray.dir.y, ray.dir.z),
-        );
+        let shear = RayShear::new(ray.dir);
+        self.geometry
+            .bvh
+            .closest_hit(ray, ray_tmin, ray_tmax, |index, closest_so_far| {
+                self.geometry.tris[index].hit(ray, &shear, ray_tmin, closest_so_far)
+            })
+            .map(|(_, hit_record)| hit_record)
+    }
 
-        let hit_tris = self.bvh.traverse(&bvh_ray, &self.tris);
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_tmin: f32,
+        ray_tmax: Vec4,
+        active: BVec4A,
+    ) -> PacketHits {
+        let ray_tmax = Vec4::select(active, ray_tmax, Vec4::NEG_INFINITY);
+        self.geometry
+            .bvh
+            .closest_hit_packet(packet, ray_tmin, ray_tmax, |index, active, tmax| {
+                self.geometry.tris[index].hit_packet(packet, ray_tmin, tmax, active)
+            })
+            .map(|hit| hit.map(|(_, hit_record)| hit_record))
+    }
 
-        for tri in hit_tris {
-            let maybe_record = tri.hit(ray, ray_tmin, ray_tmax);
-            if maybe_record.is_some() {
-                return maybe_record;
-            }
-        }
+    fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
 
-        None
+    fn get_hit_uv(&self, hit_record: &HitRecord) -> Vec2 {
+        let tri = &self.geometry.tris[hit_record.prim_index];
+        tri.interpolate(tri.uvs, hit_record.barycentric)
     }
 
-    fn material(&self) -> &Box<dyn Material> {
-        &self.material
+    fn get_hit_color(&self, hit_record: &HitRecord) -> Option<Vec3> {
+        let tri = &self.geometry.tris[hit_record.prim_index];
+        self.geometry
+            .has_colors
+            .then(|| tri.interpolate(tri.colors, hit_record.barycentric))
     }
 
-    // UNTESTED
-    fn get_hit_uv(&self, _hit_pos: Vec3) -> Vec2 {
-        // doesnt make sense so just return 0, 0
-        Vec2::new(0.0, 0.0)
+    fn get_hit_tangents(&self, hit_record: &HitRecord) -> (Vec3, Vec3) {
+        let tri = &self.geometry.tris[hit_record.prim_index];
+
+        // smooth vertex directions, lengths from the flat triangle's uv mapping
+        let tangent = tri
+            .interpolate(tri.tangents, hit_record.barycentric)
+            .normalize_or_zero();
+        let bitangent = tri
+            .interpolate(tri.bitangents, hit_record.barycentric)
+            .normalize_or_zero();
+        match tri.uv_gradients() {
+            Some((dpdu, dpdv)) => (tangent * dpdu.length(), bitangent * dpdv.length()),
+            None => (tangent, bitangent),
+        }
     }
 }
@@ -0,0 +1,235 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use rand::{rngs::SmallRng, Rng};
+
+use crate::{environment::Environment, utils::perpendicular_to};
+
+// kcd/m^2 of sky luminance to the 0-255 color range
+const SKY_EXPOSURE: f32 = 255.0 * 0.05;
+// head on irradiance of an overhead sun before the air dims it, 255 is a white light
+const SUN_IRRADIANCE: f32 = 255.0 * 3.0;
+// bigger than the real sun (0.27 degrees) for softer, less noisy shadows
+const SUN_ANGULAR_RADIUS: f32 = 0.5 * PI / 180.0;
+// chance a lighting sample goes to the sun instead of the whole sphere
+const SUN_SAMPLE_PROBABILITY: f32 = 0.5;
+
+// preetham, shirley & smits 1999 analytic daylight.
+// the sky is a perez luminance distribution around the sun, the sun a disk
+// dimmed and reddened by the air and haze it crosses.
+// as an environment its lighting samples favor the sun, so it works as the
+// scene's directional light with proper shadows
+pub struct PreethamSky {
+    pub sun_dir: Vec3,
+    // haze, 2 is a clear day, 10 is murky
+    pub turbidity: f32,
+    pub ground_albedo: Vec3,
+    pub intensity: f32,
+
+    // derived from the above by update
+    zenith: [f32; 3], // Y, x, y
+    perez: [[f32; 5]; 3],
+    sun_radiance: Vec3,
+    ground_radiance: Vec3,
+    // fades everything out as the sun drops below the horizon
+    daylight: f32,
+}
+
+impl PreethamSky {
+    pub fn new(sun_dir: Vec3) -> PreethamSky {
+        let mut sky = PreethamSky {
+            sun_dir: sun_dir.normalize(),
+            turbidity: 3.0,
+            ground_albedo: Vec3::splat(0.3),
+            intensity: 1.0,
+            zenith: [0.0; 3],
+            perez: [[0.0; 5]; 3],
+            sun_radiance: Vec3::ZERO,
+            ground_radiance: Vec3::ZERO,
+            daylight: 0.0,
+        };
+        sky.update();
+        sky
+    }
+
+    pub fn turbidity(mut self, turbidity: f32) -> Self {
+        self.turbidity = turbidity.clamp(1.7, 10.0);
+        self.update();
+        self
+    }
+
+    pub fn ground_albedo(mut self, ground_albedo: Vec3) -> Self {
+        self.ground_albedo = ground_albedo;
+        self.update();
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self.update();
+        self
+    }
+
+    fn update(&mut self) {
+        let t = self.turbidity;
+        // the model only holds for a sun above the horizon
+        let theta_s = self.sun_dir.y.clamp(0.0, 1.0).acos().min(PI / 2.0 - 0.01);
+        self.daylight = smoothstep(-0.1, 0.05, self.sun_dir.y);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (t1, t2, t3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * t1)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * t1 + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * t1 + 0.25886);
+        let zenith_y = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * t1)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * t1 + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * t1 + 0.26688);
+        self.zenith = [zenith_luminance.max(0.0), zenith_x, zenith_y];
+
+        self.perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // sun: rayleigh and angstrom haze optical depths at 680, 550 and 440nm,
+        // through kasten's air mass
+        let elevation_deg = 90.0 - theta_s.to_degrees();
+        let air_mass = 1.0 / (theta_s.cos() + 0.50572 * (elevation_deg + 6.07995).powf(-1.6364));
+        let rayleigh = Vec3::new(0.0422, 0.1003, 0.2490);
+        let haze = Vec3::new(1.652, 2.175, 2.911) * (0.04608 * t - 0.04586);
+        let transmittance = (-(rayleigh + haze) * air_mass).exp();
+        self.sun_radiance = transmittance * SUN_IRRADIANCE / sun_solid_angle();
+
+        // lambertian ground under the sun and the zenith sky
+        let sun_irradiance = self.sun_radiance * sun_solid_angle() * self.sun_dir.y.max(0.0);
+        let sky_irradiance = self.sky(Vec3::Y) * PI;
+        self.ground_radiance = self.ground_albedo * (sun_irradiance + sky_irradiance) / PI;
+    }
+
+    fn perez(&self, channel: usize, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.perez[channel];
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+
+    // sky dome only, no sun disk, no daylight fade
+    fn sky(&self, dir: Vec3) -> Vec3 {
+        let cos_theta = dir.y.max(0.01);
+        let gamma = dir.dot(self.sun_dir).clamp(-1.0, 1.0).acos();
+        let cos_theta_s = self.sun_dir.y.clamp(0.01, 1.0);
+        let theta_s = cos_theta_s.acos();
+
+        let mut values = [0.0; 3];
+        for (channel, value) in values.iter_mut().enumerate() {
+            *value = self.zenith[channel] * self.perez(channel, cos_theta, gamma)
+                / self.perez(channel, 1.0, theta_s);
+        }
+        let [luminance, x, y] = values;
+        xyy_to_rgb(x, y, luminance) * SKY_EXPOSURE
+    }
+
+    fn in_sun(&self, dir: Vec3) -> bool {
+        dir.dot(self.sun_dir) >= SUN_ANGULAR_RADIUS.cos()
+    }
+
+    fn sun_probability(&self) -> f32 {
+        if self.sun_dir.y > -SUN_ANGULAR_RADIUS {
+            SUN_SAMPLE_PROBABILITY
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Environment for PreethamSky {
+    fn radiance(&self, dir: Vec3) -> Vec3 {
+        let radiance = if dir.y < 0.0 {
+            self.ground_radiance
+        } else if self.in_sun(dir) {
+            self.sky(dir) + self.sun_radiance
+        } else {
+            self.sky(dir)
+        };
+        radiance * self.daylight * self.intensity
+    }
+
+    fn sample(&self, rng: &mut SmallRng) -> (Vec3, f32) {
+        let sun_probability = self.sun_probability();
+        let dir = if rng.gen::<f32>() < sun_probability {
+            // uniform in the sun's cone
+            let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - SUN_ANGULAR_RADIUS.cos());
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let phi = 2.0 * PI * rng.gen::<f32>();
+            let tangent = perpendicular_to(self.sun_dir);
+            let bitangent = self.sun_dir.cross(tangent);
+            (tangent * phi.cos() + bitangent * phi.sin()) * sin_theta + self.sun_dir * cos_theta
+        } else {
+            // uniform over the sphere
+            let z = 1.0 - 2.0 * rng.gen::<f32>();
+            let r = (1.0 - z * z).sqrt();
+            let phi = 2.0 * PI * rng.gen::<f32>();
+            Vec3::new(r * phi.cos(), z, r * phi.sin())
+        };
+
+        // either strategy could have produced dir
+        let mut pdf = (1.0 - sun_probability) / (4.0 * PI);
+        if self.in_sun(dir) {
+            pdf += sun_probability / sun_solid_angle();
+        }
+        (dir, pdf)
+    }
+}
+
+fn sun_solid_angle() -> f32 {
+    2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos())
+}
+
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vec3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+    .max(Vec3::ZERO)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// sun over a 24 hour day: rises in +x at 6, peaks at noon, sets in -x at 18,
+// leaning towards -z, where the default camera sits, peaking at max_elevation
+pub fn sun_direction_at(hours: f32, max_elevation: f32) -> Vec3 {
+    let angle = (hours - 6.0) / 12.0 * PI;
+    Vec3::new(
+        angle.cos(),
+        angle.sin() * max_elevation.sin(),
+        -angle.sin() * max_elevation.cos(),
+    )
+    .normalize()
+}
@@ -1,10 +1,16 @@
 extern crate glam;
 
-use glam::{Vec3, Vec3A}; // Vec3A could provide better performance due to alignment
+use glam::{Vec2, Vec3};
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub dir: Vec3,
+    // ray cone for texture filtering: width at the origin, growth per unit distance
+    pub cone_width: f32,
+    pub cone_spread: f32,
+    // when in the shutter interval the ray was sent, 0 as it opens and 1 as it closes
+    pub time: f32,
 }
 
 impl Ray {
@@ -12,23 +18,29 @@ impl Ray {
         Ray {
             origin,
             dir: dir.normalize(),
+            cone_width: 0.0,
+            cone_spread: 0.0,
+            time: 0.0,
         }
     }
 
-    pub fn at(&self, t: f32) -> Vec3 {
-        self.origin + t * self.dir
+    pub fn with_cone(mut self, width: f32, spread: f32) -> Ray {
+        self.cone_width = width;
+        self.cone_spread = spread;
+        self
     }
-}
 
-#[derive(Clone)]
-pub struct Light {
-    pub pos: Vec3,
-    pub color: Vec3,
-}
+    pub fn with_time(mut self, time: f32) -> Ray {
+        self.time = time;
+        self
+    }
 
-impl Light {
-    pub fn new(pos: Vec3, color: Vec3) -> Light {
-        Light { pos, color }
+    pub fn cone_width_at(&self, t: f32) -> f32 {
+        self.cone_width + self.cone_spread * t
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + t * self.dir
     }
 }
 
@@ -38,6 +50,16 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub t: f32,
     pub front_face: bool,
+    // which primitive of a compound shape was hit, and where on it
+    pub prim_index: usize,
+    pub barycentric: Vec2,
+    // how far rounding may have put p from the true surface, per axis
+    pub p_error: Vec3,
+    // the true surface's normal, on the same side as normal. they only differ
+    // where normal is smoothed for shading, spawned rays leave along this one
+    pub geometric_normal: Vec3,
+    // the time of the ray that found it, so moving shapes know where they were
+    pub time: f32,
 }
 
 impl HitRecord {
@@ -47,6 +69,11 @@ impl HitRecord {
             normal: Vec3::ZERO,
             t: 0.0,
             front_face: false,
+            prim_index: 0,
+            barycentric: Vec2::ZERO,
+            p_error: Vec3::ZERO,
+            geometric_normal: Vec3::ZERO,
+            time: 0.0,
         }
     }
 
@@ -57,6 +84,45 @@ impl HitRecord {
         } else {
             -outward_normal
         };
+        self.geometric_normal = self.normal;
+    }
+
+    // after set_face_normal, for surfaces with interpolated normals
+    pub fn set_shading_normal(&mut self, shading_normal: Vec3) {
+        self.normal = if shading_normal.dot(self.geometric_normal) < 0.0 {
+            -shading_normal
+        } else {
+            shading_normal
+        };
+    }
+
+    // a ray leaving the hit along dir at the hit's time. its origin is pushed off
+    // the surface along the normal just past p_error, so it can't hit the surface
+    // it left again whatever the scene's scale
+    pub fn spawn_ray(&self, dir: Vec3) -> Ray {
+        let distance = self.geometric_normal.abs().dot(self.p_error);
+        let mut offset = self.geometric_normal * distance;
+        if dir.dot(self.geometric_normal) < 0.0 {
+            offset = -offset;
+        }
+        let origin = self.p + offset;
+
+        // round away from p too, so the addition can't eat the offset
+        let away = |origin: f32, offset: f32| {
+            if offset > 0.0 {
+                origin.next_up()
+            } else if offset < 0.0 {
+                origin.next_down()
+            } else {
+                origin
+            }
+        };
+        let origin = Vec3::new(
+            away(origin.x, offset.x),
+            away(origin.y, offset.y),
+            away(origin.z, offset.z),
+        );
+        Ray::new(origin, dir).with_time(self.time)
     }
 }
 
@@ -65,3 +131,42 @@ impl Default for HitRecord {
         Self::new()
     }
 }
+
+// where on a surface a material is being evaluated
+// uv drives image and 2d patterns, p drives solid (3d) patterns
+// duv_dx / duv_dy span the pixel footprint in uv space, zero means a point sample
+// dpdu / dpdv tie the two together, moving in uv moves p along them
+// vertex_color is the shape's own color there, white where it has none
+#[derive(Clone, Copy)]
+pub struct SurfacePoint {
+    pub uv: Vec2,
+    pub p: Vec3,
+    pub duv_dx: Vec2,
+    pub duv_dy: Vec2,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub vertex_color: Vec3,
+}
+
+impl SurfacePoint {
+    pub fn new(uv: Vec2, p: Vec3) -> SurfacePoint {
+        SurfacePoint {
+            uv,
+            p,
+            duv_dx: Vec2::ZERO,
+            duv_dy: Vec2::ZERO,
+            dpdu: Vec3::ZERO,
+            dpdv: Vec3::ZERO,
+            vertex_color: Vec3::splat(255.0),
+        }
+    }
+
+    // the same surface moved by duv, for finite differences
+    pub fn offset(&self, duv: Vec2) -> SurfacePoint {
+        SurfacePoint {
+            uv: self.uv + duv,
+            p: self.p + self.dpdu * duv.x + self.dpdv * duv.y,
+            ..*self
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use glam::{Vec2, Vec3};
+
+use crate::mesh::Mesh;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum SubdivisionScheme {
+    // triangles stay triangles, each level makes four of every one
+    Loop,
+    // every face becomes quads, one per corner. quads are split in two at the end
+    CatmullClark,
+}
+
+// smooths a mesh towards its subdivision surface, with normals from the limit
+// surface rather than the facets. vertices with the same position are joined
+// first, so seams in the uvs don't tear the surface apart
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Subdivision {
+    pub scheme: SubdivisionScheme,
+    pub levels: u32,
+}
+
+impl Subdivision {
+    pub fn new(scheme: SubdivisionScheme, levels: u32) -> Subdivision {
+        Subdivision { scheme, levels }
+    }
+
+    pub fn apply(&self, mesh: &Mesh) -> Mesh {
+        let mut surface = Surface::from_mesh(mesh);
+        for _ in 0..self.levels {
+            surface = match self.scheme {
+                SubdivisionScheme::Loop => surface.loop_level(),
+                SubdivisionScheme::CatmullClark => surface.catmull_clark_level(),
+            };
+        }
+        surface.to_mesh(self.scheme)
+    }
+}
+
+// a face's corner, uvs and colors belong to corners so they can differ either
+// side of a seam
+#[derive(Clone, Copy)]
+struct Corner {
+    vertex: u32,
+    uv: Vec2,
+    color: Vec3,
+}
+
+// each edge's number, keyed by its vertices lowest first
+type EdgeIndices = HashMap<(u32, u32), usize>;
+
+// polygons over shared vertices
+struct Surface {
+    positions: Vec<Vec3>,
+    faces: Vec<Vec<Corner>>,
+    has_colors: bool,
+}
+
+impl Surface {
+    fn from_mesh(mesh: &Mesh) -> Surface {
+        let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+        let mut positions = Vec::new();
+        let vertices: Vec<u32> = mesh
+            .positions
+            .iter()
+            .map(|position| {
+                *welded
+                    .entry(position.to_array().map(f32::to_bits))
+                    .or_insert_with(|| {
+                        positions.push(*position);
+                        positions.len() as u32 - 1
+                    })
+            })
+            .collect();
+
+        // poles and other spots where corners weld together leave slivers
+        // with no area, those would only tangle the edges up
+        let faces = mesh
+            .indices
+            .chunks_exact(3)
+            .filter(|face| {
+                let [a, b, c] = [face[0], face[1], face[2]].map(|i| vertices[i as usize]);
+                a != b && b != c && c != a
+            })
+            .map(|face| {
+                face.iter()
+                    .map(|&index| Corner {
+                        vertex: vertices[index as usize],
+                        uv: mesh.uvs[index as usize],
+                        color: mesh
+                            .colors
+                            .get(index as usize)
+                            .copied()
+                            .unwrap_or(Vec3::ZERO),
+                    })
+                    .collect()
+            })
+            .collect();
+        Surface {
+            positions,
+            faces,
+            has_colors: !mesh.colors.is_empty(),
+        }
+    }
+
+    // each edge once, numbered in the order the faces reach them, with the faces on it
+    fn edges(&self) -> (EdgeIndices, Vec<Vec<usize>>) {
+        let mut edge_indices = HashMap::new();
+        let mut edge_faces: Vec<Vec<usize>> = Vec::new();
+        for (face_index, face) in self.faces.iter().enumerate() {
+            for (a, b) in face_edges(face) {
+                let index = *edge_indices.entry(edge_key(a, b)).or_insert_with(|| {
+                    edge_faces.push(Vec::new());
+                    edge_faces.len() - 1
+                });
+                edge_faces[index].push(face_index);
+            }
+        }
+        (edge_indices, edge_faces)
+    }
+
+    // every vertex's neighbours, and the ones it shares an open edge with
+    fn neighbours(
+        &self,
+        edge_indices: &EdgeIndices,
+        edge_faces: &[Vec<usize>],
+    ) -> (Vec<Vec<u32>>, Vec<Vec<u32>>) {
+        let mut neighbours = vec![Vec::new(); self.positions.len()];
+        let mut boundary_neighbours = vec![Vec::new(); self.positions.len()];
+        for (&(a, b), &index) in edge_indices {
+            neighbours[a as usize].push(b);
+            neighbours[b as usize].push(a);
+            if edge_faces[index].len() == 1 {
+                boundary_neighbours[a as usize].push(b);
+                boundary_neighbours[b as usize].push(a);
+            }
+        }
+        (neighbours, boundary_neighbours)
+    }
+
+    // along an open edge the surface follows the edge's own curve
+    fn boundary_vertex(&self, vertex: usize, boundary_neighbours: &[u32]) -> Vec3 {
+        let position = self.positions[vertex];
+        match boundary_neighbours {
+            [a, b] => {
+                0.75 * position
+                    + 0.125 * (self.positions[*a as usize] + self.positions[*b as usize])
+            }
+            // corners and non manifold vertices stay put
+            _ => position,
+        }
+    }
+
+    fn loop_level(&self) -> Surface {
+        let (edge_indices, edge_faces) = self.edges();
+        let (neighbours, boundary_neighbours) = self.neighbours(&edge_indices, &edge_faces);
+
+        let mut positions: Vec<Vec3> = (0..self.positions.len())
+            .map(|vertex| {
+                if !boundary_neighbours[vertex].is_empty() {
+                    return self.boundary_vertex(vertex, &boundary_neighbours[vertex]);
+                }
+                let n = neighbours[vertex].len() as f32;
+                let beta = if neighbours[vertex].len() == 3 {
+                    3.0 / 16.0
+                } else {
+                    3.0 / (8.0 * n)
+                };
+                let ring: Vec3 = neighbours[vertex]
+                    .iter()
+                    .map(|&neighbour| self.positions[neighbour as usize])
+                    .sum();
+                (1.0 - n * beta) * self.positions[vertex] + beta * ring
+            })
+            .collect();
+
+        // edge vertices go after the old ones, in edge order
+        let edge_base = positions.len() as u32;
+        let mut edge_points = vec![Vec3::ZERO; edge_faces.len()];
+        for (&(a, b), &index) in &edge_indices {
+            let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+            edge_points[index] = match edge_faces[index][..] {
+                [f0, f1] => {
+                    let opposite = |face: usize| {
+                        let corner = self.faces[face]
+                            .iter()
+                            .find(|corner| corner.vertex != a && corner.vertex != b)
+                            .unwrap();
+                        self.positions[corner.vertex as usize]
+                    };
+                    0.375 * (pa + pb) + 0.125 * (opposite(f0) + opposite(f1))
+                }
+                _ => 0.5 * (pa + pb),
+            };
+        }
+        positions.extend(edge_points);
+
+        let edge_corner = |a: Corner, b: Corner| Corner {
+            vertex: edge_base + edge_indices[&edge_key(a.vertex, b.vertex)] as u32,
+            uv: (a.uv + b.uv) * 0.5,
+            color: (a.color + b.color) * 0.5,
+        };
+        let faces = self
+            .faces
+            .iter()
+            .flat_map(|face| {
+                let [a, b, c] = [face[0], face[1], face[2]];
+                let (ab, bc, ca) = (edge_corner(a, b), edge_corner(b, c), edge_corner(c, a));
+                [
+                    vec![a, ab, ca],
+                    vec![ab, b, bc],
+                    vec![ca, bc, c],
+                    vec![ab, bc, ca],
+                ]
+            })
+            .collect();
+        Surface {
+            positions,
+            faces,
+            has_colors: self.has_colors,
+        }
+    }
+
+    fn catmull_clark_level(&self) -> Surface {
+        let (edge_indices, edge_faces) = self.edges();
+        let (neighbours, boundary_neighbours) = self.neighbours(&edge_indices, &edge_faces);
+
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| {
+                face.iter()
+                    .map(|corner| self.positions[corner.vertex as usize])
+                    .sum::<Vec3>()
+                    / face.len() as f32
+            })
+            .collect();
+
+        let mut vertex_faces = vec![Vec::new(); self.positions.len()];
+        for (face_index, face) in self.faces.iter().enumerate() {
+            for corner in face {
+                vertex_faces[corner.vertex as usize].push(face_index);
+            }
+        }
+
+        let mut positions: Vec<Vec3> = (0..self.positions.len())
+            .map(|vertex| {
+                if !boundary_neighbours[vertex].is_empty() {
+                    return self.boundary_vertex(vertex, &boundary_neighbours[vertex]);
+                }
+                let position = self.positions[vertex];
+                let n = neighbours[vertex].len() as f32;
+                let faces_average = vertex_faces[vertex]
+                    .iter()
+                    .map(|&face| face_points[face])
+                    .sum::<Vec3>()
+                    / vertex_faces[vertex].len() as f32;
+                let edges_average = neighbours[vertex]
+                    .iter()
+                    .map(|&neighbour| (position + self.positions[neighbour as usize]) * 0.5)
+                    .sum::<Vec3>()
+                    / n;
+                (faces_average + 2.0 * edges_average + (n - 3.0) * position) / n
+            })
+            .collect();
+
+        let edge_base = positions.len() as u32;
+        let mut edge_points = vec![Vec3::ZERO; edge_faces.len()];
+        for (&(a, b), &index) in &edge_indices {
+            let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+            edge_points[index] = match edge_faces[index][..] {
+                [f0, f1] => (pa + pb + face_points[f0] + face_points[f1]) * 0.25,
+                _ => 0.5 * (pa + pb),
+            };
+        }
+        positions.extend(edge_points);
+        let face_base = positions.len() as u32;
+        positions.extend(&face_points);
+
+        let edge_corner = |a: Corner, b: Corner| Corner {
+            vertex: edge_base + edge_indices[&edge_key(a.vertex, b.vertex)] as u32,
+            uv: (a.uv + b.uv) * 0.5,
+            color: (a.color + b.color) * 0.5,
+        };
+        let faces = self
+            .faces
+            .iter()
+            .enumerate()
+            .flat_map(|(face_index, face)| {
+                let center = Corner {
+                    vertex: face_base + face_index as u32,
+                    uv: face.iter().map(|corner| corner.uv).sum::<Vec2>() / face.len() as f32,
+                    color: face.iter().map(|corner| corner.color).sum::<Vec3>() / face.len() as f32,
+                };
+                let k = face.len();
+                (0..k)
+                    .map(|i| {
+                        let (previous, corner, next) =
+                            (face[(i + k - 1) % k], face[i], face[(i + 1) % k]);
+                        vec![
+                            corner,
+                            edge_corner(corner, next),
+                            center,
+                            edge_corner(previous, corner),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Surface {
+            positions,
+            faces,
+            has_colors: self.has_colors,
+        }
+    }
+
+    // the faces around a vertex in winding order, each as its vertices after
+    // the given one. None on open edges or where the faces don't make one fan
+    fn fan(&self, vertex: u32, faces: &[usize]) -> Option<Vec<Vec<u32>>> {
+        let mut by_first: HashMap<u32, Vec<u32>> = HashMap::new();
+        for &face_index in faces {
+            let face = &self.faces[face_index];
+            let at = face.iter().position(|corner| corner.vertex == vertex)?;
+            let rest: Vec<u32> = (1..face.len())
+                .map(|offset| face[(at + offset) % face.len()].vertex)
+                .collect();
+            if by_first.insert(rest[0], rest).is_some() {
+                return None;
+            }
+        }
+
+        let start = *by_first.keys().next()?;
+        let mut fan = Vec::with_capacity(faces.len());
+        let mut next = start;
+        for _ in 0..faces.len() {
+            let rest = by_first.get(&next)?;
+            next = *rest.last().unwrap();
+            fan.push(rest.clone());
+        }
+        (next == start).then_some(fan)
+    }
+
+    // sum of the faces' area weighted normals at each vertex
+    fn face_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+        for face in &self.faces {
+            let origin = self.positions[face[0].vertex as usize];
+            let normal: Vec3 = (1..face.len() - 1)
+                .map(|i| {
+                    let b = self.positions[face[i].vertex as usize];
+                    let c = self.positions[face[i + 1].vertex as usize];
+                    (b - origin).cross(c - origin)
+                })
+                .sum();
+            for corner in face {
+                normals[corner.vertex as usize] += normal;
+            }
+        }
+        normals
+    }
+
+    // the limit surface's normal from the tangent masks over the vertex's ring,
+    // Loop's for triangles and Halstead et al's for Catmull-Clark quads. open
+    // edges and odd fans fall back to the faces' normals
+    fn limit_normals(&self, scheme: SubdivisionScheme) -> Vec<Vec3> {
+        let mut vertex_faces = vec![Vec::new(); self.positions.len()];
+        for (face_index, face) in self.faces.iter().enumerate() {
+            for corner in face {
+                vertex_faces[corner.vertex as usize].push(face_index);
+            }
+        }
+
+        let face_normals = self.face_normals();
+        (0..self.positions.len())
+            .map(|vertex| {
+                let fallback = face_normals[vertex].normalize_or_zero();
+                let Some(fan) = self.fan(vertex as u32, &vertex_faces[vertex]) else {
+                    return fallback;
+                };
+                let n = fan.len();
+                let angle = |i: usize| 2.0 * PI * i as f32 / n as f32;
+                let point = |vertex: u32| self.positions[vertex as usize];
+
+                let (mut t1, mut t2) = (Vec3::ZERO, Vec3::ZERO);
+                match scheme {
+                    SubdivisionScheme::Loop => {
+                        for (i, rest) in fan.iter().enumerate() {
+                            t1 += angle(i).cos() * point(rest[0]);
+                            t2 += angle(i).sin() * point(rest[0]);
+                        }
+                    }
+                    SubdivisionScheme::CatmullClark => {
+                        let a = 1.0
+                            + angle(1).cos()
+                            + (PI / n as f32).cos() * (2.0 * (9.0 + angle(1).cos())).sqrt();
+                        for (i, rest) in fan.iter().enumerate() {
+                            let [edge, diagonal, _] = rest[..] else {
+                                return fallback;
+                            };
+                            t1 += a * angle(i).cos() * point(edge)
+                                + (angle(i).cos() + angle(i + 1).cos()) * point(diagonal);
+                            t2 += a * angle(i).sin() * point(edge)
+                                + (angle(i).sin() + angle(i + 1).sin()) * point(diagonal);
+                        }
+                    }
+                }
+
+                // the masks don't know which way is out, the faces do
+                let normal = t1.cross(t2).normalize_or_zero();
+                if normal == Vec3::ZERO {
+                    fallback
+                } else if normal.dot(face_normals[vertex]) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                }
+            })
+            .collect()
+    }
+
+    // back to triangles, a vertex for each position, uv and color
+    fn to_mesh(&self, scheme: SubdivisionScheme) -> Mesh {
+        let normals = self.limit_normals(scheme);
+        let mut mesh = Mesh::default();
+        let mut vertices: HashMap<(u32, [u32; 2], [u32; 3]), u32> = HashMap::new();
+        for face in &self.faces {
+            let indices: Vec<u32> = face
+                .iter()
+                .map(|corner| {
+                    *vertices
+                        .entry((
+                            corner.vertex,
+                            corner.uv.to_array().map(f32::to_bits),
+                            corner.color.to_array().map(f32::to_bits),
+                        ))
+                        .or_insert_with(|| {
+                            mesh.positions.push(self.positions[corner.vertex as usize]);
+                            mesh.normals.push(normals[corner.vertex as usize]);
+                            mesh.uvs.push(corner.uv);
+                            if self.has_colors {
+                                mesh.colors.push(corner.color);
+                            }
+                            mesh.positions.len() as u32 - 1
+                        })
+                })
+                .collect();
+            for i in 1..indices.len() - 1 {
+                mesh.indices
+                    .extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+            }
+        }
+        mesh
+    }
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+fn face_edges(face: &[Corner]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    (0..face.len()).map(|i| (face[i].vertex, face[(i + 1) % face.len()].vertex))
+}
@@ -0,0 +1,410 @@
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+use half::f16;
+use image::{DynamicImage, GenericImageView};
+
+// what happens to uvs outside [0, 1]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AddressMode {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    // bilinear on the two mip levels around the footprint, blended
+    Trilinear,
+    // elliptical weighted average over the (possibly stretched) footprint
+    Anisotropic,
+}
+
+// longest allowed ellipse axis relative to the shortest,
+// past this the ellipse is fattened and a blurrier mip is used
+const MAX_ANISOTROPY: f32 = 8.0;
+
+// texels are kept compact and decoded to floats on fetch
+#[derive(Clone)]
+enum Texels {
+    Rgb8(Vec<[u8; 3]>),
+    // for anything that does not fit 8 bits, hdr images etc
+    RgbF16(Vec<[f16; 3]>),
+}
+
+// hdr suns can go past what f16 holds, saturate instead of turning into inf
+fn to_f16(v: f32) -> f16 {
+    f16::from_f32(v.clamp(f16::MIN.to_f32(), f16::MAX.to_f32()))
+}
+
+impl Texels {
+    fn get(&self, i: usize) -> Vec3 {
+        match self {
+            Texels::Rgb8(data) => {
+                let [r, g, b] = data[i];
+                Vec3::new(r as f32, g as f32, b as f32)
+            }
+            Texels::RgbF16(data) => {
+                let [r, g, b] = data[i];
+                Vec3::new(r.to_f32(), g.to_f32(), b.to_f32())
+            }
+        }
+    }
+
+    // same encoding as self, filled from float values
+    fn encode_like(&self, values: &[Vec3]) -> Texels {
+        match self {
+            Texels::Rgb8(_) => Texels::Rgb8(
+                values
+                    .iter()
+                    .map(|v| {
+                        let v = v.round().clamp(Vec3::ZERO, Vec3::splat(255.0));
+                        [v.x as u8, v.y as u8, v.z as u8]
+                    })
+                    .collect(),
+            ),
+            Texels::RgbF16(_) => Texels::RgbF16(
+                values
+                    .iter()
+                    .map(|v| [to_f16(v.x), to_f16(v.y), to_f16(v.z)])
+                    .collect(),
+            ),
+        }
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        match self {
+            Texels::Rgb8(data) => std::mem::size_of_val(data.as_slice()),
+            Texels::RgbF16(data) => std::mem::size_of_val(data.as_slice()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Texels,
+}
+
+impl MipLevel {
+    // 2x2 box filter, odd edges reuse the last row / column
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut values = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (2 * x).min(self.width - 1);
+                let x1 = (2 * x + 1).min(self.width - 1);
+                let y0 = (2 * y).min(self.height - 1);
+                let y1 = (2 * y + 1).min(self.height - 1);
+                let sum = self.get(x0, y0) + self.get(x1, y0) + self.get(x0, y1) + self.get(x1, y1);
+                values.push(sum / 4.0);
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            texels: self.texels.encode_like(&values),
+        }
+    }
+
+    // raw stored value, before the texture's decode
+    fn get(&self, x: u32, y: u32) -> Vec3 {
+        self.texels.get((y * self.width + x) as usize)
+    }
+
+    fn texel(&self, x: i32, y: i32, address: AddressMode) -> Vec3 {
+        let x = address_texel(x, self.width as i32, address);
+        let y = address_texel(y, self.height as i32, address);
+        self.get(x as u32, y as u32)
+    }
+
+    fn bilinear(&self, uv: Vec2, address: AddressMode) -> Vec3 {
+        // texel centers sit on the half integers
+        let s = uv.x * self.width as f32 - 0.5;
+        let t = uv.y * self.height as f32 - 0.5;
+        let x0 = s.floor();
+        let y0 = t.floor();
+        let fx = s - x0;
+        let fy = t - y0;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self
+            .texel(x0, y0, address)
+            .lerp(self.texel(x0 + 1, y0, address), fx);
+        let bottom = self
+            .texel(x0, y0 + 1, address)
+            .lerp(self.texel(x0 + 1, y0 + 1, address), fx);
+        top.lerp(bottom, fy)
+    }
+
+    // pbrt style ewa: gaussian weights over every texel inside the ellipse
+    fn ewa(&self, uv: Vec2, axis0: Vec2, axis1: Vec2, address: AddressMode) -> Vec3 {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let s = uv.x * size.x - 0.5;
+        let t = uv.y * size.y - 0.5;
+        let d0 = axis0 * size;
+        let d1 = axis1 * size;
+
+        // implicit ellipse A s^2 + B s t + C t^2 = F, normalized so F = 1
+        let mut a = d0.y * d0.y + d1.y * d1.y + 1.0;
+        let mut b = -2.0 * (d0.x * d0.y + d1.x * d1.y);
+        let mut c = d0.x * d0.x + d1.x * d1.x + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = 1.0 / det;
+        let u_sqrt = (det * c).sqrt();
+        let v_sqrt = (a * det).sqrt();
+        let s0 = (s - 2.0 * inv_det * u_sqrt).ceil() as i32;
+        let s1 = (s + 2.0 * inv_det * u_sqrt).floor() as i32;
+        let t0 = (t - 2.0 * inv_det * v_sqrt).ceil() as i32;
+        let t1 = (t + 2.0 * inv_det * v_sqrt).floor() as i32;
+
+        const ALPHA: f32 = 2.0;
+        let mut sum = Vec3::ZERO;
+        let mut total_weight = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f32 - t;
+            for is in s0..=s1 {
+                let ss = is as f32 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum += self.texel(is, it, address) * weight;
+                    total_weight += weight;
+                }
+            }
+        }
+
+        if total_weight > 0.0 {
+            sum / total_weight
+        } else {
+            self.bilinear(uv, address)
+        }
+    }
+}
+
+fn address_texel(i: i32, size: i32, address: AddressMode) -> i32 {
+    match address {
+        AddressMode::Clamp => i.clamp(0, size - 1),
+        AddressMode::Repeat => i.rem_euclid(size),
+        AddressMode::Mirror => {
+            let m = i.rem_euclid(2 * size);
+            if m >= size {
+                2 * size - 1 - m
+            } else {
+                m
+            }
+        }
+    }
+}
+
+// an image plus its mip pyramid, level 0 is full resolution
+// the texel storage is shared, so remapped copies of a texture are cheap
+// filtering is linear, so texels are filtered raw and decoded once at the end
+#[derive(Clone)]
+pub struct Texture {
+    levels: Arc<[MipLevel]>,
+    // decoded = raw * scale + offset
+    scale: f32,
+    offset: f32,
+}
+
+// the same texels read the same way. textures loaded apart are never compared
+// texel by texel, that would cost more than rebuilding whatever was keyed on them
+impl PartialEq for Texture {
+    fn eq(&self, other: &Texture) -> bool {
+        Arc::ptr_eq(&self.levels, &other.levels)
+            && self.scale == other.scale
+            && self.offset == other.offset
+    }
+}
+
+impl Texture {
+    // float data is stored as half floats
+    pub fn new(width: u32, height: u32, data: Vec<Vec3>) -> Texture {
+        let texels = Texels::RgbF16(Vec::new()).encode_like(&data);
+        Texture::from_texels(width, height, texels)
+    }
+
+    fn from_texels(width: u32, height: u32, texels: Texels) -> Texture {
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        while {
+            let last = levels.last().unwrap();
+            last.width > 1 || last.height > 1
+        } {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+        Texture {
+            levels: levels.into(),
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    // 8 bit images stay 8 bit (values 0-255), deeper ones become half floats
+    pub fn from_image(image: &DynamicImage) -> Texture {
+        let (width, height) = image.dimensions();
+        let texels = match image {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_) => Texels::Rgb8(
+                image
+                    .to_rgb8()
+                    .pixels()
+                    .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect(),
+            ),
+            _ => Texels::RgbF16(
+                image
+                    .to_rgb32f()
+                    .pixels()
+                    .map(|pixel| [to_f16(pixel[0]), to_f16(pixel[1]), to_f16(pixel[2])])
+                    .collect(),
+            ),
+        };
+
+        let mut texture = Texture::from_texels(width, height, texels);
+        if matches!(texture.levels[0].texels, Texels::RgbF16(_)) {
+            // float images come in as 0-1, scale them like the 8 bit ones
+            texture.scale = 255.0;
+        }
+        texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    // texel bytes across all mip levels
+    pub fn size_in_bytes(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| level.texels.size_in_bytes())
+            .sum()
+    }
+
+    // remapping only touches the decode scale / offset, texels stay as loaded
+    pub fn remap(&mut self, current_min: f32, current_max: f32, new_min: f32, new_max: f32) {
+        let k = (new_max - new_min) / (current_max - current_min);
+        self.scale *= k;
+        self.offset = (self.offset - current_min) * k + new_min;
+    }
+
+    pub fn normalize_from_255(&mut self) {
+        self.remap(0.0, 255.0, 0.0, 1.0);
+    }
+
+    pub fn normalize_from_255_to_full_range(&mut self) {
+        self.remap(0.0, 255.0, -1.0, 1.0)
+    }
+
+    fn decode(&self, raw: Vec3) -> Vec3 {
+        raw * self.scale + Vec3::splat(self.offset)
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Vec3 {
+        self.decode(self.levels[0].get(x, y))
+    }
+
+    // nearest texel on the full resolution level
+    pub fn sample(&self, uv: Vec2, address: AddressMode) -> Vec3 {
+        self.decode(self.nearest(uv, address))
+    }
+
+    fn nearest(&self, uv: Vec2, address: AddressMode) -> Vec3 {
+        let level = &self.levels[0];
+        let x = (uv.x * level.width as f32).floor() as i32;
+        let y = (uv.y * level.height as f32).floor() as i32;
+        level.texel(x, y, address)
+    }
+
+    // duv_dx / duv_dy span the pixel footprint in uv space
+    pub fn filter(
+        &self,
+        uv: Vec2,
+        duv_dx: Vec2,
+        duv_dy: Vec2,
+        address: AddressMode,
+        filter: FilterMode,
+    ) -> Vec3 {
+        let raw = match filter {
+            FilterMode::Nearest => self.nearest(uv, address),
+            FilterMode::Bilinear => self.levels[0].bilinear(uv, address),
+            FilterMode::Trilinear => self.trilinear(uv, duv_dx, duv_dy, address),
+            FilterMode::Anisotropic => self.anisotropic(uv, duv_dx, duv_dy, address),
+        };
+        self.decode(raw)
+    }
+
+    fn texel_size(&self) -> Vec2 {
+        Vec2::new(self.width() as f32, self.height() as f32)
+    }
+
+    fn lerp_levels(&self, lod: f32, sample: impl Fn(&MipLevel) -> Vec3) -> Vec3 {
+        let max_level = (self.levels.len() - 1) as f32;
+        let lod = lod.clamp(0.0, max_level);
+        let lower = lod.floor();
+        let fraction = lod - lower;
+        let lower = lower as usize;
+        if fraction == 0.0 || lower + 1 >= self.levels.len() {
+            return sample(&self.levels[lower]);
+        }
+        sample(&self.levels[lower]).lerp(sample(&self.levels[lower + 1]), fraction)
+    }
+
+    fn trilinear(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2, address: AddressMode) -> Vec3 {
+        let size = self.texel_size();
+        let width = (duv_dx * size).length().max((duv_dy * size).length());
+        if width <= 1.0 {
+            return self.levels[0].bilinear(uv, address);
+        }
+        self.lerp_levels(width.log2(), |level| level.bilinear(uv, address))
+    }
+
+    fn anisotropic(&self, uv: Vec2, duv_dx: Vec2, duv_dy: Vec2, address: AddressMode) -> Vec3 {
+        let size = self.texel_size();
+        let (mut major, mut minor) = (duv_dx, duv_dy);
+        if (major * size).length_squared() < (minor * size).length_squared() {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let major_length = (major * size).length();
+        let mut minor_length = (minor * size).length();
+        if minor_length == 0.0 {
+            return self.trilinear(uv, duv_dx, duv_dy, address);
+        }
+
+        // clamp the eccentricity so very stretched footprints stay cheap
+        if minor_length * MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor *= scale;
+            minor_length *= scale;
+        }
+
+        // pick the level where the minor axis covers about one texel
+        let lod = minor_length.max(1.0).log2();
+        self.lerp_levels(lod, |level| level.ewa(uv, major, minor, address))
+    }
+}
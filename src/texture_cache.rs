@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use image::{codecs::hdr::HdrDecoder, DynamicImage, ImageBuffer, ImageResult};
+
+use crate::{
+    error::{Error, Result},
+    texture::Texture,
+};
+
+// how the texels of a file get decoded, the same file can be used several ways
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureUsage {
+    // 0-255, like every other color in the tracer
+    Color,
+    // 0-1, for roughness / reflection / etc masks
+    Mask,
+    // -1 to 1, tangent space normal maps
+    Normal,
+}
+
+// a key's value, filled by whichever thread gets to it first. the others wait on
+// its lock instead of decoding the same file again
+type Slot<T> = Arc<Mutex<Option<T>>>;
+type TextureKey = (PathBuf, TextureUsage);
+
+// loads every image file once, no matter how many materials or frames use it
+// different usages of one file share the decoded texels
+#[derive(Default)]
+pub struct TextureCache {
+    files: Mutex<HashMap<PathBuf, Slot<Texture>>>,
+    textures: Mutex<HashMap<TextureKey, Slot<Arc<Texture>>>>,
+}
+
+impl TextureCache {
+    pub fn global() -> &'static TextureCache {
+        static CACHE: OnceLock<TextureCache> = OnceLock::new();
+        CACHE.get_or_init(TextureCache::default)
+    }
+
+    pub fn load(&self, path: &str, usage: TextureUsage) -> Result<Arc<Texture>> {
+        // keyed on the real path so ./a.png and a.png are one file
+        let canonical = fs::canonicalize(path).map_err(|error| Error::io(path, error))?;
+        let slot = self
+            .textures
+            .lock()
+            .unwrap()
+            .entry((canonical.clone(), usage))
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().unwrap();
+        if let Some(texture) = slot.as_ref() {
+            return Ok(texture.clone());
+        }
+
+        let mut texture = self.load_file(path, canonical)?;
+        match usage {
+            TextureUsage::Color => {}
+            TextureUsage::Mask => texture.normalize_from_255(),
+            TextureUsage::Normal => texture.normalize_from_255_to_full_range(),
+        }
+        Ok(slot.insert(Arc::new(texture)).clone())
+    }
+
+    fn load_file(&self, path: &str, canonical: PathBuf) -> Result<Texture> {
+        let slot = self
+            .files
+            .lock()
+            .unwrap()
+            .entry(canonical.clone())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().unwrap();
+        if let Some(texture) = slot.as_ref() {
+            return Ok(texture.clone());
+        }
+
+        // nothing is cached for a file that failed, so fixing it and trying again works
+        let dimage = open_image(&canonical).map_err(|error| Error::image(path, error))?;
+        Ok(slot.insert(Texture::from_image(&dimage)).clone())
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.files
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|slot| slot.lock().unwrap().is_some())
+            .count()
+    }
+
+    // texel bytes held by the cache, mips included
+    pub fn memory_usage(&self) -> usize {
+        self.files
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|slot| slot.lock().unwrap().as_ref().map(Texture::size_in_bytes))
+            .sum()
+    }
+}
+
+fn open_image(path: &Path) -> ImageResult<DynamicImage> {
+    // image::open squashes radiance files down to 8 bits, keep the floats
+    let is_hdr = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+    if is_hdr {
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?;
+        let buffer = ImageBuffer::from_fn(metadata.width, metadata.height, |x, y| {
+            pixels[(y * metadata.width + x) as usize]
+        });
+        return Ok(DynamicImage::ImageRgb32F(buffer));
+    }
+    image::open(path)
+}
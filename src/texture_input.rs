@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+
+use crate::{
+    error::Result,
+    procedural::ProceduralTexture,
+    structures::SurfacePoint,
+    texture::{AddressMode, FilterMode, Texture},
+    texture_cache::{TextureCache, TextureUsage},
+};
+
+// a node in a small texture graph, any material channel can be driven by one
+// scalar channels read the average of the three components
+#[derive(Clone, PartialEq)]
+pub enum TextureInput {
+    Constant(f32),
+    Color(Vec3),
+    Image {
+        texture: Arc<Texture>,
+        scale: Vec2,
+        address: AddressMode,
+        filter: FilterMode,
+    },
+    // procedural patterns evaluate to a grey value in [0, 1]
+    Procedural(Box<ProceduralTexture>),
+    Checker {
+        scale: f32,
+        a: Box<TextureInput>,
+        b: Box<TextureInput>,
+    },
+    // a * (1 - t) + b * t
+    Mix {
+        a: Box<TextureInput>,
+        b: Box<TextureInput>,
+        t: Box<TextureInput>,
+    },
+    Multiply(Box<TextureInput>, Box<TextureInput>),
+    Add(Box<TextureInput>, Box<TextureInput>),
+    Invert(Box<TextureInput>),
+    // linear remap from one range into another, not clamped. an empty from
+    // range becomes a step at from.x
+    Remap {
+        input: Box<TextureInput>,
+        from: Vec2,
+        to: Vec2,
+    },
+    // the color the shape carries at the hit, like a scan's per vertex colors
+    VertexColor,
+}
+
+impl TextureInput {
+    // raw texel values, 0-255 like every other color in the tracer
+    pub fn image(texture_path: &str, scale: Vec2, address: AddressMode) -> Result<TextureInput> {
+        Ok(TextureInput::Image {
+            texture: TextureCache::global().load(texture_path, TextureUsage::Color)?,
+            scale,
+            address,
+            filter: FilterMode::Trilinear,
+        })
+    }
+
+    // texels remapped to [0, 1] for roughness / reflection / etc masks
+    pub fn image_mask(
+        texture_path: &str,
+        scale: Vec2,
+        address: AddressMode,
+    ) -> Result<TextureInput> {
+        Ok(TextureInput::Image {
+            texture: TextureCache::global().load(texture_path, TextureUsage::Mask)?,
+            scale,
+            address,
+            filter: FilterMode::Trilinear,
+        })
+    }
+
+    // texels remapped to [-1, 1] for tangent space normal maps
+    pub fn normal_map(
+        texture_path: &str,
+        scale: Vec2,
+        address: AddressMode,
+    ) -> Result<TextureInput> {
+        Ok(TextureInput::Image {
+            texture: TextureCache::global().load(texture_path, TextureUsage::Normal)?,
+            scale,
+            address,
+            filter: FilterMode::Trilinear,
+        })
+    }
+
+    pub fn checker(scale: f32, a: impl Into<TextureInput>, b: impl Into<TextureInput>) -> Self {
+        TextureInput::Checker {
+            scale,
+            a: Box::new(a.into()),
+            b: Box::new(b.into()),
+        }
+    }
+
+    pub fn mix(
+        a: impl Into<TextureInput>,
+        b: impl Into<TextureInput>,
+        t: impl Into<TextureInput>,
+    ) -> TextureInput {
+        TextureInput::Mix {
+            a: Box::new(a.into()),
+            b: Box::new(b.into()),
+            t: Box::new(t.into()),
+        }
+    }
+
+    pub fn multiply(a: impl Into<TextureInput>, b: impl Into<TextureInput>) -> TextureInput {
+        TextureInput::Multiply(Box::new(a.into()), Box::new(b.into()))
+    }
+
+    pub fn add(a: impl Into<TextureInput>, b: impl Into<TextureInput>) -> TextureInput {
+        TextureInput::Add(Box::new(a.into()), Box::new(b.into()))
+    }
+
+    pub fn invert(input: impl Into<TextureInput>) -> TextureInput {
+        TextureInput::Invert(Box::new(input.into()))
+    }
+
+    pub fn remap(input: impl Into<TextureInput>, from: Vec2, to: Vec2) -> TextureInput {
+        TextureInput::Remap {
+            input: Box::new(input.into()),
+            from,
+            to,
+        }
+    }
+
+    pub fn eval(&self, sp: &SurfacePoint) -> Vec3 {
+        match self {
+            TextureInput::Constant(value) => Vec3::splat(*value),
+            TextureInput::Color(color) => *color,
+            TextureInput::Image {
+                texture,
+                scale,
+                address,
+                filter,
+            } => texture.filter(
+                sp.uv / *scale,
+                sp.duv_dx / *scale,
+                sp.duv_dy / *scale,
+                *address,
+                *filter,
+            ),
+            TextureInput::Procedural(texture) => Vec3::splat(texture.value_at(sp)),
+            TextureInput::Checker { scale, a, b } => {
+                let pattern = ((sp.uv.x * scale).floor() as i32 + (sp.uv.y * scale).floor() as i32)
+                    .rem_euclid(2);
+                if pattern == 0 {
+                    a.eval(sp)
+                } else {
+                    b.eval(sp)
+                }
+            }
+            TextureInput::Mix { a, b, t } => a.eval(sp).lerp(b.eval(sp), t.value(sp)),
+            TextureInput::Multiply(a, b) => a.eval(sp) * b.eval(sp),
+            TextureInput::Add(a, b) => a.eval(sp) + b.eval(sp),
+            TextureInput::Invert(input) => Vec3::ONE - input.eval(sp),
+            TextureInput::Remap { input, from, to } => {
+                let value = input.eval(sp);
+                if from.x == from.y {
+                    let above = value.cmpge(Vec3::splat(from.x));
+                    return Vec3::select(above, Vec3::splat(to.y), Vec3::splat(to.x));
+                }
+                let t = (value - Vec3::splat(from.x)) / (from.y - from.x);
+                Vec3::splat(to.x) + t * (to.y - to.x)
+            }
+            TextureInput::VertexColor => sp.vertex_color,
+        }
+    }
+
+    // tangent space normal of the surface pushed out by value() as a height,
+    // central differences over the pixel footprint so distant bumps dont alias.
+    // x and y are the slopes along u and v, scale them to set the bump strength
+    pub fn bump_normal(&self, sp: &SurfacePoint) -> Vec3 {
+        let footprint = sp.duv_dx.length().max(sp.duv_dy.length());
+        let eps = (0.5 * footprint).max(1e-3);
+        let du = Vec2::new(eps, 0.0);
+        let dv = Vec2::new(0.0, eps);
+        let slope_u = (self.value(&sp.offset(du)) - self.value(&sp.offset(-du))) / (2.0 * eps);
+        let slope_v = (self.value(&sp.offset(dv)) - self.value(&sp.offset(-dv))) / (2.0 * eps);
+        Vec3::new(-slope_u, -slope_v, 1.0)
+    }
+
+    pub fn value(&self, sp: &SurfacePoint) -> f32 {
+        match self {
+            TextureInput::Constant(value) => *value,
+            TextureInput::Procedural(texture) => texture.value_at(sp),
+            _ => {
+                let v = self.eval(sp);
+                (v.x + v.y + v.z) / 3.0
+            }
+        }
+    }
+}
+
+impl From<f32> for TextureInput {
+    fn from(value: f32) -> Self {
+        TextureInput::Constant(value)
+    }
+}
+
+impl From<Vec3> for TextureInput {
+    fn from(color: Vec3) -> Self {
+        TextureInput::Color(color)
+    }
+}
+
+impl From<ProceduralTexture> for TextureInput {
+    fn from(texture: ProceduralTexture) -> Self {
+        TextureInput::Procedural(Box::new(texture))
+    }
+}
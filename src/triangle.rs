@@ -0,0 +1,234 @@
+use glam::{BVec4A, Vec3, Vec4};
+
+use crate::{
+    packet::{lanes, RayPacket, Vec3x4, PACKET_SIZE},
+    structures::Ray,
+    utils::gamma,
+};
+
+// watertight ray triangle test (Woop, Benthin and Wald 2013). the triangle is
+// moved into a frame where the ray starts at the origin and points down +z,
+// so each edge test is the same 2d orientation test in both triangles sharing
+// the edge, and a ray can't slip through the crack between them
+
+// the ray's frame: its dominant axis becomes z, then a shear lines it up with z.
+// worked out once per ray and reused for every triangle it's tested against
+#[derive(Clone, Copy)]
+pub struct RayShear {
+    kx: usize,
+    ky: usize,
+    kz: usize,
+    // x and y shear per unit z, then the scale that makes the direction unit z
+    shear: Vec3,
+}
+
+impl RayShear {
+    pub fn new(dir: Vec3) -> RayShear {
+        let abs = dir.abs();
+        let kz = if abs.x > abs.y && abs.x > abs.z {
+            0
+        } else if abs.y > abs.z {
+            1
+        } else {
+            2
+        };
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        let inv_dz = 1.0 / dir[kz];
+        RayShear {
+            kx,
+            ky,
+            kz,
+            shear: Vec3::new(-dir[kx] * inv_dz, -dir[ky] * inv_dz, inv_dz),
+        }
+    }
+
+    fn transform(&self, p: Vec3, origin: Vec3) -> Vec3 {
+        let p = p - origin;
+        let (x, y, z) = (p[self.kx], p[self.ky], p[self.kz]);
+        Vec3::new(x + self.shear.x * z, y + self.shear.y * z, z * self.shear.z)
+    }
+}
+
+// t and the barycentric weights of a, b and c
+pub fn hit_triangle(
+    ray: &Ray,
+    shear: &RayShear,
+    [a, b, c]: [Vec3; 3],
+    ray_tmin: f32,
+    ray_tmax: f32,
+) -> Option<(f32, Vec3)> {
+    let p0 = shear.transform(a, ray.origin);
+    let p1 = shear.transform(b, ray.origin);
+    let p2 = shear.transform(c, ray.origin);
+
+    let mut e = Vec3::new(
+        p1.x * p2.y - p1.y * p2.x,
+        p2.x * p0.y - p2.y * p0.x,
+        p0.x * p1.y - p0.y * p1.x,
+    );
+    if e.cmpeq(Vec3::ZERO).any() {
+        e = edge_functions_f64(p0, p1, p2);
+    }
+    if e.cmplt(Vec3::ZERO).any() && e.cmpgt(Vec3::ZERO).any() {
+        return None;
+    }
+
+    let det = e.x + e.y + e.z;
+    if det == 0.0 {
+        return None;
+    }
+    // range test before dividing, det's sign flips the comparisons
+    let t_scaled = e.dot(Vec3::new(p0.z, p1.z, p2.z));
+    if det < 0.0 && (t_scaled > ray_tmin * det || t_scaled < ray_tmax * det) {
+        return None;
+    }
+    if det > 0.0 && (t_scaled < ray_tmin * det || t_scaled > ray_tmax * det) {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let t = t_scaled * inv_det;
+    if t <= t_error([p0, p1, p2], e, inv_det) {
+        return None;
+    }
+    Some((t, e * inv_det))
+}
+
+// on an edge in single precision, double settles which side the ray is on
+fn edge_functions_f64(p0: Vec3, p1: Vec3, p2: Vec3) -> Vec3 {
+    let cross = |a: Vec3, b: Vec3| (a.x as f64 * b.y as f64 - a.y as f64 * b.x as f64) as f32;
+    Vec3::new(cross(p1, p2), cross(p2, p0), cross(p0, p1))
+}
+
+// how far t could be off by rounding, a t below this might really be behind the origin
+fn t_error([p0, p1, p2]: [Vec3; 3], e: Vec3, inv_det: f32) -> f32 {
+    let max_x = p0.x.abs().max(p1.x.abs()).max(p2.x.abs());
+    let max_y = p0.y.abs().max(p1.y.abs()).max(p2.y.abs());
+    let max_z = p0.z.abs().max(p1.z.abs()).max(p2.z.abs());
+    let delta_x = gamma(5) * (max_x + max_z);
+    let delta_y = gamma(5) * (max_y + max_z);
+    let delta_z = gamma(3) * max_z;
+    let delta_e = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+    let max_e = e.abs().max_element();
+    3.0 * (gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e) * inv_det.abs()
+}
+
+// the hit point from its barycentric weights, and how far rounding may have
+// moved it off the triangle. more exact than walking t along the ray
+pub fn triangle_point([a, b, c]: [Vec3; 3], weights: Vec3) -> (Vec3, Vec3) {
+    let (pa, pb, pc) = (a * weights.x, b * weights.y, c * weights.z);
+    (pa + pb + pc, gamma(7) * (pa.abs() + pb.abs() + pc.abs()))
+}
+
+// one component picked per lane, the lanes' rays can favour different axes
+#[derive(Clone, Copy)]
+struct LaneAxis {
+    is_x: BVec4A,
+    is_y: BVec4A,
+}
+
+impl LaneAxis {
+    fn new(axes: [usize; PACKET_SIZE]) -> LaneAxis {
+        LaneAxis {
+            is_x: BVec4A::new(axes[0] == 0, axes[1] == 0, axes[2] == 0, axes[3] == 0),
+            is_y: BVec4A::new(axes[0] == 1, axes[1] == 1, axes[2] == 1, axes[3] == 1),
+        }
+    }
+
+    fn pick(self, v: Vec3x4) -> Vec4 {
+        Vec4::select(self.is_x, v.x, Vec4::select(self.is_y, v.y, v.z))
+    }
+}
+
+// RayShear for each lane of a packet
+#[derive(Clone, Copy)]
+pub struct PacketShear {
+    kx: LaneAxis,
+    ky: LaneAxis,
+    kz: LaneAxis,
+    shear: Vec3x4,
+}
+
+impl PacketShear {
+    pub fn new(rays: &[Ray; PACKET_SIZE]) -> PacketShear {
+        let shears = rays.map(|ray| RayShear::new(ray.dir));
+        PacketShear {
+            kx: LaneAxis::new(shears.map(|shear| shear.kx)),
+            ky: LaneAxis::new(shears.map(|shear| shear.ky)),
+            kz: LaneAxis::new(shears.map(|shear| shear.kz)),
+            shear: Vec3x4::from_lanes(shears.map(|shear| shear.shear)),
+        }
+    }
+
+    fn transform(&self, p: Vec3, origin: Vec3x4) -> Vec3x4 {
+        let p = Vec3x4::splat(p) - origin;
+        let (x, y, z) = (self.kx.pick(p), self.ky.pick(p), self.kz.pick(p));
+        Vec3x4 {
+            x: x + self.shear.x * z,
+            y: y + self.shear.y * z,
+            z: z * self.shear.z,
+        }
+    }
+}
+
+// hit_triangle for four rays at once: which lanes hit, their t and weights
+pub fn hit_triangle_packet(
+    packet: &RayPacket,
+    vertices: [Vec3; 3],
+    ray_tmin: f32,
+    ray_tmax: Vec4,
+    active: BVec4A,
+) -> (BVec4A, Vec4, Vec3x4) {
+    let shear = &packet.shear;
+    let p0 = shear.transform(vertices[0], packet.origin);
+    let p1 = shear.transform(vertices[1], packet.origin);
+    let p2 = shear.transform(vertices[2], packet.origin);
+
+    let mut e = Vec3x4 {
+        x: p1.x * p2.y - p1.y * p2.x,
+        y: p2.x * p0.y - p2.y * p0.x,
+        z: p0.x * p1.y - p0.y * p1.x,
+    };
+    let on_edge = active & (e.x.cmpeq(Vec4::ZERO) | e.y.cmpeq(Vec4::ZERO) | e.z.cmpeq(Vec4::ZERO));
+    for lane in lanes(on_edge) {
+        let lane_e = edge_functions_f64(lane_of(p0, lane), lane_of(p1, lane), lane_of(p2, lane));
+        e.x[lane] = lane_e.x;
+        e.y[lane] = lane_e.y;
+        e.z[lane] = lane_e.z;
+    }
+
+    let any_negative = e.x.cmplt(Vec4::ZERO) | e.y.cmplt(Vec4::ZERO) | e.z.cmplt(Vec4::ZERO);
+    let any_positive = e.x.cmpgt(Vec4::ZERO) | e.y.cmpgt(Vec4::ZERO) | e.z.cmpgt(Vec4::ZERO);
+    let det = e.x + e.y + e.z;
+    let t_scaled = e.dot(Vec3x4 {
+        x: p0.z,
+        y: p1.z,
+        z: p2.z,
+    });
+    let inv_det = det.recip();
+    let t = t_scaled * inv_det;
+
+    let max_x = p0.x.abs().max(p1.x.abs()).max(p2.x.abs());
+    let max_y = p0.y.abs().max(p1.y.abs()).max(p2.y.abs());
+    let max_z = p0.z.abs().max(p1.z.abs()).max(p2.z.abs());
+    let delta_x = gamma(5) * (max_x + max_z);
+    let delta_y = gamma(5) * (max_y + max_z);
+    let delta_z = gamma(3) * max_z;
+    let delta_e = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+    let max_e = e.x.abs().max(e.y.abs()).max(e.z.abs());
+    let t_error =
+        3.0 * (gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e) * inv_det.abs();
+
+    let hit = active
+        & !(any_negative & any_positive)
+        & det.cmpne(Vec4::ZERO)
+        & t.cmpge(Vec4::splat(ray_tmin))
+        & t.cmple(ray_tmax)
+        & t.cmpgt(t_error);
+    (hit, t, e * inv_det)
+}
+
+fn lane_of(v: Vec3x4, lane: usize) -> Vec3 {
+    Vec3::new(v.x[lane], v.y[lane], v.z[lane])
+}
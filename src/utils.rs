@@ -1,8 +1,5 @@
 use glam::{Vec2, Vec3};
-use rand::{
-    rngs::{SmallRng, ThreadRng},
-    Rng,
-};
+use rand::{rngs::SmallRng, Rng};
 
 pub const UP: Vec3 = Vec3::new(0.0, 1.0, 0.0);
 
@@ -49,3 +46,26 @@ pub fn perpendicular_to(v: Vec3) -> Vec3 {
         v.cross(Vec3::new(0.0, 0.0, 1.0)).normalize()
     }
 }
+
+// bound on the relative rounding error after n float operations (pbrt's gamma)
+pub fn gamma(n: u32) -> f32 {
+    let e = n as f32 * f32::EPSILON * 0.5;
+    e / (1.0 - e)
+}
+
+// roots of a t^2 + 2 half_b t + c, nearest first, computed as q / a and c / q
+// so neither cancels. c_error bounds c's rounding, a root within that of zero
+// is the ray starting on the surface and comes back as -inf, outside any range
+pub fn solve_quadratic(a: f32, half_b: f32, c: f32, c_error: f32) -> Option<(f32, f32)> {
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let q = -(half_b + discriminant.sqrt().copysign(half_b));
+    let t_error = c_error / q.abs();
+    let (t0, t1) = (q / a, c / q);
+    let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+    let beyond_error = |t: f32| if t > t_error { t } else { f32::NEG_INFINITY };
+    Some((beyond_error(near), beyond_error(far)))
+}